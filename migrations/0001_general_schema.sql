@@ -0,0 +1,113 @@
+-- Customs declarations normalized from the Kz, Rus and Eas sources.
+CREATE TABLE IF NOT EXISTS general_schema (
+  id SERIAL PRIMARY KEY,
+  seg TEXT NOT NULL,
+  declaration_number TEXT,
+  regime TEXT,
+  sheet_number TEXT,
+  position TEXT,
+  effective_date DATE,
+  fill_date DATE,
+  batch TEXT,
+  sender_inn TEXT,
+  sender_kpp TEXT,
+  sender_category TEXT,
+  sender_region TEXT,
+  sender_name TEXT,
+  sender_address TEXT,
+  receiver_inn TEXT,
+  receiver_kpp TEXT,
+  receiver_category TEXT,
+  receiver_region TEXT,
+  receiver_name TEXT,
+  receiver_address TEXT,
+  contractor_inn TEXT,
+  contractor_kpp TEXT,
+  contractor_category TEXT,
+  contractor_region TEXT,
+  contractor_name TEXT,
+  contractor_address TEXT,
+  regime_code TEXT,
+  customs_regime TEXT,
+  border_customs_code TEXT,
+  border_customs_name TEXT,
+  border_customs_city TEXT,
+  border_customs_address TEXT,
+  internal_customs_code TEXT,
+  internal_customs_name TEXT,
+  internal_customs_address TEXT,
+  delivery_terms_code TEXT,
+  delivery_terms_alpha_code TEXT,
+  delivery_terms TEXT,
+  payment_form_code TEXT,
+  payment_form TEXT,
+  stat_accounting_sign_code TEXT,
+  stat_accounting_sign TEXT,
+  origin_country_code TEXT,
+  origin_country TEXT,
+  destination_country_code TEXT,
+  destination_country TEXT,
+  trade_country_code TEXT,
+  trade_country TEXT,
+  origin_country_code_2 TEXT,
+  origin_country_2 TEXT,
+  tnved_code TEXT,
+  tnved_description TEXT,
+  transport_border_code TEXT,
+  transport_border TEXT,
+  transport_internal_code TEXT,
+  transport_internal TEXT,
+  additional_unit_code_1 TEXT,
+  additional_unit_1 TEXT,
+  additional_unit_code_2 TEXT,
+  additional_unit_2 TEXT,
+  cost_usd TEXT,
+  product_description_1 TEXT,
+  product_description_2 TEXT,
+  manufacturer TEXT,
+  brand TEXT,
+  brand_more TEXT,
+  net_weight TEXT,
+  gross_weight TEXT,
+  quantity_physical TEXT,
+  quantity_additional TEXT,
+  cargo_type TEXT,
+  number_of_packages TEXT,
+  delivery_point TEXT,
+  container_sign TEXT,
+  preferences TEXT,
+  customs_value TEXT,
+  invoice_value TEXT,
+  stat_value TEXT,
+  stat_value_usd TEXT,
+  total_invoice_value TEXT,
+  customs_correct_sign TEXT,
+  customs_correct_desc TEXT,
+  currency_code TEXT,
+  currency_description TEXT,
+  exchange_rate TEXT,
+  gtd_registration_place TEXT,
+  gtd_registration_date DATE,
+  loading_place TEXT,
+  processing_code TEXT,
+  processing_description TEXT,
+  declaration_type TEXT,
+  price_per_kg TEXT,
+  price_per_unit TEXT,
+  price_per_additional_unit TEXT,
+  info_type TEXT,
+  decision_code_ts TEXT,
+  recall_decision_code TEXT,
+  customs_value_ts TEXT,
+  previous_customs_value TEXT,
+  total_customs_payments TEXT,
+  customs_region TEXT,
+  customs_procedure TEXT,
+  additional_bdecl1 TEXT,
+  customs_op TEXT,
+  incoterm TEXT,
+  category TEXT,
+  eval TEXT,
+  eval_sec TEXT,
+  created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+);
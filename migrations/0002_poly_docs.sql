@@ -0,0 +1,11 @@
+-- Embedded descriptions of already labelled rows used for kNN search.
+CREATE EXTENSION IF NOT EXISTS vector;
+
+CREATE TABLE IF NOT EXISTS poly_docs (
+  id uuid DEFAULT gen_random_uuid(), -- we can have repeated entries
+  document TEXT NOT NULL,
+  embedding vector(1024)
+);
+
+CREATE INDEX IF NOT EXISTS document_embeddings_idx ON poly_docs
+USING hnsw(embedding vector_cosine_ops);
@@ -0,0 +1,37 @@
+-- Natural key of a declaration line within its segment:
+--   Rus: declaration_number | sheet_number | position
+--   Kz:  declaration_number | batch (G32 product line)
+--   Eas: declaration_number | tnved_code | origin_country_code
+-- New rows get the key from GeneralSchema::natural_key, the backfill
+-- below mirrors it for rows imported before this migration.
+ALTER TABLE general_schema ADD COLUMN IF NOT EXISTS natural_key TEXT;
+
+UPDATE general_schema SET natural_key =
+  CASE
+    WHEN NULLIF(btrim(declaration_number), '') IS NULL THEN NULL
+    WHEN seg = 'Rus' THEN btrim(declaration_number)
+      || '|' || coalesce(btrim(sheet_number), '')
+      || '|' || coalesce(btrim(position), '')
+    WHEN seg = 'Kz' THEN btrim(declaration_number)
+      || '|' || coalesce(btrim(batch), '')
+    ELSE btrim(declaration_number)
+      || '|' || coalesce(btrim(tnved_code), '')
+      || '|' || coalesce(btrim(origin_country_code), '')
+  END
+WHERE natural_key IS NULL;
+
+-- Drop duplicates from earlier overlapping imports, keeping a labelled
+-- row first and the oldest one otherwise
+DELETE FROM general_schema g
+USING (
+  SELECT id, ROW_NUMBER() OVER (
+    PARTITION BY seg, natural_key
+    ORDER BY (eval IS NULL OR eval = 'NoEval'), id
+  ) AS rn
+  FROM general_schema
+  WHERE natural_key IS NOT NULL
+) d
+WHERE g.id = d.id AND d.rn > 1;
+
+CREATE UNIQUE INDEX IF NOT EXISTS general_schema_natural_key_idx
+ON general_schema (seg, natural_key);
@@ -0,0 +1,44 @@
+-- One row per imported file
+CREATE TABLE IF NOT EXISTS import_runs (
+  id SERIAL PRIMARY KEY,
+  file_path TEXT NOT NULL,
+  checksum TEXT,
+  seg TEXT,
+  -- running, done, failed, rolled_back
+  status TEXT NOT NULL DEFAULT 'running',
+  rows_read BIGINT NOT NULL DEFAULT 0,
+  rows_inserted BIGINT NOT NULL DEFAULT 0,
+  rows_updated BIGINT NOT NULL DEFAULT 0,
+  rows_rejected BIGINT NOT NULL DEFAULT 0,
+  rows_embedded BIGINT NOT NULL DEFAULT 0,
+  load_ms BIGINT,
+  embed_ms BIGINT,
+  error TEXT,
+  started_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+  finished_at TIMESTAMP
+);
+
+-- Run that created the row and run that last changed it
+ALTER TABLE general_schema
+  ADD COLUMN IF NOT EXISTS import_run_id INTEGER REFERENCES import_runs(id),
+  ADD COLUMN IF NOT EXISTS updated_run_id INTEGER REFERENCES import_runs(id);
+
+ALTER TABLE poly_docs
+  ADD COLUMN IF NOT EXISTS import_run_id INTEGER REFERENCES import_runs(id);
+
+CREATE INDEX IF NOT EXISTS general_schema_import_run_idx
+ON general_schema (import_run_id);
+CREATE INDEX IF NOT EXISTS general_schema_updated_run_idx
+ON general_schema (updated_run_id);
+CREATE INDEX IF NOT EXISTS poly_docs_import_run_idx
+ON poly_docs (import_run_id);
+
+-- Previous version of rows changed by a run, restored on rollback
+CREATE TABLE IF NOT EXISTS import_run_backup (
+  run_id INTEGER NOT NULL REFERENCES import_runs(id) ON DELETE CASCADE,
+  general_schema_id INTEGER NOT NULL,
+  row JSONB NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS import_run_backup_run_idx
+ON import_run_backup (run_id);
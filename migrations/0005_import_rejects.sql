@@ -0,0 +1,12 @@
+-- Source rows skipped by an import, one row per failed check
+CREATE TABLE IF NOT EXISTS import_rejects (
+  id SERIAL PRIMARY KEY,
+  run_id INTEGER NOT NULL REFERENCES import_runs(id) ON DELETE CASCADE,
+  line BIGINT,
+  column_name TEXT,
+  reason TEXT NOT NULL,
+  raw TEXT
+);
+
+CREATE INDEX IF NOT EXISTS import_rejects_run_idx
+ON import_rejects (run_id);
@@ -0,0 +1,2 @@
+-- Schema drift report of the imported file, NULL when it matched
+ALTER TABLE import_runs ADD COLUMN IF NOT EXISTS drift JSONB;
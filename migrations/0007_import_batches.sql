@@ -0,0 +1,9 @@
+-- Runs load in batches, so a row may be matched more than once per run;
+-- only its version from before the run is kept
+DELETE FROM import_run_backup a USING import_run_backup b
+WHERE a.run_id = b.run_id
+  AND a.general_schema_id = b.general_schema_id
+  AND a.ctid > b.ctid;
+
+CREATE UNIQUE INDEX IF NOT EXISTS import_run_backup_row_idx
+ON import_run_backup (run_id, general_schema_id);
@@ -0,0 +1,107 @@
+-- Text to number for the formats found in the exports: "1 234,5",
+-- "1.234,5", "1,234.5". When both ',' and '.' are present the last one
+-- is the decimal separator; a single ',' is a decimal comma, repeated
+-- ones group thousands. NULL when the text is not a number. Mirrors
+-- parse_number() in validation.rs.
+CREATE OR REPLACE FUNCTION parse_locale_number(a_value TEXT)
+RETURNS NUMERIC AS $$
+DECLARE
+  s TEXT := regexp_replace(
+    a_value, '[[:space:]' || chr(160) || chr(39) || ']', '', 'g');
+  commas INTEGER := length(s) - length(replace(s, ',', ''));
+  dots INTEGER := length(s) - length(replace(s, '.', ''));
+BEGIN
+  IF s IS NULL OR s = '' THEN
+    RETURN NULL;
+  END IF;
+
+  IF commas > 0 AND dots > 0 THEN
+    IF strpos(reverse(s), ',') < strpos(reverse(s), '.') THEN
+      s := replace(replace(s, '.', ''), ',', '.');
+    ELSE
+      s := replace(s, ',', '');
+    END IF;
+  ELSIF commas = 1 THEN
+    s := replace(s, ',', '.');
+  ELSIF commas > 1 THEN
+    s := replace(s, ',', '');
+  ELSIF dots > 1 THEN
+    s := replace(s, '.', '');
+  END IF;
+
+  RETURN s::NUMERIC;
+EXCEPTION WHEN others THEN
+  RETURN NULL;
+END;
+$$ LANGUAGE plpgsql IMMUTABLE;
+
+-- Whole numbers only, NULL for fractions
+CREATE OR REPLACE FUNCTION parse_locale_integer(a_value TEXT)
+RETURNS BIGINT AS $$
+  SELECT CASE WHEN n = trunc(n)
+    AND n BETWEEN -9223372036854775808 AND 9223372036854775807
+    THEN n::BIGINT END
+  FROM parse_locale_number(a_value) n
+$$ LANGUAGE sql IMMUTABLE;
+
+-- Values that could not be converted when the columns got their types.
+-- They are set to NULL in general_schema and kept here.
+CREATE TABLE IF NOT EXISTS type_conversion_errors (
+  id SERIAL PRIMARY KEY,
+  general_schema_id INTEGER NOT NULL,
+  column_name TEXT NOT NULL,
+  value TEXT NOT NULL,
+  target_type TEXT NOT NULL,
+  created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS type_conversion_errors_column_idx
+ON type_conversion_errors (column_name);
+
+DO $$
+DECLARE
+  col TEXT;
+  target TEXT;
+  conv TEXT;
+BEGIN
+  FOR col IN
+    SELECT c.column_name FROM information_schema.columns c
+    WHERE c.table_schema = current_schema()
+      AND c.table_name = 'general_schema'
+      AND c.data_type = 'text'
+      AND c.column_name IN (
+        'cost_usd', 'net_weight', 'gross_weight', 'quantity_physical',
+        'quantity_additional', 'number_of_packages', 'customs_value',
+        'invoice_value', 'stat_value', 'stat_value_usd',
+        'total_invoice_value', 'exchange_rate', 'price_per_kg',
+        'price_per_unit', 'price_per_additional_unit',
+        'customs_value_ts', 'previous_customs_value',
+        'total_customs_payments')
+  LOOP
+    IF col = 'number_of_packages' THEN
+      target := 'BIGINT';
+      conv := 'parse_locale_integer';
+    ELSE
+      target := 'NUMERIC';
+      conv := 'parse_locale_number';
+    END IF;
+
+    EXECUTE format(
+      'INSERT INTO type_conversion_errors '
+      '(general_schema_id, column_name, value, target_type) '
+      'SELECT id, %L, %I, %L FROM general_schema '
+      'WHERE btrim(%I) <> '''' AND %s(%I) IS NULL',
+      col, col, target, col, conv, col);
+
+    EXECUTE format(
+      'ALTER TABLE general_schema ALTER COLUMN %I TYPE %s USING %s(%I)',
+      col, target, conv, col);
+
+    -- Backups are restored with jsonb_populate_record on rollback
+    EXECUTE format(
+      'UPDATE import_run_backup SET row = jsonb_set(row, ARRAY[%L], '
+      'COALESCE(to_jsonb(%s(row->>%L)), ''null''::JSONB)) WHERE row ? %L',
+      col, conv, col, col);
+  END LOOP;
+END;
+$$;
@@ -0,0 +1,20 @@
+-- Quantities in canonical units next to the declared ones, filled by
+-- units.rs on import. volume_m3 is the declared volume or, when
+-- volume_estimated, the weight divided by the density of the label.
+ALTER TABLE general_schema
+  ADD COLUMN IF NOT EXISTS unit_norm TEXT,
+  ADD COLUMN IF NOT EXISTS quantity_norm NUMERIC,
+  ADD COLUMN IF NOT EXISTS weight_kg NUMERIC,
+  ADD COLUMN IF NOT EXISTS volume_m3 NUMERIC,
+  ADD COLUMN IF NOT EXISTS volume_estimated BOOLEAN NOT NULL DEFAULT FALSE;
+
+-- Net weights are declared in kg by every source. Units of rows loaded
+-- before are filled by `migrate --normalize-units`.
+UPDATE general_schema SET weight_kg = net_weight
+WHERE weight_kg IS NULL AND net_weight <> 0;
+
+-- Backups are restored with jsonb_populate_record on rollback
+UPDATE import_run_backup
+SET row = row || jsonb_build_object(
+  'weight_kg', row->'net_weight', 'volume_estimated', false)
+WHERE NOT row ? 'volume_estimated';
@@ -0,0 +1,49 @@
+-- Exchange rates in RUB per unit of currency, loaded from a local CSV
+-- by currency.rs
+CREATE TABLE IF NOT EXISTS fx_rates (
+  currency TEXT NOT NULL,
+  rate_date DATE NOT NULL,
+  rate NUMERIC NOT NULL CHECK (rate > 0),
+  PRIMARY KEY (currency, rate_date)
+);
+
+-- ISO alpha code of a currency given by its alpha or numeric code, NULL
+-- when it is neither
+CREATE OR REPLACE FUNCTION fx_currency(a_code TEXT)
+RETURNS TEXT AS $$
+  SELECT CASE
+    WHEN c IN ('643', '810', 'RUR') THEN 'RUB'
+    WHEN c = '840' THEN 'USD'
+    WHEN c = '978' THEN 'EUR'
+    WHEN c = '398' THEN 'KZT'
+    WHEN c = '156' THEN 'CNY'
+    WHEN c = '933' THEN 'BYN'
+    WHEN c = '417' THEN 'KGS'
+    WHEN c = '051' THEN 'AMD'
+    WHEN c = '860' THEN 'UZS'
+    WHEN c = '949' THEN 'TRY'
+    WHEN c = '826' THEN 'GBP'
+    WHEN c = '756' THEN 'CHF'
+    WHEN c = '392' THEN 'JPY'
+    WHEN c ~ '^[A-Z]{3}$' THEN c
+  END
+  FROM (SELECT CASE WHEN x ~ '^[0-9]{1,3}$' THEN lpad(x, 3, '0') ELSE x END
+    FROM upper(btrim(a_code)) x) AS t(c)
+$$ LANGUAGE sql IMMUTABLE;
+
+-- RUB per unit of a currency: the latest rate on or before the date, or
+-- the latest known rate when there is no date
+CREATE OR REPLACE FUNCTION fx_rate(a_currency TEXT, a_date DATE)
+RETURNS NUMERIC AS $$
+  SELECT CASE WHEN a_currency = 'RUB' THEN 1 ELSE (
+    SELECT rate FROM fx_rates
+    WHERE currency = a_currency AND (a_date IS NULL OR rate_date <= a_date)
+    ORDER BY rate_date DESC LIMIT 1) END
+$$ LANGUAGE sql STABLE;
+
+-- Row values in RUB and USD and how they were obtained, filled by
+-- currency.rs after each import run
+ALTER TABLE general_schema
+  ADD COLUMN IF NOT EXISTS value_rub NUMERIC,
+  ADD COLUMN IF NOT EXISTS value_usd NUMERIC,
+  ADD COLUMN IF NOT EXISTS fx_method TEXT;
@@ -0,0 +1,38 @@
+-- Counterparties resolved by entities.rs: one row per valid INN/BIN, or
+-- per normalized name when no valid identifier was declared
+CREATE TABLE IF NOT EXISTS companies (
+  id SERIAL PRIMARY KEY,
+  identifier_type TEXT,
+  identifier TEXT,
+  -- Most frequent spelling among company_names
+  name TEXT,
+  legal_form TEXT,
+  name_key TEXT,
+  created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+  UNIQUE (identifier_type, identifier)
+);
+
+CREATE INDEX IF NOT EXISTS companies_name_key_idx ON companies (name_key);
+
+-- Spellings of a company in declarations and the rows using them
+CREATE TABLE IF NOT EXISTS company_names (
+  company_id INTEGER NOT NULL REFERENCES companies(id) ON DELETE CASCADE,
+  name TEXT NOT NULL,
+  rows BIGINT NOT NULL,
+  PRIMARY KEY (company_id, name)
+);
+
+ALTER TABLE general_schema
+  ADD COLUMN IF NOT EXISTS sender_company_id INTEGER
+    REFERENCES companies(id) ON DELETE SET NULL,
+  ADD COLUMN IF NOT EXISTS receiver_company_id INTEGER
+    REFERENCES companies(id) ON DELETE SET NULL,
+  ADD COLUMN IF NOT EXISTS contractor_company_id INTEGER
+    REFERENCES companies(id) ON DELETE SET NULL;
+
+CREATE INDEX IF NOT EXISTS general_schema_sender_company_idx
+ON general_schema (sender_company_id);
+CREATE INDEX IF NOT EXISTS general_schema_receiver_company_idx
+ON general_schema (receiver_company_id);
+CREATE INDEX IF NOT EXISTS general_schema_contractor_company_idx
+ON general_schema (contractor_company_id);
@@ -0,0 +1,60 @@
+-- Flagged values: the row was loaded, only the value failed its check
+ALTER TABLE import_rejects
+  ADD COLUMN IF NOT EXISTS loaded BOOLEAN NOT NULL DEFAULT FALSE;
+
+-- legal or individual, derived from the identifier by identifiers.rs
+ALTER TABLE companies ADD COLUMN IF NOT EXISTS entity_type TEXT;
+
+UPDATE companies SET entity_type = CASE
+    WHEN identifier_type = 'inn' AND length(identifier) = 10 THEN 'legal'
+    WHEN identifier_type = 'bin'
+      AND substr(identifier, 5, 1) IN ('4', '5') THEN 'legal'
+    ELSE 'individual'
+  END
+WHERE identifier IS NOT NULL AND entity_type IS NULL;
+
+-- Identifiers stored before normalization: float formatting undone and
+-- a lost leading zero restored, as normalize_identifier does
+CREATE OR REPLACE FUNCTION pg_temp.norm_identifier(a TEXT) RETURNS TEXT
+LANGUAGE SQL IMMUTABLE AS $$
+  SELECT CASE
+    WHEN s !~ '[1-9]' THEN NULL
+    WHEN length(s) IN (9, 11) THEN '0' || s
+    ELSE s
+  END
+  FROM (SELECT CASE
+      WHEN t ~ '^[0-9]+(\.0*)?$' THEN split_part(t, '.', 1)
+      WHEN t ~ '^[0-9]+(\.[0-9]+)?[eE]\+?[0-9]+$'
+        AND t::NUMERIC = trunc(t::NUMERIC) THEN trunc(t::NUMERIC)::TEXT
+      ELSE btrim(a)
+    END AS s
+    FROM (SELECT regexp_replace(a, '\s', '', 'g') AS t) x) y
+$$;
+
+CREATE OR REPLACE FUNCTION pg_temp.norm_kpp(a TEXT) RETURNS TEXT
+LANGUAGE SQL IMMUTABLE AS $$
+  SELECT CASE
+    WHEN s !~ '[1-9]' THEN NULL
+    WHEN s ~ '^[0-9]{8}$' THEN '0' || s
+    ELSE s
+  END
+  FROM (SELECT upper(regexp_replace(
+      regexp_replace(btrim(a), '\.0$', ''), '\s', '', 'g')) AS s) x
+$$;
+
+UPDATE general_schema SET
+  sender_inn = pg_temp.norm_identifier(sender_inn),
+  receiver_inn = pg_temp.norm_identifier(receiver_inn),
+  contractor_inn = pg_temp.norm_identifier(contractor_inn),
+  sender_kpp = pg_temp.norm_kpp(sender_kpp),
+  receiver_kpp = pg_temp.norm_kpp(receiver_kpp),
+  contractor_kpp = pg_temp.norm_kpp(contractor_kpp)
+WHERE (sender_inn, receiver_inn, contractor_inn,
+    sender_kpp, receiver_kpp, contractor_kpp)
+  IS DISTINCT FROM (
+    pg_temp.norm_identifier(sender_inn),
+    pg_temp.norm_identifier(receiver_inn),
+    pg_temp.norm_identifier(contractor_inn),
+    pg_temp.norm_kpp(sender_kpp),
+    pg_temp.norm_kpp(receiver_kpp),
+    pg_temp.norm_kpp(contractor_kpp));
@@ -0,0 +1,17 @@
+-- ISO alpha-2 codes and canonical names of the country fields, filled
+-- by countries.rs on import. Rows loaded before are filled by
+-- `migrate --normalize-countries`.
+ALTER TABLE general_schema
+  ADD COLUMN IF NOT EXISTS origin_country_iso TEXT,
+  ADD COLUMN IF NOT EXISTS origin_country_name TEXT,
+  ADD COLUMN IF NOT EXISTS destination_country_iso TEXT,
+  ADD COLUMN IF NOT EXISTS destination_country_name TEXT,
+  ADD COLUMN IF NOT EXISTS trade_country_iso TEXT,
+  ADD COLUMN IF NOT EXISTS trade_country_name TEXT,
+  ADD COLUMN IF NOT EXISTS origin_country_2_iso TEXT,
+  ADD COLUMN IF NOT EXISTS origin_country_2_name TEXT;
+
+CREATE INDEX IF NOT EXISTS general_schema_origin_country_iso_idx
+ON general_schema (origin_country_iso);
+CREATE INDEX IF NOT EXISTS general_schema_destination_country_iso_idx
+ON general_schema (destination_country_iso);
@@ -0,0 +1,32 @@
+-- TN VED EAEU reference stored by tnved.rs: chapters (2 digits),
+-- headings (4), subheadings (6) and codes (10)
+CREATE TABLE IF NOT EXISTS tnved (
+  code TEXT PRIMARY KEY,
+  description TEXT NOT NULL,
+  level SMALLINT GENERATED ALWAYS AS (length(code)) STORED
+);
+
+-- tnved_code as 10 digits with its levels, for grouping and filtering
+-- on any level of the hierarchy
+ALTER TABLE general_schema
+  ADD COLUMN IF NOT EXISTS tnved_norm TEXT,
+  ADD COLUMN IF NOT EXISTS tnved_chapter TEXT
+    GENERATED ALWAYS AS (left(tnved_norm, 2)) STORED,
+  ADD COLUMN IF NOT EXISTS tnved_heading TEXT
+    GENERATED ALWAYS AS (left(tnved_norm, 4)) STORED,
+  ADD COLUMN IF NOT EXISTS tnved_subheading TEXT
+    GENERATED ALWAYS AS (left(tnved_norm, 6)) STORED;
+
+CREATE INDEX IF NOT EXISTS general_schema_tnved_norm_idx
+ON general_schema (tnved_norm);
+CREATE INDEX IF NOT EXISTS general_schema_tnved_heading_idx
+ON general_schema (tnved_heading);
+
+-- Codes needing no reference lookup; nine digit codes are filled by
+-- `migrate --normalize-tnved`
+UPDATE general_schema
+SET tnved_norm = regexp_replace(
+  regexp_replace(tnved_code, '\.0$', ''), '[\s.-]', '', 'g')
+WHERE tnved_norm IS NULL
+  AND regexp_replace(regexp_replace(tnved_code, '\.0$', ''), '[\s.-]', '',
+    'g') ~ '^[0-9]{10}$';
@@ -0,0 +1,11 @@
+-- Month the goods moved, as its first day. EAS exports declare it;
+-- other rows take the month of effective_date.
+ALTER TABLE general_schema
+  ADD COLUMN IF NOT EXISTS movement_period DATE;
+
+UPDATE general_schema
+SET movement_period = date_trunc('month', effective_date)::DATE
+WHERE movement_period IS NULL AND effective_date IS NOT NULL;
+
+CREATE INDEX IF NOT EXISTS general_schema_movement_period_idx
+ON general_schema (movement_period);
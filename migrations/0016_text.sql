@@ -0,0 +1,10 @@
+-- Product descriptions prepared by text.rs for embedding and prompting,
+-- with their language and the dimensions and density read from them.
+-- Stored rows are filled by `migrate --normalize-text`.
+ALTER TABLE general_schema
+  ADD COLUMN IF NOT EXISTS description_norm TEXT,
+  ADD COLUMN IF NOT EXISTS description_lang TEXT,
+  ADD COLUMN IF NOT EXISTS length_mm NUMERIC,
+  ADD COLUMN IF NOT EXISTS width_mm NUMERIC,
+  ADD COLUMN IF NOT EXISTS thickness_mm NUMERIC,
+  ADD COLUMN IF NOT EXISTS density_kg_m3 NUMERIC;
@@ -0,0 +1,22 @@
+-- Product attributes extracted from the descriptions of general_schema
+-- rows by attributes.rs, one row per attribute. value holds the text
+-- ("50", "ТЕХНОНИКОЛЬ CARBON PROF"), value_num numeric attributes.
+-- method is rule or llm; confidence is between 0 and 1.
+CREATE TABLE IF NOT EXISTS product_attributes (
+  general_schema_id INTEGER NOT NULL
+    REFERENCES general_schema (id) ON DELETE CASCADE,
+  attribute TEXT NOT NULL,
+  value TEXT NOT NULL,
+  value_num NUMERIC,
+  confidence REAL NOT NULL,
+  method TEXT NOT NULL,
+  extracted_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+  PRIMARY KEY (general_schema_id, attribute)
+);
+
+CREATE INDEX IF NOT EXISTS product_attributes_attribute_idx
+ON product_attributes (attribute, value_num);
+
+-- When the model last read the row, so that it is asked only once
+ALTER TABLE general_schema
+  ADD COLUMN IF NOT EXISTS llm_attributes_at TIMESTAMPTZ;
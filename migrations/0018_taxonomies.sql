@@ -0,0 +1,7 @@
+-- Examples of each classification dimension (taxonomies.rs) are kept
+-- apart: a document carries the label of the taxonomy it belongs to.
+-- Documents embedded before are labelled by material.
+ALTER TABLE poly_docs
+  ADD COLUMN IF NOT EXISTS taxonomy TEXT NOT NULL DEFAULT 'material';
+
+CREATE INDEX IF NOT EXISTS poly_docs_taxonomy_idx ON poly_docs (taxonomy);
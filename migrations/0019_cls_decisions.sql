@@ -0,0 +1,18 @@
+-- Decisions of the classification stages of cls.rs, per row and
+-- taxonomy: stage relevance (relevant or irrelevant) and label. method
+-- is rules, knn, model, default (no filter decided) or relevance (label
+-- given by the first stage); model names the model asked, if any.
+CREATE TABLE IF NOT EXISTS cls_decisions (
+  general_schema_id INTEGER NOT NULL
+    REFERENCES general_schema (id) ON DELETE CASCADE,
+  taxonomy TEXT NOT NULL,
+  stage TEXT NOT NULL,
+  decision TEXT NOT NULL,
+  method TEXT NOT NULL,
+  model TEXT,
+  decided_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+  PRIMARY KEY (general_schema_id, taxonomy, stage)
+);
+
+CREATE INDEX IF NOT EXISTS cls_decisions_stage_idx
+ON cls_decisions (taxonomy, stage, method);
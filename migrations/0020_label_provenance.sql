@@ -0,0 +1,113 @@
+-- Provenance of the labels of general_schema, per taxonomy name:
+-- {"source": "file" | "rule" | "model" | "user", "ref": file path,
+-- rule, model or user name, "prompt": prompt version, "run": import run,
+-- "at": time of the change, "locked": true for labels verified by
+-- hand}. Automated writers never replace a locked label.
+ALTER TABLE general_schema
+  ADD COLUMN IF NOT EXISTS label_meta JSONB NOT NULL DEFAULT '{}';
+
+-- Every change of a label or of its provenance
+CREATE TABLE IF NOT EXISTS label_audit (
+  id BIGSERIAL PRIMARY KEY,
+  general_schema_id INTEGER NOT NULL,
+  taxonomy TEXT NOT NULL,
+  old_label TEXT,
+  new_label TEXT,
+  source TEXT,
+  reference TEXT,
+  prompt TEXT,
+  import_run_id INTEGER,
+  locked BOOLEAN NOT NULL DEFAULT false,
+  changed_by TEXT NOT NULL DEFAULT current_user,
+  changed_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS label_audit_row_idx
+ON label_audit (general_schema_id, taxonomy, changed_at);
+
+-- Before a label column or label_meta changes: a change without new
+-- provenance is a manual edit, and a locked label is only changed by a
+-- user. Arguments: taxonomy name, label column.
+CREATE OR REPLACE FUNCTION guard_label() RETURNS trigger AS $$
+DECLARE
+  tax TEXT := TG_ARGV[0];
+  col TEXT := TG_ARGV[1];
+  old_label TEXT := to_jsonb(OLD) ->> col;
+  new_label TEXT := to_jsonb(NEW) ->> col;
+  old_meta JSONB := OLD.label_meta -> tax;
+  new_meta JSONB := NEW.label_meta -> tax;
+  locked BOOLEAN := coalesce((old_meta ->> 'locked')::boolean, false);
+BEGIN
+  IF old_meta IS NOT DISTINCT FROM new_meta THEN
+    IF old_label IS NOT DISTINCT FROM new_label THEN
+      RETURN NEW;
+    END IF;
+    new_meta := jsonb_build_object(
+      'source', 'user', 'ref', current_user, 'locked', locked);
+  ELSIF locked AND new_meta ->> 'source' IS DISTINCT FROM 'user' THEN
+    RETURN jsonb_populate_record(NEW, jsonb_build_object(
+      col, old_label,
+      'label_meta', jsonb_set(NEW.label_meta, ARRAY[tax], old_meta)));
+  ELSIF new_meta IS NULL THEN
+    RETURN NEW;
+  END IF;
+
+  NEW.label_meta := jsonb_set(NEW.label_meta, ARRAY[tax],
+    new_meta || jsonb_build_object('at', now()));
+  RETURN NEW;
+END
+$$ LANGUAGE plpgsql;
+
+-- After a row is inserted with a label of known provenance, or its
+-- label or provenance changed: write label_audit
+CREATE OR REPLACE FUNCTION audit_label() RETURNS trigger AS $$
+DECLARE
+  tax TEXT := TG_ARGV[0];
+  col TEXT := TG_ARGV[1];
+  old_label TEXT;
+  new_label TEXT := to_jsonb(NEW) ->> col;
+  old_meta JSONB;
+  new_meta JSONB := NEW.label_meta -> tax;
+BEGIN
+  IF TG_OP = 'INSERT' THEN
+    IF new_meta IS NULL THEN
+      RETURN NULL;
+    END IF;
+  ELSE
+    old_label := to_jsonb(OLD) ->> col;
+    old_meta := OLD.label_meta -> tax;
+    IF old_label IS NOT DISTINCT FROM new_label
+      AND old_meta IS NOT DISTINCT FROM new_meta THEN
+      RETURN NULL;
+    END IF;
+  END IF;
+
+  INSERT INTO label_audit (general_schema_id, taxonomy, old_label,
+    new_label, source, reference, prompt, import_run_id, locked)
+  VALUES (NEW.id, tax, old_label, new_label, new_meta ->> 'source',
+    new_meta ->> 'ref', new_meta ->> 'prompt',
+    (new_meta ->> 'run')::integer,
+    coalesce((new_meta ->> 'locked')::boolean, false));
+  RETURN NULL;
+END
+$$ LANGUAGE plpgsql;
+
+-- The built-in taxonomies; taxonomies.rs creates the triggers of the
+-- others when they are first used
+DROP TRIGGER IF EXISTS label_eval ON general_schema;
+CREATE TRIGGER label_eval
+BEFORE UPDATE OF eval, label_meta ON general_schema
+FOR EACH ROW EXECUTE FUNCTION guard_label('material', 'eval');
+DROP TRIGGER IF EXISTS label_eval_audit ON general_schema;
+CREATE TRIGGER label_eval_audit
+AFTER INSERT OR UPDATE OF eval, label_meta ON general_schema
+FOR EACH ROW EXECUTE FUNCTION audit_label('material', 'eval');
+
+DROP TRIGGER IF EXISTS label_eval_sec ON general_schema;
+CREATE TRIGGER label_eval_sec
+BEFORE UPDATE OF eval_sec, label_meta ON general_schema
+FOR EACH ROW EXECUTE FUNCTION guard_label('application', 'eval_sec');
+DROP TRIGGER IF EXISTS label_eval_sec_audit ON general_schema;
+CREATE TRIGGER label_eval_sec_audit
+AFTER INSERT OR UPDATE OF eval_sec, label_meta ON general_schema
+FOR EACH ROW EXECUTE FUNCTION audit_label('application', 'eval_sec');
@@ -0,0 +1,48 @@
+-- Eas stat forms have no line number, and goods of the same code and
+-- origin on one form are different lines. Their natural key ends with
+-- the first 12 hex digits of the sha256 of
+--   product_description_1 | net_weight | stat_value
+-- (trimmed text, numbers without trailing zeros), as built by
+-- GeneralSchema::natural_key. Of identical lines only one keeps a key,
+-- preferring a row that had one and then a labelled one.
+--
+-- Text is trimmed of the Unicode White_Space characters, as str::trim
+-- does; btrim alone only strips spaces.
+CREATE OR REPLACE FUNCTION eas_trim(a_str TEXT) RETURNS TEXT AS $$
+  SELECT btrim(a_str, E'\t\n\u000B\f\r \u0085\u00A0\u1680'
+    || E'\u2000\u2001\u2002\u2003\u2004\u2005\u2006\u2007'
+    || E'\u2008\u2009\u200A\u2028\u2029\u202F\u205F\u3000')
+$$ LANGUAGE sql IMMUTABLE;
+
+CREATE OR REPLACE FUNCTION eas_line_hash(
+  a_descr TEXT, a_weight NUMERIC, a_value NUMERIC
+) RETURNS TEXT AS $$
+  SELECT left(encode(sha256(convert_to(
+    coalesce(eas_trim(a_descr), '')
+    || '|' || coalesce(trim_scale(a_weight)::TEXT, '')
+    || '|' || coalesce(trim_scale(a_value)::TEXT, ''),
+    'UTF8')), 'hex'), 12)
+$$ LANGUAGE sql IMMUTABLE;
+
+UPDATE general_schema g
+SET natural_key = CASE WHEN k.rn = 1 THEN k.key END
+FROM (
+  SELECT id, key, ROW_NUMBER() OVER (
+    PARTITION BY key
+    ORDER BY natural_key IS NULL, (eval IS NULL OR eval = 'NoEval'), id
+  ) AS rn
+  FROM (
+    SELECT id, natural_key, eval, eas_trim(declaration_number)
+      || '|' || coalesce(eas_trim(tnved_code), '')
+      || '|' || coalesce(eas_trim(origin_country_code), '')
+      || '|' || eas_line_hash(product_description_1, net_weight,
+        stat_value) AS key
+    FROM general_schema
+    WHERE seg = 'Eas'
+      AND NULLIF(eas_trim(declaration_number), '') IS NOT NULL
+  ) x
+) k
+WHERE g.id = k.id;
+
+DROP FUNCTION eas_line_hash(TEXT, NUMERIC, NUMERIC);
+DROP FUNCTION eas_trim(TEXT);
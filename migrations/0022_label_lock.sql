@@ -0,0 +1,77 @@
+-- Locking keeps the provenance of a label: lock_labels() only sets
+-- "locked" and "locked_by", and the guard stamps "locked_at" instead
+-- of the time of the label. The audit names the user who locked it.
+CREATE OR REPLACE FUNCTION guard_label() RETURNS trigger AS $$
+DECLARE
+  tax TEXT := TG_ARGV[0];
+  col TEXT := TG_ARGV[1];
+  lock_keys TEXT[] := ARRAY['locked', 'locked_by', 'locked_at', 'at'];
+  old_label TEXT := to_jsonb(OLD) ->> col;
+  new_label TEXT := to_jsonb(NEW) ->> col;
+  old_meta JSONB := OLD.label_meta -> tax;
+  new_meta JSONB := NEW.label_meta -> tax;
+  locked BOOLEAN := coalesce((old_meta ->> 'locked')::boolean, false);
+BEGIN
+  IF old_meta IS NOT DISTINCT FROM new_meta THEN
+    IF old_label IS NOT DISTINCT FROM new_label THEN
+      RETURN NEW;
+    END IF;
+    new_meta := jsonb_build_object(
+      'source', 'user', 'ref', current_user, 'locked', locked);
+  ELSIF old_label IS NOT DISTINCT FROM new_label
+    AND coalesce(old_meta, '{}') - lock_keys
+      = coalesce(new_meta, '{}') - lock_keys THEN
+    -- Only locked or unlocked
+    NEW.label_meta := jsonb_set(NEW.label_meta, ARRAY[tax],
+      new_meta || jsonb_build_object('locked_at', now()));
+    RETURN NEW;
+  ELSIF locked AND new_meta ->> 'source' IS DISTINCT FROM 'user' THEN
+    RETURN jsonb_populate_record(NEW, jsonb_build_object(
+      col, old_label,
+      'label_meta', jsonb_set(NEW.label_meta, ARRAY[tax], old_meta)));
+  ELSIF new_meta IS NULL THEN
+    RETURN NEW;
+  END IF;
+
+  NEW.label_meta := jsonb_set(NEW.label_meta, ARRAY[tax],
+    new_meta || jsonb_build_object('at', now()));
+  RETURN NEW;
+END
+$$ LANGUAGE plpgsql;
+
+CREATE OR REPLACE FUNCTION audit_label() RETURNS trigger AS $$
+DECLARE
+  tax TEXT := TG_ARGV[0];
+  col TEXT := TG_ARGV[1];
+  old_label TEXT;
+  new_label TEXT := to_jsonb(NEW) ->> col;
+  old_meta JSONB;
+  new_meta JSONB := NEW.label_meta -> tax;
+  author TEXT := current_user;
+BEGIN
+  IF TG_OP = 'INSERT' THEN
+    IF new_meta IS NULL THEN
+      RETURN NULL;
+    END IF;
+  ELSE
+    old_label := to_jsonb(OLD) ->> col;
+    old_meta := OLD.label_meta -> tax;
+    IF old_label IS NOT DISTINCT FROM new_label
+      AND old_meta IS NOT DISTINCT FROM new_meta THEN
+      RETURN NULL;
+    END IF;
+    IF old_meta -> 'locked' IS DISTINCT FROM new_meta -> 'locked' THEN
+      author := coalesce(new_meta ->> 'locked_by', author);
+    END IF;
+  END IF;
+
+  INSERT INTO label_audit (general_schema_id, taxonomy, old_label,
+    new_label, source, reference, prompt, import_run_id, locked,
+    changed_by)
+  VALUES (NEW.id, tax, old_label, new_label, new_meta ->> 'source',
+    new_meta ->> 'ref', new_meta ->> 'prompt',
+    (new_meta ->> 'run')::integer,
+    coalesce((new_meta ->> 'locked')::boolean, false), author);
+  RETURN NULL;
+END
+$$ LANGUAGE plpgsql;
@@ -0,0 +1,7 @@
+-- Companies remember the import run that created them, so rolling the
+-- run back also removes the companies no other row links to
+ALTER TABLE companies ADD COLUMN IF NOT EXISTS import_run_id INTEGER
+  REFERENCES import_runs(id) ON DELETE SET NULL;
+
+CREATE INDEX IF NOT EXISTS companies_import_run_idx
+ON companies (import_run_id);
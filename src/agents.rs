@@ -1,6 +1,6 @@
 use crate::conf::LlmConfig;
 use crate::database::VecItem;
-use crate::schemas::TypeT;
+use crate::taxonomies::{Relevance, Taxonomy};
 use rig::client::builder::DynClientBuilder;
 use rig::completion::Prompt;
 use rig::embeddings::Embedding;
@@ -34,41 +34,22 @@ pub async fn embed_str(
   return Ok(res);
 }
 
+/// Ask the model for the label of a product by the taxonomy. Answers
+/// outside the taxonomy give its pending label.
 pub async fn cls(
   a_itm: String,
+  a_taxonomy: &Taxonomy,
   a_conf_model: &LlmConfig,
-) -> Result<TypeT, Box<dyn Error>>
+) -> Result<String, Box<dyn Error>>
 {
   a_conf_model.set_env();
 
   let res = DynClientBuilder::new()
-    .agent(a_conf_model.protocol.to_str(), a_conf_model.model_name.as_str())?
-    .preamble("\
-    You are a construction products classifier. Your task is to read the \
-    incoming product description with respective examples and classify the \
-    description into one of the types: XPS, EPS, PSON, PSV, PIR, \
-    GlassWool, StoneWool and Other.
-    The categories can be described as follows:
-    -- XPS (extruded polystyrene foam, extruded polystyrene foam, \
-    extruded polystyrene foam) is a synthetic thermal insulation material
-    -- EPS (expanded polystyrene, expanded polystyrene) is a lightweight, \
-    rigid insulating material made from solid polystyrene particles.
-    -- PSON (general purpose polystyrene, general purpose polystyrene) - \
-    raw materials, in particular for XPS
-    -- PSV (foaming polystyrene) - raw materials, in particular for EPS \
-    -- PIR (Polyisocyanurate), a thermosetting polymer material with closed \
-    cells with a sufficiently high degree of rigidity
-    -- GlassWool is a fibrous mineral thermal insulation material, a \
-    type of mineral wool
-    -- StoneWool is basalt wool obtained from the melt by spraying on \
-    rolls, thermal insulation material
-    -- Other - category for not relevant and other materials
-    Note that we are primerely interested in construction materials. \
-    Thus, various decorating materials have to be classified as Other. \
-    You will be given few examples of similar descriptions as well as evaluated \
-    category. Use those for reference. \
-    Answer ONLY with the selected category with no other descriptions or symbols.
-    ")
+    .agent(
+      a_conf_model.protocol.to_str(),
+      a_conf_model.model_name.as_str(),
+    )?
+    .preamble(&a_taxonomy.prompt())
     .max_tokens(4000)
     .build()
     .prompt(a_itm)
@@ -77,11 +58,74 @@ pub async fn cls(
   let d = remove_block(&res, "think".to_string());
   let c = d.strip_prefix("\n\n").unwrap_or(d.as_str()).to_string();
   tracing::debug!("Got output from model: {}", c);
-  let out = TypeT::from_str(&c);
+  let out = a_taxonomy.normalize(Some(&c));
 
   Ok(out)
 }
 
+/// Ask the small model whether a product is relevant to the taxonomy.
+/// None when it answers neither yes nor no.
+pub async fn relevant(
+  a_itm: String,
+  a_relevance: &Relevance,
+  a_conf_model: &LlmConfig,
+) -> Result<Option<bool>, Box<dyn Error>>
+{
+  a_conf_model.set_env();
+
+  let res = DynClientBuilder::new()
+    .agent(
+      a_conf_model.protocol.to_str(),
+      a_conf_model.model_name.as_str(),
+    )?
+    .preamble(&a_relevance.prompt())
+    .max_tokens(4000)
+    .build()
+    .prompt(a_itm)
+    .await?;
+
+  let d = remove_block(&res, "think".to_string());
+  tracing::debug!("Got output from model: {}", d);
+
+  Ok(a_relevance.by_answer(&d))
+}
+
+/// Ask the model for the attributes stated in a product description.
+/// Returns its answer, expected to be a JSON object.
+pub async fn extract(
+  a_itm: String,
+  a_conf_model: &LlmConfig,
+) -> Result<String, Box<dyn Error>>
+{
+  a_conf_model.set_env();
+
+  let res = DynClientBuilder::new()
+    .agent(a_conf_model.protocol.to_str(), a_conf_model.model_name.as_str())?
+    .preamble("\
+    You read descriptions of construction and insulation products from \
+    customs declarations and extract the attributes they state. \
+    Answer with a single JSON object with the keys:
+    -- thickness_mm: board or roll thickness in millimetres
+    -- density_kg_m3: density in kg/m3
+    -- length_mm, width_mm: board or roll length and width in millimetres
+    -- pack_pieces: number of boards or pieces in one pack
+    -- pack_area_m2: area of one pack in square metres
+    -- series: brand and product series, e.g. TECHNONICOL CARBON PROF
+    Convert centimetres and metres to millimetres. Use null for anything \
+    the description does not state; never guess. \
+    Answer ONLY with the JSON object with no other descriptions or symbols.
+    ")
+    .max_tokens(4000)
+    .build()
+    .prompt(a_itm)
+    .await?;
+
+  let d = remove_block(&res, "think".to_string());
+  tracing::debug!("Got output from model: {}", d);
+
+  Ok(d)
+}
+
 pub fn remove_block(a_in: &String, a_block: String) -> String
 {
   let open = format!("<{}>", a_block);
@@ -0,0 +1,562 @@
+use crate::agents::extract;
+use crate::conf::{AttributesConfig, LlmConfig};
+use crate::text::{DENSITY_UNITS, preprocess, row_description};
+use crate::validation::parse_number;
+use futures::future::join_all;
+use rust_decimal::Decimal;
+use std::error::Error;
+use std::str::FromStr;
+use std::sync::LazyLock;
+use tokio_postgres::Client;
+
+/// Bundled product series: the brand and its spellings, the series and
+/// its spellings in prepared (lower case) descriptions, separated by ';'
+const SERIES_CSV: &str = include_str!("../references/series.csv");
+
+/// Rows read per query when extracting attributes
+const EXTRACT_PAGE: i64 = 5000;
+
+/// Descriptions sent to the model at a time
+const LLM_CHUNK: usize = 50;
+
+/// Words counting pieces, "8 шт/уп", "10 плит в упаковке"
+const PIECE_UNITS: &[&str] = &[
+  "шт",
+  "штук",
+  "штуки",
+  "листов",
+  "листа",
+  "плит",
+  "плиты",
+  "pcs",
+];
+
+/// Words of square metres, "5,76 м2 в упаковке"
+const AREA_UNITS: &[&str] = &["м2", "м²", "кв.м", "m2"];
+
+/// Beginnings of the words of a pack
+const PACK_STEMS: &[&str] = &["уп", "пач", "pack"];
+
+/// Product attribute extracted from a description
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AttributeT
+{
+  ThicknessMm,
+  DensityKgM3,
+  LengthMm,
+  WidthMm,
+  PackPieces,
+  PackAreaM2,
+  /// Brand and product series, "ТЕХНОНИКОЛЬ CARBON PROF"
+  Series,
+}
+
+impl AttributeT
+{
+  /// Value stored in product_attributes.attribute
+  pub fn code(&self) -> &'static str
+  {
+    match self {
+      AttributeT::ThicknessMm => "thickness_mm",
+      AttributeT::DensityKgM3 => "density_kg_m3",
+      AttributeT::LengthMm => "length_mm",
+      AttributeT::WidthMm => "width_mm",
+      AttributeT::PackPieces => "pack_pieces",
+      AttributeT::PackAreaM2 => "pack_area_m2",
+      AttributeT::Series => "series",
+    }
+  }
+}
+
+/// How an attribute was extracted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MethodT
+{
+  Rule,
+  Llm,
+}
+
+impl MethodT
+{
+  /// Value stored in product_attributes.method
+  pub fn code(&self) -> &'static str
+  {
+    match self {
+      MethodT::Rule => "rule",
+      MethodT::Llm => "llm",
+    }
+  }
+}
+
+/// An extracted attribute with its value as text, and as a number for
+/// numeric attributes
+#[derive(Debug, Clone, PartialEq)]
+pub struct Attribute
+{
+  pub attribute: AttributeT,
+  pub value: String,
+  pub number: Option<Decimal>,
+  /// 0 to 1
+  pub confidence: f32,
+  pub method: MethodT,
+}
+
+impl Attribute
+{
+  fn number(
+    a_attribute: AttributeT,
+    a_value: Decimal,
+    a_confidence: f32,
+    a_method: MethodT,
+  ) -> Self
+  {
+    Attribute {
+      attribute: a_attribute,
+      value: a_value.normalize().to_string(),
+      number: Some(a_value.normalize()),
+      confidence: a_confidence,
+      method: a_method,
+    }
+  }
+}
+
+/// A product series of the reference
+#[derive(Debug, Clone)]
+pub struct Series
+{
+  pub brand: String,
+  pub brand_aliases: Vec<String>,
+  pub name: String,
+  pub aliases: Vec<String>,
+}
+
+/// The bundled series, parsed on first use
+pub static SERIES: LazyLock<Vec<Series>> = LazyLock::new(|| {
+  let mut out = Vec::new();
+  let mut reader = csv::Reader::from_reader(SERIES_CSV.as_bytes());
+  for record in reader.records() {
+    let rec = record.expect("Invalid series.csv");
+    let field =
+      |i: usize| rec.get(i).unwrap_or("").trim().to_string();
+    let list = |i: usize| {
+      field(i)
+        .split(';')
+        .map(|x| x.trim().to_lowercase())
+        .filter(|x| !x.is_empty())
+        .collect::<Vec<String>>()
+    };
+    out.push(Series {
+      brand: field(0),
+      brand_aliases: list(1),
+      name: field(2),
+      aliases: list(3),
+    });
+  }
+  out
+});
+
+/// Whether `a_phrase` occurs in `a_text` as whole words
+fn contains_phrase(a_text: &str, a_phrase: &str) -> bool
+{
+  a_text.match_indices(a_phrase).any(|(i, _)| {
+    let before = a_text[..i].chars().next_back();
+    let after = a_text[i + a_phrase.len()..].chars().next();
+    !before.map(|x| x.is_alphanumeric()).unwrap_or(false)
+      && !after.map(|x| x.is_alphanumeric()).unwrap_or(false)
+  })
+}
+
+/// Pieces and area per pack: a count followed by a unit and a pack
+/// word ("8 шт/уп", "5,76 м2 в упаковке"), or a pack word followed by
+/// a count and a unit ("в упаковке 8 шт")
+fn packaging_of(a_text: &str) -> Vec<Attribute>
+{
+  let tokens: Vec<&str> = a_text
+    .split(|x: char| x.is_whitespace() || x == '/')
+    .map(|x| x.trim_matches(['.', ',', ';', ':', '(', ')', '"']))
+    .filter(|x| !x.is_empty())
+    .collect();
+  let number = |i: usize| {
+    tokens
+      .get(i)
+      .filter(|x| {
+        x.chars().next().map(|c| c.is_ascii_digit()) == Some(true)
+      })
+      .and_then(|x| parse_number(&x.replace(',', "."), '.'))
+  };
+  let is_pack = |i: usize| {
+    tokens
+      .get(i)
+      .map(|x| PACK_STEMS.iter().any(|s| x.starts_with(s)))
+      .unwrap_or(false)
+  };
+  let unit = |i: usize| match tokens.get(i) {
+    Some(x) if PIECE_UNITS.contains(x) => {
+      Some(AttributeT::PackPieces)
+    }
+    Some(x) if AREA_UNITS.contains(x) => Some(AttributeT::PackAreaM2),
+    _ => None,
+  };
+
+  let mut out: Vec<Attribute> = Vec::new();
+  for i in 0..tokens.len() {
+    let found = match (number(i), unit(i + 1)) {
+      // "8 шт уп", "8 шт в упаковке", "5,76 м2 per pack"
+      (Some(n), Some(a))
+        if is_pack(i + 2)
+          || (matches!(
+            tokens.get(i + 2),
+            Some(&"в") | Some(&"per")
+          ) && is_pack(i + 3)) =>
+      {
+        Some((a, n))
+      }
+      // "упаковка 8 шт", "в пачке 10 плит"
+      (Some(n), Some(a)) if i > 0 && is_pack(i - 1) => Some((a, n)),
+      _ => None,
+    };
+    if let Some((a, n)) =
+      found.filter(|(a, _)| !out.iter().any(|x| x.attribute == *a))
+    {
+      out.push(Attribute::number(a, n, 0.85, MethodT::Rule));
+    }
+  }
+
+  out
+}
+
+/// Brand and series named in a prepared description. More certain when
+/// the brand is named too, in the text or in one of `a_brands` (the
+/// brand and manufacturer columns).
+fn series_of(
+  a_text: &str,
+  a_brands: &[Option<&str>],
+) -> Option<Attribute>
+{
+  let brands: Vec<String> = a_brands
+    .iter()
+    .flatten()
+    .map(|x| x.to_lowercase())
+    .collect();
+
+  for s in SERIES.iter() {
+    if !s.aliases.iter().any(|x| contains_phrase(a_text, x)) {
+      continue;
+    }
+    let branded = s.brand_aliases.iter().any(|x| {
+      contains_phrase(a_text, x)
+        || brands.iter().any(|b| b.contains(x))
+    });
+
+    return Some(Attribute {
+      attribute: AttributeT::Series,
+      value: format!("{} {}", s.brand, s.name),
+      number: None,
+      confidence: if branded { 0.95 } else { 0.8 },
+      method: MethodT::Rule,
+    });
+  }
+
+  None
+}
+
+/// Attributes of a description found by rules. Dimensions and density
+/// are those read by text.rs; a written unit makes them more certain.
+pub fn extract_rules(
+  a_description: &str,
+  a_brands: &[Option<&str>],
+) -> Vec<Attribute>
+{
+  let f = preprocess(a_description);
+  let d = &f.dimensions;
+  let dims_confidence = if d.unit_given { 0.9 } else { 0.75 };
+  let mut out = Vec::new();
+
+  for (a, v) in [
+    (AttributeT::LengthMm, d.length_mm),
+    (AttributeT::WidthMm, d.width_mm),
+  ] {
+    if let Some(n) = v {
+      out.push(Attribute::number(
+        a,
+        n,
+        dims_confidence,
+        MethodT::Rule,
+      ));
+    }
+  }
+  if let Some(n) = d.thickness_mm {
+    // Without length the thickness was written out ("толщина 50 мм")
+    let c = if d.length_mm.is_none() {
+      0.9
+    } else {
+      dims_confidence
+    };
+    out.push(Attribute::number(
+      AttributeT::ThicknessMm,
+      n,
+      c,
+      MethodT::Rule,
+    ));
+  }
+  if let Some(n) = f.density_kg_m3 {
+    let c = match DENSITY_UNITS.iter().any(|x| f.text.contains(x)) {
+      true => 0.95,
+      false => 0.8,
+    };
+    out.push(Attribute::number(
+      AttributeT::DensityKgM3,
+      n,
+      c,
+      MethodT::Rule,
+    ));
+  }
+  out.extend(packaging_of(&f.text));
+  out.extend(series_of(&f.text, a_brands));
+
+  out
+}
+
+/// Number of a value of the model's answer, given as a number or as
+/// text ("50", "50 мм")
+fn json_number(a_value: &serde_json::Value) -> Option<Decimal>
+{
+  match a_value {
+    serde_json::Value::Number(x) => {
+      Decimal::from_str(&x.to_string()).ok()
+    }
+    serde_json::Value::String(x) => x
+      .split_whitespace()
+      .next()
+      .and_then(|x| parse_number(&x.replace(',', "."), '.')),
+    _ => None,
+  }
+}
+
+/// Attributes of the model's answer, a JSON object possibly surrounded
+/// by text or a code block. Keys it does not know are ignored.
+pub fn parse_llm_answer(
+  a_answer: &str,
+  a_confidence: f32,
+) -> Result<Vec<Attribute>, Box<dyn Error>>
+{
+  let start = a_answer.find('{').ok_or("no JSON object")?;
+  let end = a_answer.rfind('}').ok_or("no JSON object")?;
+  let json: serde_json::Value =
+    serde_json::from_str(&a_answer[start..=end])?;
+
+  let mut out = Vec::new();
+  for a in [
+    AttributeT::ThicknessMm,
+    AttributeT::DensityKgM3,
+    AttributeT::LengthMm,
+    AttributeT::WidthMm,
+    AttributeT::PackPieces,
+    AttributeT::PackAreaM2,
+  ] {
+    if let Some(n) = json
+      .get(a.code())
+      .and_then(json_number)
+      .filter(|x| *x > Decimal::ZERO)
+    {
+      out.push(Attribute::number(a, n, a_confidence, MethodT::Llm));
+    }
+  }
+  if let Some(s) = json
+    .get("series")
+    .and_then(|x| x.as_str())
+    .filter(|x| !x.trim().is_empty())
+  {
+    out.push(Attribute {
+      attribute: AttributeT::Series,
+      value: s.trim().to_uppercase(),
+      number: None,
+      confidence: a_confidence,
+      method: MethodT::Llm,
+    });
+  }
+
+  Ok(out)
+}
+
+/// Replace the attributes extracted by `a_method` for rows `a_ids`. An
+/// attribute found by another method is kept when it is more certain.
+/// Returns the attributes written.
+pub async fn store_attributes(
+  a_cli: &Client,
+  a_ids: &[i32],
+  a_method: MethodT,
+  a_attributes: &[(i32, Attribute)],
+) -> Result<u64, Box<dyn Error>>
+{
+  a_cli
+    .execute(
+      "DELETE FROM product_attributes \
+      WHERE general_schema_id = ANY($1) AND method = $2",
+      &[&a_ids, &a_method.code()],
+    )
+    .await?;
+  if a_attributes.is_empty() {
+    return Ok(0);
+  }
+
+  let ids: Vec<i32> = a_attributes.iter().map(|x| x.0).collect();
+  let names: Vec<&str> =
+    a_attributes.iter().map(|x| x.1.attribute.code()).collect();
+  let values: Vec<&String> =
+    a_attributes.iter().map(|x| &x.1.value).collect();
+  let numbers: Vec<Option<Decimal>> =
+    a_attributes.iter().map(|x| x.1.number).collect();
+  let confidences: Vec<f32> =
+    a_attributes.iter().map(|x| x.1.confidence).collect();
+
+  let n = a_cli
+    .execute(
+      "INSERT INTO product_attributes (general_schema_id, attribute, \
+        value, value_num, confidence, method) \
+      SELECT u.*, $6 FROM UNNEST($1::INTEGER[], $2::TEXT[], $3::TEXT[], \
+        $4::NUMERIC[], $5::REAL[]) AS u \
+      ON CONFLICT (general_schema_id, attribute) DO UPDATE SET \
+        value = EXCLUDED.value, value_num = EXCLUDED.value_num, \
+        confidence = EXCLUDED.confidence, method = EXCLUDED.method, \
+        extracted_at = now() \
+      WHERE EXCLUDED.confidence >= product_attributes.confidence",
+      &[
+        &ids,
+        &names,
+        &values,
+        &numbers,
+        &confidences,
+        &a_method.code(),
+      ],
+    )
+    .await?;
+
+  Ok(n)
+}
+
+/// Extract the attributes of stored rows by rules, of all rows or of
+/// those added or updated by run `a_run_id`. Returns the attributes
+/// written.
+pub async fn extract_attributes(
+  a_cli: &Client,
+  a_run_id: Option<i32>,
+) -> Result<u64, Box<dyn Error>>
+{
+  let mut last_id = 0;
+  let mut written = 0;
+
+  loop {
+    let rows = a_cli
+      .query(
+        "SELECT id, product_description_1, product_description_2, brand, \
+          manufacturer \
+        FROM general_schema WHERE id > $1 AND ($3::INTEGER IS NULL \
+          OR import_run_id = $3 OR updated_run_id = $3) \
+        ORDER BY id LIMIT $2",
+        &[&last_id, &EXTRACT_PAGE, &a_run_id],
+      )
+      .await?;
+    if rows.is_empty() {
+      break;
+    }
+
+    let mut ids = Vec::with_capacity(rows.len());
+    let mut found = Vec::new();
+    for r in &rows {
+      let id: i32 = r.get(0);
+      let desc_1: Option<String> = r.get(1);
+      let desc_2: Option<String> = r.get(2);
+      let brand: Option<String> = r.get(3);
+      let manufacturer: Option<String> = r.get(4);
+      ids.push(id);
+
+      if let Some(d) =
+        row_description(desc_1.as_deref(), desc_2.as_deref())
+      {
+        let brands = [brand.as_deref(), manufacturer.as_deref()];
+        for a in extract_rules(&d, &brands) {
+          found.push((id, a));
+        }
+      }
+    }
+    last_id = *ids.last().unwrap_or(&last_id);
+
+    written +=
+      store_attributes(a_cli, &ids, MethodT::Rule, &found).await?;
+  }
+
+  Ok(written)
+}
+
+/// Ask the model for the attributes of up to `a_limit` insulation rows
+/// (labelled other than Other and NoNeed) that the rules left without
+/// thickness or density and that the model has not read yet. Returns
+/// the rows read.
+pub async fn extract_attributes_llm(
+  a_cli: &Client,
+  a_conf_model: &LlmConfig,
+  a_conf: &AttributesConfig,
+  a_limit: i64,
+) -> Result<u64, Box<dyn Error>>
+{
+  let rows = a_cli
+    .query(
+      "SELECT g.id, g.description_norm FROM general_schema g \
+      WHERE g.description_norm IS NOT NULL \
+        AND g.llm_attributes_at IS NULL \
+        AND g.eval NOT IN ('Other', 'NoNeed') \
+        AND (SELECT COUNT(*) FROM product_attributes a \
+          WHERE a.general_schema_id = g.id \
+            AND a.attribute IN ('thickness_mm', 'density_kg_m3')) < 2 \
+      ORDER BY g.id LIMIT $1",
+      &[&a_limit],
+    )
+    .await?;
+
+  let mut done = 0;
+  for ch in rows.chunks(LLM_CHUNK) {
+    let mut tasks = Vec::new();
+    for r in ch {
+      let text: String = r.get(1);
+      tasks.push(extract(text, a_conf_model));
+    }
+
+    let mut ids = Vec::with_capacity(ch.len());
+    let mut found = Vec::new();
+    for (r, res) in ch.iter().zip(join_all(tasks).await) {
+      let id: i32 = r.get(0);
+      let answer = match res {
+        Ok(x) => x,
+        // Not marked, the row is asked again on the next run
+        Err(e) => {
+          tracing::warn!(
+            "Could not ask the model about {}: {}",
+            id,
+            e
+          );
+          continue;
+        }
+      };
+      ids.push(id);
+      match parse_llm_answer(&answer, a_conf.llm_confidence) {
+        Ok(x) => found.extend(x.into_iter().map(|a| (id, a))),
+        Err(e) => {
+          tracing::warn!("Unreadable attributes of {}: {}", id, e);
+        }
+      }
+    }
+
+    store_attributes(a_cli, &ids, MethodT::Llm, &found).await?;
+    a_cli
+      .execute(
+        "UPDATE general_schema SET llm_attributes_at = now() \
+        WHERE id = ANY($1)",
+        &[&ids],
+      )
+      .await?;
+    done += ids.len() as u64;
+  }
+
+  Ok(done)
+}
@@ -0,0 +1,69 @@
+use clap::Parser;
+use poly_cls::attributes::*;
+use poly_cls::conf::*;
+use poly_cls::database::*;
+use poly_cls::migrations::*;
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Cli
+{
+  /// Only extract the attributes of the rows added or updated by this
+  /// import run
+  #[arg(long)]
+  run: Option<i32>,
+
+  /// Then ask the model for the attributes of insulation rows the rules
+  /// left without thickness or density
+  #[arg(long)]
+  llm: bool,
+
+  /// Number of rows sent to the model
+  #[arg(long, default_value_t = 1000)]
+  limit: i64,
+}
+
+#[tokio::main]
+async fn main()
+{
+  let args = Cli::parse();
+
+  tracing_subscriber::fmt()
+    .with_max_level(tracing::Level::INFO)
+    // .with_target(false)
+    .init();
+
+  // Load configuration
+  let config: TendConfig =
+    load_config(&"configs/test.ini".to_string())
+      .expect("Wrong llm config");
+  let llm_config = load_llm_config(&"configs/test.ini".to_string())
+    .expect("Wrong llm config");
+
+  // Connect to database using config details
+  let pool = connect_to_database(&config.postgres)
+    .await
+    .expect("Postgres error");
+  let client = pool.get().await.expect("Postgres error");
+
+  check_migrations(&client)
+    .await
+    .expect("Database schema check failed");
+
+  let n = extract_attributes(&client, args.run)
+    .await
+    .expect("Could not extract attributes");
+  tracing::info!("Extracted {} attribute(s) by rules", n);
+
+  if args.llm {
+    let n = extract_attributes_llm(
+      &client,
+      &llm_config[0],
+      &config.attributes,
+      args.limit,
+    )
+    .await
+    .expect("Could not extract attributes with the model");
+    tracing::info!("Model read the attributes of {} row(s)", n);
+  }
+}
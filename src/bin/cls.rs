@@ -0,0 +1,114 @@
+use clap::Parser;
+use poly_cls::cls::*;
+use poly_cls::conf::*;
+use poly_cls::database::*;
+use poly_cls::migrations::*;
+use poly_cls::taxonomies::*;
+use poly_cls::units::*;
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Cli
+{
+  /// Taxonomies to classify by, by name or path to a .toml file. All
+  /// known taxonomies when none is given.
+  taxonomies: Vec<String>,
+
+  /// Only list the known taxonomies and their label columns
+  #[arg(long)]
+  list: bool,
+
+  /// Skip the relevance stage and ask the classifier about every row
+  #[arg(long)]
+  no_filter: bool,
+}
+
+#[tokio::main]
+async fn main()
+{
+  let args = Cli::parse();
+
+  tracing_subscriber::fmt()
+    .with_max_level(tracing::Level::INFO)
+    // .with_target(false)
+    .init();
+
+  let mut taxonomies: Vec<Taxonomy> = if args.taxonomies.is_empty() {
+    known_taxonomies().expect("Could not read taxonomies")
+  } else {
+    args
+      .taxonomies
+      .iter()
+      .map(|x| load_taxonomy(x).expect("Could not read taxonomy"))
+      .collect()
+  };
+
+  if args.list {
+    for t in taxonomies.iter() {
+      let labels: Vec<&str> =
+        t.labels.iter().map(|x| x.name.as_str()).collect();
+      println!(
+        "{:<16} {:<16} {}",
+        t.name,
+        t.column,
+        labels.join(", ")
+      );
+    }
+    return;
+  }
+
+  if args.no_filter {
+    for t in taxonomies.iter_mut() {
+      t.relevance = None;
+    }
+  }
+
+  // Load configuration
+  let config: TendConfig =
+    load_config(&"configs/test.ini".to_string())
+      .expect("Wrong llm config");
+  let llm_config = load_llm_config(&"configs/test.ini".to_string())
+    .expect("Wrong llm config");
+
+  // Connect to database using config details
+  let pool = connect_to_database(&config.postgres)
+    .await
+    .expect("Postgres error");
+  let client = pool.get().await.expect("Postgres error");
+
+  check_migrations(&client)
+    .await
+    .expect("Database schema check failed");
+
+  for t in taxonomies.iter() {
+    let res = eval(
+      &client,
+      &pool,
+      t,
+      &llm_config[1],
+      &llm_config[0],
+      config.cls.relevance_model(&llm_config),
+      &config.main,
+    )
+    .await;
+    let done: Vec<i32> = res
+      .iter()
+      .filter(|x| x.eval != t.pending)
+      .filter_map(|x| x.id.parse().ok())
+      .collect();
+    tracing::info!(
+      "{}: labelled {} of {} row(s) in {}",
+      t.name,
+      done.len(),
+      res.len(),
+      t.column
+    );
+
+    // Estimated volumes follow the new labels
+    if t.column == DENSITY_COLUMN {
+      update_row_volumes(&client, &config.density, &done)
+        .await
+        .expect("Could not update volumes");
+    }
+  }
+}
@@ -2,7 +2,13 @@ use poly_cls::cls::*;
 use poly_cls::conf::*;
 use poly_cls::database::*;
 use poly_cls::imports::*;
+use poly_cls::mapping::*;
+use poly_cls::migrations::*;
 use poly_cls::schemas::*;
+use poly_cls::taxonomies::*;
+use poly_cls::tnved::*;
+use poly_cls::units::*;
+use std::collections::HashMap;
 use std::path::PathBuf;
 
 #[tokio::main]
@@ -19,16 +25,11 @@ async fn main() -> Result<(), Box<dyn std::error::Error>>
   let llm_config = load_llm_config(&"configs/test.ini".to_string())?;
 
   // Connect to database using config details
-  let client = connect_to_database(
-    &config.postgres.host,
-    &config.postgres.user,
-    &config.postgres.psswd,
-  )
-  .await?;
+  let pool = connect_to_database(&config.postgres).await?;
+  let mut client = pool.get().await?;
 
-  // Initialize the GeneralSchema table
-  init_general_schema_table(&client, true).await?;
-  init_vector_storage_data(&client, true).await?;
+  // Recreate the schema from scratch
+  init_database(&mut client, true).await?;
 
   // Read sample data from CSV file
   let ps = [
@@ -37,56 +38,87 @@ async fn main() -> Result<(), Box<dyn std::error::Error>>
     (PathBuf::from("samples/rus_customs_4.csv"), SegmentT::Rus),
   ];
 
+  let tnved = Tnved::load(&config.tnved)?;
+  store_tnved(&client, &tnved).await?;
+
+  let settings = ImportSettings {
+    conf_embed: &llm_config[1],
+    conf_import: &config.import,
+    conf_density: &config.density,
+    tnved: &tnved,
+  };
   let mut done: usize = 0;
   for (path, seg) in ps {
-    done +=
-      read_file(&path, seg, false, &client, &llm_config[1]).await?;
+    let mapping = builtin_mapping(&seg);
+    done += read_file(&mut client, &path, &mapping, &pool, &settings)
+      .await?
+      .embedded as usize;
   }
   tracing::info!("Red total of {} rows", done);
 
-  // Randomly choose pos for eval
-  for t in enum_iterator::all::<TypeT>() {
-    if t.clone() as u8 == TypeT::NoEval as u8 {
-      continue;
-    }
-    client.batch_execute(format!("
-      UPDATE general_schema SET eval='NoEval' WHERE id IN (
-        SELECT id FROM general_schema WHERE eval='{:?}' ORDER BY RANDOM() LIMIT 4
-      );", t).as_str()).await?;
-  }
+  // Taxonomy to check, material unless named
+  let name =
+    std::env::args().nth(1).unwrap_or("material".to_string());
+  let taxonomy = load_taxonomy(&name)?;
 
-  let res =
-    eval(&client, &llm_config[1], &llm_config[0], &config.main).await;
-
-  let mut ok: usize = 0;
-  for r in res.iter() {
-    let q = client
+  // Randomly choose pos for eval, remembering their labels
+  let mut truth = HashMap::new();
+  for l in taxonomy.labels.iter() {
+    let rows = client
       .query(
         format!(
           "
-      SELECT eval_sec FROM general_schema WHERE id={}",
-          r.id
+      UPDATE general_schema g SET {0} = $1 FROM (
+        SELECT id, {0} AS label FROM general_schema WHERE {0} = $2
+        ORDER BY RANDOM() LIMIT 4
+      ) p WHERE g.id = p.id RETURNING g.id, p.label",
+          taxonomy.column
         )
         .as_str(),
-        &[],
+        &[&taxonomy.pending, &l.name],
       )
       .await?;
-    assert!(q.len() == 1);
-    let st: String = q[0].get(0);
-    let real_t = TypeT::from_str(&st);
+    for r in rows {
+      truth.insert(
+        r.get::<_, i32>(0).to_string(),
+        r.get::<_, String>(1),
+      );
+    }
+  }
+
+  let res = eval(
+    &client,
+    &pool,
+    &taxonomy,
+    &llm_config[1],
+    &llm_config[0],
+    config.cls.relevance_model(&llm_config),
+    &config.main,
+  )
+  .await;
+  // Estimated volumes follow the new labels
+  update_volumes(&client, &config.density, None).await?;
 
-    if real_t.clone() as u8 == r.eval.clone() as u8 {
+  // Only the chosen rows are scored, not those that had no label
+  let res: Vec<ToEval> = res
+    .into_iter()
+    .filter(|x| truth.contains_key(&x.id))
+    .collect();
+  let mut ok: usize = 0;
+  for r in res.iter() {
+    let real_t = &truth[&r.id];
+    if *real_t == r.eval {
       ok += 1;
     }
 
-    println!("{}:\n{:?} - {:?}", r.descr, r.eval, real_t);
+    println!("{}:\n{} - {}", r.descr, r.eval, real_t);
   }
 
   println!(
     "Total acc: {}\\{}, {}%",
     ok,
     res.len(),
-    ok * 100 / res.len()
+    ok * 100 / res.len().max(1)
   );
 
   Ok(())
@@ -1,5 +1,6 @@
 use poly_cls::conf::*;
 use poly_cls::database::*;
+use poly_cls::migrations::*;
 use poly_cls::schemas::*;
 use std::path::PathBuf;
 
@@ -11,19 +12,11 @@ async fn main() -> Result<(), Box<dyn std::error::Error>>
     load_config(&"configs/test.ini".to_string())?;
 
   // Connect to database using config details
-  let client = connect_to_database(
-    &config.postgres.host,
-    &config.postgres.user,
-    &config.postgres.psswd,
-  )
-  .await?;
-
-  client
-    .execute("DROP TABLE IF EXISTS general_schema;", &[])
-    .await?;
+  let pool = connect_to_database(&config.postgres).await?;
+  let mut client = pool.get().await?;
 
-  // Initialize the GeneralSchema table
-  init_general_schema_table(&client, true).await?;
+  // Recreate the schema from scratch
+  init_database(&mut client, true).await?;
 
   // Read sample data from CSV file
   let ps = [
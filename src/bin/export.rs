@@ -55,13 +55,10 @@ async fn main()
     .expect("Wrong llm config");
 
   // Connect to database using config details
-  let client = connect_to_database(
-    &config.postgres.host,
-    &config.postgres.user,
-    &config.postgres.psswd,
-  )
-  .await
-  .expect("Postgres error");
+  let pool = connect_to_database(&config.postgres)
+    .await
+    .expect("Postgres error");
+  let client = pool.get().await.expect("Postgres error");
 
   let start_date = args
     .start_d
@@ -1,15 +1,41 @@
+use clap::Parser;
 use poly_cls::cls;
 use poly_cls::conf::*;
+use poly_cls::currency::*;
 use poly_cls::database::*;
 use poly_cls::imports::*;
-use poly_cls::schemas::*;
+use poly_cls::mapping::*;
+use poly_cls::migrations::*;
+use poly_cls::tnved::*;
 use std::path::PathBuf;
-use std::str::FromStr;
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Cli
+{
+  /// Source files to import
+  #[arg(required = true)]
+  files: Vec<String>,
+
+  /// Mapping of the files: a built-in segment name, a mapping name or
+  /// a path to a .toml file. Detected from the header row of each
+  /// file when not given.
+  #[arg(short, long)]
+  mapping: Option<String>,
+
+  /// Refuse files that drifted from their mapping
+  #[arg(long, conflicts_with = "lenient")]
+  strict: bool,
+
+  /// Import files that drifted from their mapping with a warning
+  #[arg(long)]
+  lenient: bool,
+}
 
 #[tokio::main]
 async fn main()
 {
-  let args: Vec<String> = std::env::args().collect();
+  let args = Cli::parse();
 
   tracing_subscriber::fmt()
     .with_max_level(tracing::Level::INFO)
@@ -17,62 +43,113 @@ async fn main()
     .init();
 
   // Load configuration
-  let config: TendConfig =
+  let mut config: TendConfig =
     load_config(&"configs/test.ini".to_string())
       .expect("Wrong llm config");
   let llm_config = load_llm_config(&"configs/test.ini".to_string())
     .expect("Wrong llm config");
 
   // Connect to database using config details
-  let client = connect_to_database(
-    &config.postgres.host,
-    &config.postgres.user,
-    &config.postgres.psswd,
-  )
-  .await
-  .expect("Postgres error");
-
-  // Initialize the GeneralSchema table
-  init_general_schema_table(&client, false)
+  let pool = connect_to_database(&config.postgres)
+    .await
+    .expect("Postgres error");
+  let mut client = pool.get().await.expect("Postgres error");
+
+  check_migrations(&client)
     .await
-    .expect("Could not init schema general");
-  init_vector_storage_data(&client, false)
+    .expect("Database schema check failed");
+
+  if let Some(path) = &config.currency.rates {
+    let n = load_rates(&client, path)
+      .await
+      .expect("Could not load exchange rates");
+    tracing::info!("Loaded {} exchange rates from {:?}", n, path);
+  }
+
+  let tnved =
+    Tnved::load(&config.tnved).expect("Could not read TN VED");
+  store_tnved(&client, &tnved)
     .await
-    .expect("Could not init vector storage");
+    .expect("Could not store TN VED reference");
+
+  // --strict / --lenient override the configured drift mode
+  if args.strict {
+    config.import.drift = DriftModeT::Strict;
+  } else if args.lenient {
+    config.import.drift = DriftModeT::Lenient;
+  }
+
+  let settings = ImportSettings {
+    conf_embed: &llm_config[1],
+    conf_import: &config.import,
+    conf_density: &config.density,
+    tnved: &tnved,
+  };
 
-  for pack in args[1..].chunks_exact(2) {
-    let path = pack[0].clone();
-    let seg = pack[1].clone();
+  for path in &args.files {
+    let p = PathBuf::from(path);
 
-    let p = PathBuf::from(&path);
-    let seg_t = SegmentT::from_str(seg.as_str());
-    match seg_t {
-      Err(_) => {
-        tracing::error!("Could not recognize segment {}", seg);
+    let found = match &args.mapping {
+      Some(name) => load_mapping(name.as_str())
+        .and_then(|x| check_mapping(&p, &x)),
+      None => detect_mapping(&p),
+    };
+    let mapping = match found {
+      Ok(x) => {
+        tracing::info!(
+          "{} matches format {} ({:.0}% of columns)",
+          path,
+          x.mapping.name,
+          x.score * 100.0
+        );
+        x.mapping
+      }
+      Err(e) => {
+        tracing::error!(
+          "Could not pick a format for {}: {}",
+          path,
+          e
+        );
         continue;
       }
-      _ => {}
-    }
+    };
 
     let res =
-      read_file(&p, seg_t.expect(""), true, &client, &llm_config[1])
-        .await;
+      read_file(&mut client, &p, &mapping, &pool, &settings).await;
 
-    if res.is_ok() {
-      tracing::info!("Ok read {} {}", path, seg);
+    if let Ok(stats) = &res {
+      tracing::info!(
+        "Ok read {} {} as run {}: {} read, {} inserted, {} updated, {} \
+         embedded",
+        path,
+        mapping.name,
+        stats.run_id,
+        stats.read,
+        stats.inserted,
+        stats.updated,
+        stats.embedded
+      );
     } else {
       tracing::error!(
         "Could not read {} {} {:?}",
         path,
-        seg,
+        mapping.name,
         res.err().unwrap()
       );
     }
   }
 
-  // let res =
-  //   cls::eval(&client, &llm_config[1], &llm_config[0], &config.main)
-  //     .await;
+  // let material = load_taxonomy("material").unwrap();
+  // let res = cls::eval(
+  //   &client,
+  //   &pool,
+  //   &material,
+  //   &llm_config[1],
+  //   &llm_config[0],
+  //   config.cls.relevance_model(&llm_config),
+  //   &config.main,
+  // )
+  // .await;
   //
   // println!("Totally evaled {} entries", res.len());
 }
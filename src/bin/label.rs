@@ -0,0 +1,208 @@
+use clap::{Parser, Subcommand};
+use poly_cls::conf::*;
+use poly_cls::database::*;
+use poly_cls::migrations::*;
+use poly_cls::taxonomies::*;
+use poly_cls::units::*;
+use std::collections::HashMap;
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Cli
+{
+  #[command(subcommand)]
+  command: Command,
+
+  /// Name recorded as the author of the changes, $USER by default
+  #[arg(long)]
+  user: Option<String>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command
+{
+  /// Set the label of a row by hand
+  Set
+  {
+    id: i32,
+    /// Taxonomy name or path to a .toml file
+    taxonomy: String,
+    label: String,
+    /// Also lock the label against automated overwrites
+    #[arg(long)]
+    lock: bool,
+    /// Also unlock the label, it is kept locked otherwise
+    #[arg(long, conflicts_with = "lock")]
+    unlock: bool,
+  },
+  /// Lock the labels of rows as verified
+  Lock
+  {
+    taxonomy: String, ids: Vec<i32>
+  },
+  /// Let automated writers change the labels of rows again
+  Unlock
+  {
+    taxonomy: String, ids: Vec<i32>
+  },
+  /// Show the labels of a row with their provenance
+  Show
+  {
+    id: i32
+  },
+  /// List the label changes of a row
+  History
+  {
+    id: i32
+  },
+}
+
+#[tokio::main]
+async fn main()
+{
+  let args = Cli::parse();
+
+  tracing_subscriber::fmt()
+    .with_max_level(tracing::Level::INFO)
+    // .with_target(false)
+    .init();
+
+  let user = args
+    .user
+    .clone()
+    .or_else(|| std::env::var("USER").ok())
+    .unwrap_or("user".to_string());
+
+  // Load configuration
+  let config: TendConfig =
+    load_config(&"configs/test.ini".to_string())
+      .expect("Wrong llm config");
+
+  // Connect to database using config details
+  let pool = connect_to_database(&config.postgres)
+    .await
+    .expect("Postgres error");
+  let client = pool.get().await.expect("Postgres error");
+
+  check_migrations(&client)
+    .await
+    .expect("Database schema check failed");
+
+  match &args.command {
+    Command::Set { id, taxonomy, label, lock, unlock } => {
+      let t =
+        load_taxonomy(taxonomy).expect("Could not read taxonomy");
+      prepare_taxonomy(&client, &t)
+        .await
+        .expect("Could not create the label column");
+      let label = match t.find_label(label) {
+        Some(x) => x,
+        None => {
+          tracing::error!("{} is not a label of {}", label, t.name);
+          return;
+        }
+      };
+      let set = set_label(
+        &client,
+        &t,
+        *id,
+        &label,
+        &Provenance::user(&user, *lock),
+      )
+      .await
+      .expect("Could not set the label");
+      if set {
+        tracing::info!("{} of row {} set to {}", t.name, id, label);
+        if *unlock {
+          lock_labels(&client, &t, &[*id], &user, false)
+            .await
+            .expect("Could not unlock the label");
+        }
+        if t.column == DENSITY_COLUMN {
+          update_row_volumes(&client, &config.density, &[*id])
+            .await
+            .expect("Could not update the volume");
+        }
+      } else {
+        tracing::warn!("No row {}", id);
+      }
+    }
+    Command::Lock { taxonomy, ids }
+    | Command::Unlock { taxonomy, ids } => {
+      let locked = matches!(args.command, Command::Lock { .. });
+      let t =
+        load_taxonomy(taxonomy).expect("Could not read taxonomy");
+      let n = lock_labels(&client, &t, ids, &user, locked)
+        .await
+        .expect("Could not lock the labels");
+      tracing::info!(
+        "{} {} label(s) of {}",
+        if locked { "Locked" } else { "Unlocked" },
+        n,
+        t.name
+      );
+    }
+    Command::Show { id } => {
+      let row = client
+        .query_opt(
+          "SELECT to_jsonb(g)::TEXT, label_meta::TEXT \
+          FROM general_schema g WHERE id = $1",
+          &[&id],
+        )
+        .await
+        .expect("Could not read the row");
+      let Some(row) = row else {
+        tracing::warn!("No row {}", id);
+        return;
+      };
+      let values: HashMap<String, serde_json::Value> =
+        serde_json::from_str(row.get(0)).expect("Bad row");
+      let meta: HashMap<String, Provenance> =
+        serde_json::from_str(row.get(1)).expect("Bad label_meta");
+      for t in TAXONOMIES.iter() {
+        let label = values
+          .get(&t.column)
+          .and_then(|x| x.as_str())
+          .unwrap_or("-");
+        match meta.get(&t.name) {
+          Some(p) => println!(
+            "{:<16} {:<12} {} {} {} {}{}",
+            t.name,
+            label,
+            p.source
+              .map(|x| format!("{:?}", x))
+              .unwrap_or("-".into()),
+            p.reference.as_deref().unwrap_or("-"),
+            p.at.as_deref().unwrap_or("-"),
+            p.prompt.as_deref().unwrap_or(""),
+            match (p.locked, &p.locked_by) {
+              (true, Some(x)) => format!(" locked by {}", x),
+              (true, None) => " locked".to_string(),
+              _ => "".to_string(),
+            }
+          ),
+          None => println!("{:<16} {}", t.name, label),
+        }
+      }
+    }
+    Command::History { id } => {
+      let changes = label_history(&client, *id)
+        .await
+        .expect("Could not read the history");
+      for c in changes {
+        println!(
+          "{} {:<16} {} -> {} {} {} {} by {}{}",
+          c.changed_at,
+          c.taxonomy,
+          c.old_label.as_deref().unwrap_or("-"),
+          c.new_label.as_deref().unwrap_or("-"),
+          c.source.as_deref().unwrap_or("-"),
+          c.reference.as_deref().unwrap_or("-"),
+          c.prompt.as_deref().unwrap_or(""),
+          c.changed_by,
+          if c.locked { " locked" } else { "" }
+        );
+      }
+    }
+  }
+}
@@ -0,0 +1,189 @@
+use clap::Parser;
+use poly_cls::conf::*;
+use poly_cls::countries::*;
+use poly_cls::currency::*;
+use poly_cls::database::*;
+use poly_cls::entities::*;
+use poly_cls::migrations::*;
+use poly_cls::taxonomies::*;
+use poly_cls::text::*;
+use poly_cls::tnved::*;
+use poly_cls::units::*;
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Cli
+{
+  /// Only list applied and pending migrations
+  #[arg(long)]
+  status: bool,
+
+  /// Drop all managed tables before migrating. Deletes all data!
+  #[arg(long)]
+  purge: bool,
+
+  /// Recompute normalized quantities and volumes of all stored rows,
+  /// e.g. after changing [density]
+  #[arg(long)]
+  normalize_units: bool,
+
+  /// Reload the exchange rates of [currency] and recompute the RUB and
+  /// USD values of all stored rows
+  #[arg(long)]
+  normalize_values: bool,
+
+  /// Link the parties of all stored rows to companies
+  #[arg(long)]
+  resolve_companies: bool,
+
+  /// Recompute the ISO codes and canonical names of the countries of
+  /// all stored rows
+  #[arg(long)]
+  normalize_countries: bool,
+
+  /// Store the TN VED reference, with [tnved] reference if set, and
+  /// recompute the normalized TN VED codes of all stored rows
+  #[arg(long)]
+  normalize_tnved: bool,
+
+  /// Prepare the descriptions of all stored rows again and read their
+  /// language, dimensions and density
+  #[arg(long)]
+  normalize_text: bool,
+
+  /// Store the labels of all stored rows as their taxonomies read them;
+  /// values outside a taxonomy are left for classification
+  #[arg(long)]
+  normalize_labels: bool,
+}
+
+#[tokio::main]
+async fn main()
+{
+  let args = Cli::parse();
+
+  tracing_subscriber::fmt()
+    .with_max_level(tracing::Level::INFO)
+    // .with_target(false)
+    .init();
+
+  // Load configuration
+  let config: TendConfig =
+    load_config(&"configs/test.ini".to_string())
+      .expect("Wrong llm config");
+
+  // Connect to database using config details
+  let pool = connect_to_database(&config.postgres)
+    .await
+    .expect("Postgres error");
+  let mut client = pool.get().await.expect("Postgres error");
+
+  if args.status {
+    let applied = applied_migrations(&client)
+      .await
+      .expect("Could not read schema_migrations");
+    for m in MIGRATIONS {
+      let state = if applied.contains(&m.version) {
+        "applied"
+      } else {
+        "pending"
+      };
+      println!("{:04} {:<24} {}", m.version, m.name, state);
+    }
+    return;
+  }
+
+  let done = init_database(&mut client, args.purge)
+    .await
+    .expect("Could not apply migrations");
+
+  tracing::info!("Applied {} migration(s)", done);
+
+  let errors = conversion_errors(&client)
+    .await
+    .expect("Could not read type_conversion_errors");
+  for (column, count) in errors {
+    tracing::warn!(
+      "{} value(s) of {} could not be converted and were cleared, \
+      see type_conversion_errors",
+      count,
+      column
+    );
+  }
+
+  if args.normalize_units {
+    let changed = normalize_stored_units(&client)
+      .await
+      .expect("Could not normalize units");
+    let volumes = update_volumes(&client, &config.density, None)
+      .await
+      .expect("Could not update volumes");
+    tracing::info!(
+      "Normalized units of {} row(s), volumes of {} row(s)",
+      changed,
+      volumes
+    );
+  }
+
+  if args.normalize_values {
+    if let Some(path) = &config.currency.rates {
+      let n = load_rates(&client, path)
+        .await
+        .expect("Could not load exchange rates");
+      tracing::info!("Loaded {} exchange rates from {:?}", n, path);
+    }
+    let changed = update_values(&client, None)
+      .await
+      .expect("Could not update values");
+    tracing::info!("Updated values of {} row(s)", changed);
+  }
+
+  if args.resolve_companies {
+    let stats = resolve_companies(&client, None)
+      .await
+      .expect("Could not resolve companies");
+    tracing::info!(
+      "Resolved {} row(s): {} linked, {} new companies",
+      stats.rows,
+      stats.linked,
+      stats.created
+    );
+  }
+
+  if args.normalize_countries {
+    let changed = normalize_stored_countries(&client)
+      .await
+      .expect("Could not normalize countries");
+    tracing::info!("Normalized countries of {} row(s)", changed);
+  }
+
+  if args.normalize_tnved {
+    let tnved =
+      Tnved::load(&config.tnved).expect("Could not read TN VED");
+    let n = store_tnved(&client, &tnved)
+      .await
+      .expect("Could not store TN VED reference");
+    let changed = normalize_stored_tnved(&client, &tnved)
+      .await
+      .expect("Could not normalize TN VED codes");
+    tracing::info!(
+      "Stored {} TN VED codes, normalized codes of {} row(s)",
+      n,
+      changed
+    );
+  }
+
+  if args.normalize_text {
+    let changed = normalize_stored_text(&client)
+      .await
+      .expect("Could not prepare descriptions");
+    tracing::info!("Prepared descriptions of {} row(s)", changed);
+  }
+
+  if args.normalize_labels {
+    let changed = normalize_stored_labels(&client)
+      .await
+      .expect("Could not normalize labels");
+    tracing::info!("Normalized {} label(s)", changed);
+  }
+}
@@ -0,0 +1,107 @@
+use clap::Parser;
+use poly_cls::conf::*;
+use poly_cls::database::*;
+use poly_cls::migrations::*;
+use poly_cls::runs::*;
+use poly_cls::validation::*;
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Cli
+{
+  /// Id of the import run to undo
+  run_id: Option<i32>,
+
+  /// List recent import runs instead
+  #[arg(long)]
+  list: bool,
+
+  /// Print the rejected rows and flagged values of the given run instead
+  #[arg(long)]
+  rejects: bool,
+
+  /// Number of runs to list
+  #[arg(long, default_value_t = 20)]
+  limit: i64,
+}
+
+#[tokio::main]
+async fn main()
+{
+  let args = Cli::parse();
+
+  tracing_subscriber::fmt()
+    .with_max_level(tracing::Level::INFO)
+    // .with_target(false)
+    .init();
+
+  // Load configuration
+  let config: TendConfig =
+    load_config(&"configs/test.ini".to_string())
+      .expect("Wrong llm config");
+
+  // Connect to database using config details
+  let pool = connect_to_database(&config.postgres)
+    .await
+    .expect("Postgres error");
+  let mut client = pool.get().await.expect("Postgres error");
+
+  check_migrations(&client)
+    .await
+    .expect("Database schema check failed");
+
+  if args.list || args.run_id.is_none() {
+    let runs = list_runs(&client, args.limit)
+      .await
+      .expect("Could not read import_runs");
+    for r in runs {
+      println!(
+        "{:>5} {:<11} {:<4} read {:>7} ins {:>7} upd {:>7} rej {:>5} \
+         emb {:>7} {} {}",
+        r.id,
+        r.status,
+        r.seg.unwrap_or_default(),
+        r.rows_read,
+        r.rows_inserted,
+        r.rows_updated,
+        r.rows_rejected,
+        r.rows_embedded,
+        r.started_at.map(|x| x.to_string()).unwrap_or_default(),
+        r.file_path
+      );
+    }
+    return;
+  }
+
+  let run_id = args.run_id.unwrap();
+  if args.rejects {
+    let rejects = list_rejects(&client, run_id)
+      .await
+      .expect("Could not read import_rejects");
+    for r in rejects {
+      println!(
+        "{:>7} {} {:<28} {} | {}",
+        r.line.unwrap_or_default(),
+        if r.loaded { "flagged " } else { "rejected" },
+        r.column.unwrap_or_default(),
+        r.reason,
+        r.raw.unwrap_or_default()
+      );
+    }
+    return;
+  }
+
+  let stats = rollback_run(&mut client, run_id)
+    .await
+    .expect("Could not roll back import run");
+
+  tracing::info!(
+    "Rolled back run {}: {} rows deleted, {} rows restored, {} \
+     embeddings deleted, {} companies deleted",
+    run_id,
+    stats.deleted,
+    stats.restored,
+    stats.deleted_docs,
+    stats.deleted_companies
+  );
+}
@@ -1,7 +1,9 @@
 use poly_cls::agents::*;
 use poly_cls::conf::*;
 use poly_cls::database::*;
+use poly_cls::migrations::*;
 use poly_cls::schemas::*;
+use poly_cls::taxonomies::*;
 use std::path::PathBuf;
 
 #[tokio::main]
@@ -14,27 +16,27 @@ async fn main() -> Result<(), Box<dyn std::error::Error>>
   let llm_configs = load_llm_config(&"configs/test.ini".to_string())?;
 
   // Connect to database using config details
-  let client = connect_to_database(
-    &config.postgres.host,
-    &config.postgres.user,
-    &config.postgres.psswd,
-  )
-  .await?;
+  let pool = connect_to_database(&config.postgres).await?;
+  let mut client = pool.get().await?;
 
   let file_path = PathBuf::from("samples/eas_customs_2.csv");
 
-  init_vector_storage_data(&client, false).await?;
+  init_database(&mut client, false).await?;
+
+  let material = load_taxonomy("material")?;
 
   let rows =
     GeneralSchema::from_csv_file_path(&file_path, SegmentT::Eas)?;
 
   for row in rows {
-    insert_vec_store(&client, &row, &llm_configs[1]).await?;
+    insert_vec_store(&client, &row, &material, &llm_configs[1], None)
+      .await?;
   }
 
   let search = find_vec_store(
     &client,
     &"пенополистирол".to_string(),
+    &material,
     &llm_configs[1],
     2,
   )
@@ -1,47 +1,357 @@
-use crate::agents::cls;
+use crate::agents::{cls, relevant};
 use crate::conf::{LlmConfig, MainConfig};
 use crate::database::{
-  ToEval, find_vec_store, get_rows_for_eval, update_eval_status,
+  Pool, ToEval, find_vec_store, get_rows_for_eval, update_eval_status,
+};
+use crate::taxonomies::{
+  FilterT, Provenance, Relevance, Taxonomy, prepare_taxonomy,
 };
-use crate::schemas::*;
 use futures::future::join_all;
+use std::error::Error;
 use tokio_postgres::Client;
 
+/// Rows searched or asked about at once
+const CHUNK: usize = 50;
+
+/// Stages recorded in cls_decisions
+pub const STAGE_RELEVANCE: &str = "relevance";
+pub const STAGE_LABEL: &str = "label";
+
+/// What a stage decided on a row, how and with which model
+#[derive(Debug, Clone, PartialEq)]
+pub struct Decision
+{
+  pub stage: &'static str,
+  /// relevant or irrelevant, or the label
+  pub decision: String,
+  /// rules, knn or model; default when no filter decided, relevance
+  /// for labels given by the first stage
+  pub method: String,
+  pub model: Option<String>,
+}
+
+/// Where the closest labelled examples of rows are searched
+struct ExampleSearch<'a>
+{
+  pool: &'a Pool,
+  taxonomy: &'a Taxonomy,
+  /// Model embedding the descriptions
+  conf_emb: &'a LlmConfig,
+  top_n: usize,
+}
+
+/// Store the closest labelled examples of the rows at `a_idx` that have
+/// none yet. Rows whose search fails are logged and left without.
+async fn find_examples(
+  a_search: &ExampleSearch<'_>,
+  a_rows: &[ToEval],
+  a_idx: &[usize],
+  a_out: &mut [Option<Vec<String>>],
+)
+{
+  let missing: Vec<usize> = a_idx
+    .iter()
+    .copied()
+    .filter(|x| a_out[*x].is_none())
+    .collect();
+
+  for ch in missing.chunks(CHUNK) {
+    let mut sims = Vec::new();
+    for i in ch {
+      let itm = &a_rows[*i];
+      sims.push(async move {
+        let mut descr = itm.descr.clone();
+        if descr.len() > 512 {
+          let max = descr.floor_char_boundary(510);
+          descr.truncate(max);
+        }
+        let conn = a_search.pool.get().await?;
+        find_vec_store(
+          &conn,
+          &descr,
+          a_search.taxonomy,
+          a_search.conf_emb,
+          a_search.top_n,
+        )
+        .await
+      });
+    }
+
+    for (i, sim) in ch.iter().zip(join_all(sims).await) {
+      match sim {
+        Ok(x) => a_out[*i] = Some(x),
+        Err(e) => {
+          tracing::error!(
+            " Could not find examples of id: {} err: {:?}",
+            a_rows[*i].id,
+            e
+          );
+        }
+      }
+    }
+  }
+}
+
+/// First stage: whether each row is relevant, by the methods of the
+/// taxonomy in order. Rows no method decides on are relevant.
+async fn filter_relevance(
+  a_search: &ExampleSearch<'_>,
+  a_relevance: &Relevance,
+  a_rows: &[ToEval],
+  a_conf_filter: Option<&LlmConfig>,
+  a_examples: &mut [Option<Vec<String>>],
+) -> Vec<(bool, Decision)>
+{
+  let mut state: Vec<Option<(bool, FilterT, Option<String>)>> =
+    vec![None; a_rows.len()];
+
+  for m in a_relevance.methods.iter() {
+    let open: Vec<usize> =
+      (0..a_rows.len()).filter(|x| state[*x].is_none()).collect();
+    if open.is_empty() {
+      break;
+    }
+
+    match m {
+      FilterT::Rules => {
+        for i in open {
+          let itm = &a_rows[i];
+          state[i] = a_relevance
+            .by_rules(&itm.product, itm.tnved.as_deref())
+            .map(|x| (x, *m, None));
+        }
+      }
+      FilterT::Knn => {
+        find_examples(a_search, a_rows, &open, a_examples).await;
+        for i in open {
+          // Left to the next method when the search failed
+          let Some(docs) = a_examples[i].as_deref() else {
+            continue;
+          };
+          state[i] = a_relevance.by_examples(docs).map(|x| {
+            (x, *m, Some(a_search.conf_emb.model_name.clone()))
+          });
+        }
+      }
+      FilterT::Model => {
+        let conf = match a_conf_filter {
+          Some(x) => x,
+          None => {
+            tracing::warn!(
+              "{}: no relevance model configured, skipping it",
+              a_search.taxonomy.name
+            );
+            continue;
+          }
+        };
+        for ch in open.chunks(CHUNK) {
+          let mut tts = Vec::new();
+          for i in ch {
+            tts.push(relevant(
+              a_rows[*i].descr.clone(),
+              a_relevance,
+              conf,
+            ));
+          }
+
+          for (i, t) in ch.iter().zip(join_all(tts).await) {
+            match t {
+              Ok(x) => {
+                state[*i] =
+                  x.map(|x| (x, *m, Some(conf.model_name.clone())))
+              }
+              Err(e) => {
+                tracing::error!(
+                  " Could not check relevance of id: {} err: {:?}",
+                  a_rows[*i].id,
+                  e
+                );
+              }
+            }
+          }
+        }
+      }
+    }
+  }
+
+  state
+    .into_iter()
+    .map(|x| {
+      let (ok, method, model) = match x {
+        Some((ok, m, model)) => (ok, m.code().to_string(), model),
+        None => (true, "default".to_string(), None),
+      };
+      let decision = Decision {
+        stage: STAGE_RELEVANCE,
+        decision: if ok { "relevant" } else { "irrelevant" }
+          .to_string(),
+        method,
+        model,
+      };
+      (ok, decision)
+    })
+    .collect()
+}
+
+/// Store the decisions of the stages, replacing earlier ones of the same
+/// row, taxonomy and stage
+pub async fn store_decisions(
+  a_cli: &Client,
+  a_taxonomy: &Taxonomy,
+  a_decisions: &[(i32, Decision)],
+) -> Result<u64, Box<dyn Error>>
+{
+  if a_decisions.is_empty() {
+    return Ok(0);
+  }
+
+  let ids: Vec<i32> = a_decisions.iter().map(|x| x.0).collect();
+  let stages: Vec<&str> =
+    a_decisions.iter().map(|x| x.1.stage).collect();
+  let decisions: Vec<&str> =
+    a_decisions.iter().map(|x| x.1.decision.as_str()).collect();
+  let methods: Vec<&str> =
+    a_decisions.iter().map(|x| x.1.method.as_str()).collect();
+  let models: Vec<Option<&str>> =
+    a_decisions.iter().map(|x| x.1.model.as_deref()).collect();
+
+  let n = a_cli
+    .execute(
+      "INSERT INTO cls_decisions \
+        (general_schema_id, taxonomy, stage, decision, method, model) \
+      SELECT u.id, $1, u.stage, u.decision, u.method, u.model \
+      FROM UNNEST($2::INTEGER[], $3::TEXT[], $4::TEXT[], $5::TEXT[], \
+        $6::TEXT[]) AS u(id, stage, decision, method, model) \
+      ON CONFLICT (general_schema_id, taxonomy, stage) DO UPDATE SET \
+        decision = EXCLUDED.decision, method = EXCLUDED.method, \
+        model = EXCLUDED.model, decided_at = now()",
+      &[&a_taxonomy.name, &ids, &stages, &decisions, &methods, &models],
+    )
+    .await?;
+
+  Ok(n)
+}
+
+/// Classify the rows waiting for a label of the taxonomy through
+/// `a_cli`, with the closest labelled examples, searched with
+/// connections of `a_pool`, in the prompt. Taxonomies with a relevance
+/// stage first give rows found not relevant its label, and only the
+/// others are asked about; `a_conf_filter` is the small model of that
+/// stage. Rows without examples, as their search failed, wait for the
+/// next run. Returns the rows with their new labels; locked rows and
+/// rows not labelled keep the pending label, and only labelled rows
+/// get their decisions stored.
 pub async fn eval(
   a_cli: &Client,
+  a_pool: &Pool,
+  a_taxonomy: &Taxonomy,
   a_conf_emb: &LlmConfig,
   a_conf_model: &LlmConfig,
+  a_conf_filter: Option<&LlmConfig>,
   a_conf_main: &MainConfig,
 ) -> Vec<ToEval>
 {
-  let mut to_eval = get_rows_for_eval(a_cli)
+  prepare_taxonomy(a_cli, a_taxonomy)
+    .await
+    .expect("Could not create the label column");
+  let mut to_eval = get_rows_for_eval(a_cli, a_taxonomy)
     .await
     .expect("Could not retrieve data from db");
 
-  let mut tasks = Vec::new();
-  for itm in to_eval.iter() {
-    let mut descr = itm.descr.clone();
-    if descr.len() > 512 {
-      let max = descr.floor_char_boundary(510);
-      descr.truncate(max);
+  let search = ExampleSearch {
+    pool: a_pool,
+    taxonomy: a_taxonomy,
+    conf_emb: a_conf_emb,
+    top_n: a_conf_main.top_n,
+  };
+  let mut examples: Vec<Option<Vec<String>>> =
+    vec![None; to_eval.len()];
+  let mut decisions: Vec<(i32, Decision)> = Vec::new();
+  // Relevance decisions of the rows left to the classifier, stored
+  // once they are labelled
+  let mut relevance: Vec<Option<Decision>> =
+    vec![None; to_eval.len()];
+  let mut open: Vec<usize> = (0..to_eval.len()).collect();
+
+  if let Some(r) = &a_taxonomy.relevance {
+    let filtered = filter_relevance(
+      &search,
+      r,
+      &to_eval,
+      a_conf_filter,
+      &mut examples,
+    )
+    .await;
+
+    open.clear();
+    for (i, (ok, decision)) in filtered.into_iter().enumerate() {
+      if ok {
+        relevance[i] = Some(decision);
+        open.push(i);
+        continue;
+      }
+
+      let id: i32 = to_eval[i].id.parse().unwrap_or_default();
+      let prov = match &decision.model {
+        Some(m) if decision.method == FilterT::Model.code() => {
+          Provenance::model(m, &r.prompt())
+        }
+        _ => {
+          Provenance::rule(&format!("relevance:{}", decision.method))
+        }
+      };
+      let pending =
+        std::mem::replace(&mut to_eval[i].eval, r.label.clone());
+      let set =
+        update_eval_status(a_cli, a_taxonomy, &to_eval[i], &prov)
+          .await
+          .expect("Could not update the entries");
+      if !set {
+        tracing::info!("Label of item {} is locked", &to_eval[i].id);
+        to_eval[i].eval = pending;
+        continue;
+      }
+      decisions.push((id, decision));
+      decisions.push((
+        id,
+        Decision {
+          stage: STAGE_LABEL,
+          decision: r.label.clone(),
+          method: "relevance".to_string(),
+          model: None,
+        },
+      ));
     }
-    let sim =
-      find_vec_store(a_cli, &descr, a_conf_emb, a_conf_main.top_n)
-        .await
-        .expect("Could not make vector search");
+    tracing::info!(
+      "{}: {} of {} row(s) relevant",
+      a_taxonomy.name,
+      open.len(),
+      to_eval.len()
+    );
+  }
+
+  find_examples(&search, &to_eval, &open, &mut examples).await;
+  open.retain(|x| examples[*x].is_some());
 
-    let smpls = sim.join("\n");
-    let prompt =
-      format!("DESCRIPTION: {}\nSAMPLES:\n{}", &itm.descr, smpls);
+  let mut tasks = Vec::new();
+  for i in open.iter() {
+    let smpls =
+      examples[*i].as_deref().unwrap_or_default().join("\n");
+    let prompt = format!(
+      "DESCRIPTION: {}\nSAMPLES:\n{}",
+      &to_eval[*i].descr, smpls
+    );
 
     tasks.push(prompt);
   }
 
+  let prov =
+    Provenance::model(&a_conf_model.model_name, &a_taxonomy.prompt());
   let mut results = Vec::new();
-  for ch in tasks.chunks(50) {
+  for ch in tasks.chunks(CHUNK) {
     let mut tts = Vec::new();
     for s in ch {
-      tts.push(cls(s.clone(), a_conf_model));
+      tts.push(cls(s.clone(), a_taxonomy, a_conf_model));
     }
 
     for t in join_all(tts).await {
@@ -49,21 +359,37 @@ pub async fn eval(
     }
   }
 
-  for (eval, res_) in to_eval.iter_mut().zip(results) {
+  for (i, res_) in open.into_iter().zip(results) {
+    let eval = &mut to_eval[i];
     let out = res_;
 
     match out {
       Ok(res) => {
-        match res {
-          TypeT::NoEval => {
-            tracing::warn!("Could not evaluate item {}", &eval.id);
-          }
-          _ => {}
+        if res == a_taxonomy.pending {
+          tracing::warn!("Could not evaluate item {}", &eval.id);
         }
-        eval.eval = res;
-        update_eval_status(a_cli, &eval)
+        let pending = std::mem::replace(&mut eval.eval, res);
+        let set = update_eval_status(a_cli, a_taxonomy, eval, &prov)
           .await
           .expect("Could not update the entries");
+        if !set {
+          tracing::info!("Label of item {} is locked", &eval.id);
+          eval.eval = pending;
+          continue;
+        }
+        let id: i32 = eval.id.parse().unwrap_or_default();
+        if let Some(x) = relevance[i].take() {
+          decisions.push((id, x));
+        }
+        decisions.push((
+          id,
+          Decision {
+            stage: STAGE_LABEL,
+            decision: eval.eval.clone(),
+            method: FilterT::Model.code().to_string(),
+            model: Some(a_conf_model.model_name.clone()),
+          },
+        ));
       }
       Err(e) => {
         tracing::error!(
@@ -75,5 +401,9 @@ pub async fn eval(
     }
   }
 
+  store_decisions(a_cli, a_taxonomy, &decisions)
+    .await
+    .expect("Could not store the decisions");
+
   return to_eval;
 }
@@ -1,5 +1,6 @@
 use ::config::{Config, ConfigError, File, FileFormat};
 use serde::Deserialize;
+use std::collections::HashMap;
 use std::env;
 use std::path::PathBuf;
 
@@ -35,7 +36,16 @@ pub fn load_config(a_path: &String)
   let config = Config::builder()
     .add_source(File::new(a_path.as_str(), FileFormat::Ini))
     .build()?;
-  config.try_deserialize()
+  let out: TendConfig = config.try_deserialize()?;
+
+  if out.postgres.pool_size < MIN_POOL_SIZE {
+    return Err(ConfigError::Message(format!(
+      "postgres.pool_size must be at least {}",
+      MIN_POOL_SIZE
+    )));
+  }
+
+  Ok(out)
 }
 
 pub fn load_llm_config(
@@ -72,6 +82,18 @@ pub struct TendConfig
 {
   pub main: MainConfig,
   pub postgres: PostgresConfig,
+  #[serde(default)]
+  pub import: ImportConfig,
+  #[serde(default)]
+  pub density: DensityConfig,
+  #[serde(default)]
+  pub currency: CurrencyConfig,
+  #[serde(default)]
+  pub tnved: TnvedConfig,
+  #[serde(default)]
+  pub attributes: AttributesConfig,
+  #[serde(default)]
+  pub cls: ClsConfig,
 }
 
 #[derive(Deserialize)]
@@ -83,15 +105,225 @@ pub struct MainConfig
   pub top_n: usize,
 }
 
+//===========================================================================//
+// import config                                                             //
+//===========================================================================//
+#[derive(Deserialize)]
+pub struct ImportConfig
+{
+  #[serde(default)]
+  pub drift: DriftModeT,
+  /// Rows inspected for value type changes
+  #[serde(default = "default_drift_sample_rows")]
+  pub drift_sample_rows: usize,
+  /// Rows read, loaded and embedded together. Bounds the memory used
+  /// by an import whatever the file size.
+  #[serde(default = "default_batch_size")]
+  pub batch_size: usize,
+}
+
+impl Default for ImportConfig
+{
+  fn default() -> Self
+  {
+    ImportConfig {
+      drift: DriftModeT::default(),
+      drift_sample_rows: default_drift_sample_rows(),
+      batch_size: default_batch_size(),
+    }
+  }
+}
+
+/// What to do when a file does not match its mapping
+#[derive(Deserialize, Default, Clone, Copy, Debug, PartialEq)]
+pub enum DriftModeT
+{
+  /// Refuse to import
+  #[serde(rename = "strict")]
+  Strict,
+  /// Import and warn
+  #[default]
+  #[serde(rename = "lenient")]
+  Lenient,
+}
+
+fn default_drift_sample_rows() -> usize
+{
+  1000
+}
+
+fn default_batch_size() -> usize
+{
+  5000
+}
+
+//===========================================================================//
+// density config                                                            //
+//===========================================================================//
+/// Bulk density in kg/m³ per classification label, used to estimate
+/// volumes from weights. Labels not listed use units::DEFAULT_DENSITIES.
+pub type DensityConfig = HashMap<String, f64>;
+
+//===========================================================================//
+// currency config                                                           //
+//===========================================================================//
+#[derive(Deserialize, Default)]
+pub struct CurrencyConfig
+{
+  /// CSV of exchange rates (date, currency, rate in RUB) loaded by
+  /// `insert` before importing
+  pub rates: Option<PathBuf>,
+}
+
+//===========================================================================//
+// tnved config                                                              //
+//===========================================================================//
+#[derive(Deserialize, Default)]
+pub struct TnvedConfig
+{
+  /// Complete TN VED reference (code, description) added to the
+  /// bundled one; codes missing from it are flagged on import
+  pub reference: Option<PathBuf>,
+}
+
+//===========================================================================//
+// attributes config                                                         //
+//===========================================================================//
+#[derive(Deserialize)]
+pub struct AttributesConfig
+{
+  /// Confidence given to attributes extracted by the model, below that
+  /// of the rules so that a rule match is not replaced
+  #[serde(default = "default_llm_confidence")]
+  pub llm_confidence: f32,
+}
+
+impl Default for AttributesConfig
+{
+  fn default() -> Self
+  {
+    AttributesConfig {
+      llm_confidence: default_llm_confidence(),
+    }
+  }
+}
+
+fn default_llm_confidence() -> f32
+{
+  0.6
+}
+
+//===========================================================================//
+// cls config                                                                //
+//===========================================================================//
+#[derive(Deserialize, Default)]
+pub struct ClsConfig
+{
+  /// Number of the [llm_N] section of the small model asked about
+  /// relevance; without it that stage is skipped
+  pub relevance_llm: Option<usize>,
+}
+
+impl ClsConfig
+{
+  /// The small model of the relevance stage, if configured
+  pub fn relevance_model<'a>(
+    &self,
+    a_llms: &'a [LlmConfig],
+  ) -> Option<&'a LlmConfig>
+  {
+    self
+      .relevance_llm
+      .and_then(|x| a_llms.get(x.checked_sub(1)?))
+  }
+}
+
 //===========================================================================//
 // postgres config                                                           //
 //===========================================================================//
 #[derive(Deserialize)]
 pub struct PostgresConfig
 {
+  #[serde(default)]
   pub host: String,
+  #[serde(default = "default_pg_port")]
+  pub port: u16,
+  pub dbname: Option<String>,
+  #[serde(default)]
   pub user: String,
+  #[serde(default)]
   pub psswd: String,
+  #[serde(default)]
+  pub sslmode: SslModeT,
+  /// PEM file with the CA certificate(s) used to verify the server
+  pub sslrootcert: Option<PathBuf>,
+  #[serde(default = "default_pg_app_name")]
+  pub application_name: String,
+  /// postgresql:// URL, takes precedence over the separate fields
+  pub url: Option<String>,
+  /// At least MIN_POOL_SIZE: imports and the classifier hold one
+  /// connection while embedding or searching with others
+  #[serde(default = "default_pg_pool_size")]
+  pub pool_size: usize,
+  /// Seconds to wait for a free connection before failing
+  #[serde(default = "default_pg_pool_wait")]
+  pub pool_wait: u64,
+}
+
+/// Smallest usable pool, see `PostgresConfig::pool_size`
+pub const MIN_POOL_SIZE: usize = 2;
+
+/// Same semantics as libpq sslmode
+#[derive(Deserialize, Default, Clone, Copy, Debug, PartialEq)]
+pub enum SslModeT
+{
+  #[serde(rename = "disable")]
+  Disable,
+  #[default]
+  #[serde(rename = "prefer")]
+  Prefer,
+  #[serde(rename = "require")]
+  Require,
+  #[serde(rename = "verify-ca")]
+  VerifyCa,
+  #[serde(rename = "verify-full")]
+  VerifyFull,
+}
+
+impl SslModeT
+{
+  /// The mode of a libpq sslmode value; allow is taken as prefer
+  pub fn from_libpq(a_value: &str) -> Option<Self>
+  {
+    match a_value {
+      "disable" => Some(SslModeT::Disable),
+      "allow" | "prefer" => Some(SslModeT::Prefer),
+      "require" => Some(SslModeT::Require),
+      "verify-ca" => Some(SslModeT::VerifyCa),
+      "verify-full" => Some(SslModeT::VerifyFull),
+      _ => None,
+    }
+  }
+}
+
+fn default_pg_port() -> u16
+{
+  5432
+}
+
+fn default_pg_app_name() -> String
+{
+  "poly_cls".to_string()
+}
+
+fn default_pg_pool_size() -> usize
+{
+  16
+}
+
+fn default_pg_pool_wait() -> u64
+{
+  60
 }
 
 //===========================================================================//
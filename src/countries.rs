@@ -0,0 +1,294 @@
+use crate::schemas::GeneralSchema;
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::LazyLock;
+use tokio_postgres::Client;
+
+/// Bundled ISO 3166 reference: alpha-2 and numeric codes, the short
+/// name of the Russian classifier of countries (ОКСМ) and other
+/// spellings met in the exports, separated by ';'
+const COUNTRIES_CSV: &str =
+  include_str!("../references/countries.csv");
+
+/// Rows read per query when normalizing stored rows
+const NORMALIZE_PAGE: i64 = 5000;
+
+/// A country of the reference
+#[derive(Debug, Clone, PartialEq)]
+pub struct Country
+{
+  pub alpha2: String,
+  /// ISO numeric code, 3 digits
+  pub numeric: String,
+  /// Canonical name, the ОКСМ short name
+  pub name: String,
+}
+
+/// Countries by alpha-2 code, numeric code and name key
+#[derive(Debug, Default)]
+pub struct Countries
+{
+  pub countries: Vec<Country>,
+  by_alpha2: HashMap<String, usize>,
+  by_numeric: HashMap<String, usize>,
+  by_name: HashMap<String, usize>,
+}
+
+/// The bundled reference, parsed on first use
+pub static COUNTRIES: LazyLock<Countries> = LazyLock::new(|| {
+  Countries::parse(COUNTRIES_CSV).expect("Invalid countries.csv")
+});
+
+/// Name in comparable form: upper case, Ё as Е, single spaces
+fn name_key(a_name: &str) -> String
+{
+  a_name
+    .to_uppercase()
+    .replace('Ё', "Е")
+    .split_whitespace()
+    .collect::<Vec<&str>>()
+    .join(" ")
+}
+
+impl Countries
+{
+  pub fn parse(a_csv: &str) -> Result<Self, Box<dyn Error>>
+  {
+    let mut out = Countries::default();
+    let mut reader = csv::Reader::from_reader(a_csv.as_bytes());
+
+    for record in reader.records() {
+      let rec = record?;
+      let field = |i: usize| rec.get(i).unwrap_or("").trim();
+      let i = out.countries.len();
+
+      out.by_alpha2.insert(field(0).to_string(), i);
+      out.by_numeric.insert(field(1).to_string(), i);
+      out.by_name.insert(name_key(field(2)), i);
+      for alias in field(3).split(';').filter(|x| !x.is_empty()) {
+        out.by_name.entry(name_key(alias)).or_insert(i);
+      }
+      out.countries.push(Country {
+        alpha2: field(0).to_string(),
+        numeric: field(1).to_string(),
+        name: field(2).to_string(),
+      });
+    }
+
+    Ok(out)
+  }
+
+  /// Country of a code: alpha-2 in any case, or numeric with lost
+  /// leading zeros or float formatting ("40.0" is Austria)
+  pub fn by_code(&self, a_code: &str) -> Option<&Country>
+  {
+    let s = a_code.trim();
+    let i = if s.chars().all(|x| x.is_ascii_alphabetic()) {
+      self.by_alpha2.get(&s.to_uppercase())
+    } else {
+      let n = s.trim_end_matches(".0").parse::<u32>().ok()?;
+      self.by_numeric.get(&format!("{:03}", n))
+    }?;
+
+    Some(&self.countries[*i])
+  }
+
+  pub fn by_name(&self, a_name: &str) -> Option<&Country>
+  {
+    self
+      .by_name
+      .get(&name_key(a_name))
+      .map(|i| &self.countries[*i])
+  }
+
+  /// Country of a code and name pair as found in a row. The code wins;
+  /// EAS exports put both in the code column ("BY - БЕЛАРУСЬ") and a
+  /// name in the code column is matched as a name.
+  pub fn find(
+    &self,
+    a_code: Option<&str>,
+    a_name: Option<&str>,
+  ) -> Option<&Country>
+  {
+    let (code, name) = match a_code.and_then(|x| x.split_once(" - "))
+    {
+      Some((c, n)) => (Some(c), Some(n)),
+      None => (a_code, None),
+    };
+
+    code
+      .and_then(|x| self.by_code(x))
+      .or_else(|| name.and_then(|x| self.by_name(x)))
+      .or_else(|| a_name.and_then(|x| self.by_name(x)))
+      .or_else(|| code.and_then(|x| self.by_name(x)))
+  }
+}
+
+/// ISO alpha-2 code and canonical name of a code and name pair, None
+/// when the country is not in the reference
+pub fn normalize_country(
+  a_code: Option<&str>,
+  a_name: Option<&str>,
+) -> (Option<String>, Option<String>)
+{
+  match COUNTRIES.find(a_code, a_name) {
+    Some(c) => (Some(c.alpha2.clone()), Some(c.name.clone())),
+    None => (None, None),
+  }
+}
+
+/// Fill the ISO code and canonical name of every country field of a
+/// converted row
+pub fn normalize_countries(a_row: &mut GeneralSchema)
+{
+  (a_row.origin_country_iso, a_row.origin_country_name) =
+    normalize_country(
+      a_row.origin_country_code.as_deref(),
+      a_row.origin_country.as_deref(),
+    );
+  (
+    a_row.destination_country_iso,
+    a_row.destination_country_name,
+  ) = normalize_country(
+    a_row.destination_country_code.as_deref(),
+    a_row.destination_country.as_deref(),
+  );
+  (a_row.trade_country_iso, a_row.trade_country_name) =
+    normalize_country(
+      a_row.trade_country_code.as_deref(),
+      a_row.trade_country.as_deref(),
+    );
+  (a_row.origin_country_2_iso, a_row.origin_country_2_name) =
+    normalize_country(
+      a_row.origin_country_code_2.as_deref(),
+      a_row.origin_country_2.as_deref(),
+    );
+}
+
+/// Recompute the ISO codes and canonical names of every stored row,
+/// e.g. for rows imported before countries were normalized. Returns the
+/// rows changed.
+pub async fn normalize_stored_countries(
+  a_cli: &Client,
+) -> Result<u64, Box<dyn Error>>
+{
+  let mut last_id = 0;
+  let mut changed = 0;
+
+  loop {
+    let rows = a_cli
+      .query(
+        "SELECT id, origin_country_code, origin_country, \
+        destination_country_code, destination_country, trade_country_code, \
+        trade_country, origin_country_code_2, origin_country_2 \
+        FROM general_schema WHERE id > $1 ORDER BY id LIMIT $2",
+        &[&last_id, &NORMALIZE_PAGE],
+      )
+      .await?;
+    if rows.is_empty() {
+      break;
+    }
+
+    let mut ids = Vec::with_capacity(rows.len());
+    // ISO code and name per country field, in column order
+    let mut values: Vec<Vec<Option<String>>> = vec![Vec::new(); 8];
+    for r in &rows {
+      ids.push(r.get::<_, i32>(0));
+      for k in 0..4 {
+        let code: Option<String> = r.get(1 + 2 * k);
+        let name: Option<String> = r.get(2 + 2 * k);
+        let (iso, canonical) =
+          normalize_country(code.as_deref(), name.as_deref());
+        values[2 * k].push(iso);
+        values[2 * k + 1].push(canonical);
+      }
+    }
+    last_id = *ids.last().unwrap_or(&last_id);
+
+    changed += a_cli
+      .execute(
+        "UPDATE general_schema g SET \
+          origin_country_iso = u.o_iso, origin_country_name = u.o_name, \
+          destination_country_iso = u.d_iso, \
+          destination_country_name = u.d_name, \
+          trade_country_iso = u.t_iso, trade_country_name = u.t_name, \
+          origin_country_2_iso = u.o2_iso, \
+          origin_country_2_name = u.o2_name \
+        FROM UNNEST($1::INTEGER[], $2::TEXT[], $3::TEXT[], $4::TEXT[], \
+          $5::TEXT[], $6::TEXT[], $7::TEXT[], $8::TEXT[], $9::TEXT[]) \
+          AS u(id, o_iso, o_name, d_iso, d_name, t_iso, t_name, o2_iso, \
+            o2_name) \
+        WHERE g.id = u.id AND (g.origin_country_iso, \
+            g.origin_country_name, g.destination_country_iso, \
+            g.destination_country_name, g.trade_country_iso, \
+            g.trade_country_name, g.origin_country_2_iso, \
+            g.origin_country_2_name) \
+          IS DISTINCT FROM (u.o_iso, u.o_name, u.d_iso, u.d_name, \
+            u.t_iso, u.t_name, u.o2_iso, u.o2_name)",
+        &[
+          &ids, &values[0], &values[1], &values[2], &values[3],
+          &values[4], &values[5], &values[6], &values[7],
+        ],
+      )
+      .await?;
+  }
+
+  Ok(changed)
+}
+
+#[cfg(test)]
+mod tests
+{
+  use super::*;
+
+  fn alpha2(
+    a_code: Option<&str>,
+    a_name: Option<&str>,
+  ) -> Option<String>
+  {
+    COUNTRIES.find(a_code, a_name).map(|x| x.alpha2.clone())
+  }
+
+  #[test]
+  fn find_by_code()
+  {
+    assert_eq!(alpha2(Some("cn"), None), Some("CN".into()));
+    assert_eq!(alpha2(Some("40.0"), None), Some("AT".into()));
+    assert_eq!(alpha2(Some("643"), None), Some("RU".into()));
+    // The code wins over the name
+    assert_eq!(alpha2(Some("KZ"), Some("Китай")), Some("KZ".into()));
+  }
+
+  #[test]
+  fn find_by_name()
+  {
+    assert_eq!(
+      alpha2(Some("BY - БЕЛАРУСЬ"), None),
+      Some("BY".into())
+    );
+    assert_eq!(
+      alpha2(Some("XX - Беларусь"), None),
+      Some("BY".into())
+    );
+    assert_eq!(
+      alpha2(None, Some("китайская  народная республика")),
+      Some("CN".into())
+    );
+    assert_eq!(
+      alpha2(Some("Российская Федерация"), None),
+      Some("RU".into())
+    );
+    assert_eq!(alpha2(Some("??"), Some("Атлантида")), None);
+    assert_eq!(alpha2(None, None), None);
+  }
+
+  #[test]
+  fn canonical_name()
+  {
+    assert_eq!(
+      normalize_country(None, Some("Белоруссия")),
+      (Some("BY".to_string()), Some("БЕЛАРУСЬ".to_string()))
+    );
+    assert_eq!(normalize_country(Some("0"), None), (None, None));
+  }
+}
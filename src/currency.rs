@@ -0,0 +1,149 @@
+use crate::database::parse_date;
+use crate::validation::parse_number;
+use chrono::NaiveDate;
+use csv::ReaderBuilder;
+use rust_decimal::Decimal;
+use std::error::Error;
+use std::path::PathBuf;
+use tokio_postgres::Client;
+
+/// fx_method of values converted with the exchange rate of the row
+pub const FX_ROW_RATE: &str = "row_rate";
+
+/// fx_method of values converted with rates from fx_rates only
+pub const FX_TABLE: &str = "table";
+
+/// fx_method of rows without an invoice currency, valued from their
+/// declared USD amount
+pub const FX_DECLARED_USD: &str = "declared_usd";
+
+/// Load exchange rates from a CSV file with `date`, `currency` and
+/// `rate` columns, the rate being RUB per unit of the currency. Rates
+/// already loaded for the same day are replaced. Returns the rates
+/// read.
+pub async fn load_rates(
+  a_cli: &Client,
+  a_path: &PathBuf,
+) -> Result<u64, Box<dyn Error>>
+{
+  let mut reader = ReaderBuilder::new()
+    .trim(csv::Trim::All)
+    .from_path(a_path)?;
+  let headers = reader.headers()?.clone();
+  let column = |name: &str| {
+    headers
+      .iter()
+      .position(|x| x.eq_ignore_ascii_case(name))
+      .ok_or(format!("{:?}: no {} column", a_path, name))
+  };
+  let (i_date, i_cur, i_rate) =
+    (column("date")?, column("currency")?, column("rate")?);
+
+  let mut dates: Vec<NaiveDate> = Vec::new();
+  let mut currencies: Vec<String> = Vec::new();
+  let mut rates: Vec<Decimal> = Vec::new();
+  for record in reader.records() {
+    let rec = record?;
+    let line = rec.position().map(|x| x.line()).unwrap_or_default();
+    let field = |i: usize| rec.get(i).unwrap_or("");
+
+    let date = parse_date(field(i_date)).ok_or(format!(
+      "{:?} line {}: unparseable date '{}'",
+      a_path,
+      line,
+      field(i_date)
+    ))?;
+    // Rates may be written with a decimal comma
+    let rate = parse_number(field(i_rate), ',')
+      .filter(|x| x.is_sign_positive() && !x.is_zero())
+      .ok_or(format!(
+        "{:?} line {}: invalid rate '{}'",
+        a_path,
+        line,
+        field(i_rate)
+      ))?;
+
+    dates.push(date);
+    currencies.push(field(i_cur).to_string());
+    rates.push(rate);
+  }
+
+  a_cli
+    .execute(
+      "INSERT INTO fx_rates (currency, rate_date, rate) \
+      SELECT DISTINCT ON (c, d) c, d, r \
+      FROM UNNEST($1::TEXT[], $2::DATE[], $3::NUMERIC[]) \
+        WITH ORDINALITY AS u(code, d, r, n), fx_currency(u.code) c \
+      WHERE c IS NOT NULL ORDER BY c, d, n DESC \
+      ON CONFLICT (currency, rate_date) \
+      DO UPDATE SET rate = EXCLUDED.rate",
+      &[&currencies, &dates, &rates],
+    )
+    .await?;
+
+  Ok(rates.len() as u64)
+}
+
+/// Derive value_rub and value_usd of the invoice value and record the
+/// method in fx_method. The invoice currency is converted with the
+/// row's exchange rate, which is quoted in the currency of the segment
+/// (KZT for Kz, RUB otherwise), or with fx_rates at the row's date when
+/// the row has none. Rows without an invoice currency are valued from
+/// stat_value_usd. Limited to the rows of one import run when
+/// `a_run_id` is set; run again after loading new rates.
+pub async fn update_values(
+  a_cli: &Client,
+  a_run_id: Option<i32>,
+) -> Result<u64, Box<dyn Error>>
+{
+  let n = a_cli
+    .execute(
+      "WITH src AS ( \
+        SELECT g.id, g.invoice_value AS amount, \
+          fx_currency(g.currency_code) AS cur, \
+          CASE WHEN g.seg = 'Kz' THEN 'KZT' ELSE 'RUB' END AS nat, \
+          NULLIF(g.exchange_rate, 0) AS row_rate, \
+          g.stat_value_usd AS declared_usd, \
+          COALESCE(g.effective_date, g.gtd_registration_date, \
+            g.fill_date) AS d \
+        FROM general_schema g \
+        WHERE $1::INTEGER IS NULL \
+          OR g.import_run_id = $1 OR g.updated_run_id = $1 \
+      ), nat AS ( \
+        SELECT s.*, CASE \
+            WHEN s.cur = s.nat THEN s.amount \
+            WHEN s.row_rate IS NOT NULL THEN s.amount * s.row_rate \
+            ELSE s.amount * fx_rate(s.cur, s.d) / fx_rate(s.nat, s.d) \
+          END AS amount_nat \
+        FROM src s \
+      ), inv AS ( \
+        SELECT n.*, \
+          n.amount_nat * fx_rate(n.nat, n.d) AS rub, \
+          CASE WHEN n.cur = 'USD' THEN n.amount \
+            ELSE n.amount_nat * fx_rate(n.nat, n.d) / fx_rate('USD', n.d) \
+          END AS usd \
+        FROM nat n \
+      ), v AS ( \
+        SELECT i.id, \
+          CASE WHEN i.rub IS NOT NULL OR i.usd IS NOT NULL THEN i.rub \
+            ELSE i.declared_usd * fx_rate('USD', i.d) END AS rub, \
+          CASE WHEN i.rub IS NOT NULL OR i.usd IS NOT NULL THEN i.usd \
+            ELSE i.declared_usd END AS usd, \
+          CASE WHEN i.rub IS NOT NULL OR i.usd IS NOT NULL THEN \
+              CASE WHEN i.cur <> i.nat AND i.row_rate IS NOT NULL \
+                THEN $2 ELSE $3 END \
+            WHEN i.declared_usd IS NOT NULL THEN $4 \
+          END AS method \
+        FROM inv i \
+      ) \
+      UPDATE general_schema g SET value_rub = round(v.rub, 2), \
+        value_usd = round(v.usd, 2), fx_method = v.method \
+      FROM v WHERE g.id = v.id \
+        AND (g.value_rub, g.value_usd, g.fx_method) IS DISTINCT FROM \
+          (round(v.rub, 2), round(v.usd, 2), v.method)",
+      &[&a_run_id, &FX_ROW_RATE, &FX_TABLE, &FX_DECLARED_USD],
+    )
+    .await?;
+
+  Ok(n)
+}
@@ -1,45 +1,41 @@
 use chrono::NaiveDate;
 use csv::Writer;
+use deadpool_postgres::{
+  Manager, ManagerConfig, RecyclingMethod, Runtime,
+};
+use futures::{SinkExt, pin_mut};
+use native_tls::{Certificate, TlsConnector};
+use postgres_native_tls::MakeTlsConnector;
 use rig::Embed;
+use rust_decimal::Decimal;
+use std::collections::HashSet;
 use std::fs::File;
-use std::path::Path;
-use tokio_postgres::{Client, Error, NoTls, types::ToSql};
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio_postgres::config::SslMode;
+use tokio_postgres::{Client, Error, types::ToSql};
 
 use crate::{
   agents::{embed_item, embed_str},
-  conf::LlmConfig,
-  schemas::{GeneralSchema, SegmentT, TypeT},
+  conf::{LlmConfig, PostgresConfig, SslModeT},
+  dates::{DEFAULT_DATE_FORMATS, parse_date_as},
+  schemas::{GeneralSchema, SegmentT},
+  taxonomies::{
+    Provenance, SOURCE_LABEL_COLUMNS, TAXONOMIES, Taxonomy,
+    normalize_label, set_label,
+  },
+  text::{preprocess, row_description},
 };
 use serde::{Deserialize, Serialize};
 
-/// Parse a date string into a NaiveDate, with multiple format support
+pub use deadpool_postgres::Pool;
+
+/// Parse a date with the default formats, None when it matches none or
+/// is ambiguous (see `DEFAULT_DATE_FORMATS`)
 pub fn parse_date(date_str: &str) -> Option<NaiveDate>
 {
-  // Try common date formats, including US format (month/day/year)
-  let formats = [
-    "%Y-%m-%d", // 2024-05-01
-    "%m/%d/%Y", // 05/01/2024 - US format
-    "%Y/%m/%d", // 2024/05/01
-    "%d.%m.%Y", // 01.05.2024
-    "%d-%m-%Y", // 01-05-2024
-  ];
-
-  for format in &formats {
-    if let Ok(date) = NaiveDate::parse_from_str(date_str, format) {
-      return Some(date);
-    }
-  }
-
-  // If none of the formats work, try parsing as a timestamp
-  if let Ok(timestamp) = date_str.parse::<i64>() {
-    if let Some(date) = chrono::DateTime::from_timestamp(timestamp, 0)
-    {
-      return Some(date.naive_local().date());
-    }
-  }
-
-  // If all attempts fail, return None
-  None
+  parse_date_as(date_str.trim(), DEFAULT_DATE_FORMATS).ok()
 }
 
 #[derive(Debug)]
@@ -47,30 +43,45 @@ pub struct ToEval
 {
   pub id: String,
   pub descr: String,
-  pub eval: TypeT,
+  /// Label of the taxonomy the row is classified by
+  pub eval: String,
+  /// Prepared description alone, for the relevance rules
+  pub product: String,
+  pub tnved: Option<String>,
 }
 
 impl ToEval
 {
-  pub fn new(a_id: String, a_descr: String) -> Self
+  pub fn new(a_id: String, a_descr: String, a_eval: String) -> Self
   {
     return Self {
       id: a_id,
       descr: a_descr,
-      eval: TypeT::NoEval,
+      eval: a_eval,
+      product: String::new(),
+      tnved: None,
     };
   }
 }
 
+/// Rows waiting for a label of the taxonomy, except locked ones
 pub async fn get_rows_for_eval(
   a_cli: &Client,
+  a_taxonomy: &Taxonomy,
 ) -> Result<Vec<ToEval>, Box<dyn std::error::Error>>
 {
   let res = a_cli
     .query(
-      "SELECT id, sender_name, product_description_1, product_description_2 \
-    FROM general_schema WHERE eval='NoEval'",
-      &[],
+      format!(
+        "SELECT id, sender_name, product_description_1, \
+        product_description_2, description_norm, tnved_norm \
+        FROM general_schema \
+        WHERE ({col} IS NULL OR {col} = $1) AND NOT coalesce( \
+          (label_meta -> $2::TEXT ->> 'locked')::BOOLEAN, false)",
+        col = a_taxonomy.column
+      )
+      .as_str(),
+      &[&a_taxonomy.pending, &a_taxonomy.name],
     )
     .await?;
 
@@ -81,177 +92,24 @@ pub async fn get_rows_for_eval(
     let sender: String = s.get(1);
     let s1: String = s.get(2);
     let s2: Option<String> = s.get(3);
-    let s3 = format!(
-      "Sender: {} Product: {} {}",
-      sender,
-      s1,
-      s2.unwrap_or("".to_string())
-    );
+    // Rows loaded before descriptions were prepared get it done here
+    let norm: Option<String> = s.get(4);
+    let product = norm.unwrap_or_else(|| {
+      let raw = row_description(Some(&s1), s2.as_deref());
+      preprocess(&raw.unwrap_or_default()).text
+    });
+    let s3 = format!("Sender: {} Product: {}", sender, product);
 
-    out.push(ToEval::new(id.to_string(), s3));
+    let mut itm =
+      ToEval::new(id.to_string(), s3, a_taxonomy.pending.clone());
+    itm.product = product;
+    itm.tnved = s.get(5);
+    out.push(itm);
   }
 
   return Ok(out);
 }
 
-/// Initialize the GeneralSchema table in PostgreSQL
-pub async fn init_general_schema_table(
-  client: &Client,
-  purge: bool,
-) -> Result<(), Error>
-{
-  if purge {
-    client
-      .batch_execute("DROP TABLE IF EXISTS general_schema;")
-      .await?;
-  }
-
-  client
-    .batch_execute(
-      "
-      CREATE TABLE IF NOT EXISTS general_schema (
-                id SERIAL PRIMARY KEY,
-                seg TEXT NOT NULL,
-                declaration_number TEXT,
-                regime TEXT,
-                sheet_number TEXT,
-                position TEXT,
-                effective_date DATE,
-                fill_date DATE,
-                batch TEXT,
-                sender_inn TEXT,
-                sender_kpp TEXT,
-                sender_category TEXT,
-                sender_region TEXT,
-                sender_name TEXT,
-                sender_address TEXT,
-                receiver_inn TEXT,
-                receiver_kpp TEXT,
-                receiver_category TEXT,
-                receiver_region TEXT,
-                receiver_name TEXT,
-                receiver_address TEXT,
-                contractor_inn TEXT,
-                contractor_kpp TEXT,
-                contractor_category TEXT,
-                contractor_region TEXT,
-                contractor_name TEXT,
-                contractor_address TEXT,
-                regime_code TEXT,
-                customs_regime TEXT,
-                border_customs_code TEXT,
-                border_customs_name TEXT,
-                border_customs_city TEXT,
-                border_customs_address TEXT,
-                internal_customs_code TEXT,
-                internal_customs_name TEXT,
-                internal_customs_address TEXT,
-                delivery_terms_code TEXT,
-                delivery_terms_alpha_code TEXT,
-                delivery_terms TEXT,
-                payment_form_code TEXT,
-                payment_form TEXT,
-                stat_accounting_sign_code TEXT,
-                stat_accounting_sign TEXT,
-                origin_country_code TEXT,
-                origin_country TEXT,
-                destination_country_code TEXT,
-                destination_country TEXT,
-                trade_country_code TEXT,
-                trade_country TEXT,
-                origin_country_code_2 TEXT,
-                origin_country_2 TEXT,
-                tnved_code TEXT,
-                tnved_description TEXT,
-                transport_border_code TEXT,
-                transport_border TEXT,
-                transport_internal_code TEXT,
-                transport_internal TEXT,
-                additional_unit_code_1 TEXT,
-                additional_unit_1 TEXT,
-                additional_unit_code_2 TEXT,
-                additional_unit_2 TEXT,
-                cost_usd TEXT,
-                product_description_1 TEXT,
-                product_description_2 TEXT,
-                manufacturer TEXT,
-                brand TEXT,
-                brand_more TEXT,
-                net_weight TEXT,
-                gross_weight TEXT,
-                quantity_physical TEXT,
-                quantity_additional TEXT,
-                cargo_type TEXT,
-                number_of_packages TEXT,
-                delivery_point TEXT,
-                container_sign TEXT,
-                preferences TEXT,
-                customs_value TEXT,
-                invoice_value TEXT,
-                stat_value TEXT,
-                stat_value_usd TEXT,
-                total_invoice_value TEXT,
-                customs_correct_sign TEXT,
-                customs_correct_desc TEXT,
-                currency_code TEXT,
-                currency_description TEXT,
-                exchange_rate TEXT,
-                gtd_registration_place TEXT,
-                gtd_registration_date DATE,
-                loading_place TEXT,
-                processing_code TEXT,
-                processing_description TEXT,
-                declaration_type TEXT,
-                price_per_kg TEXT,
-                price_per_unit TEXT,
-                price_per_additional_unit TEXT,
-                info_type TEXT,
-                decision_code_ts TEXT,
-                recall_decision_code TEXT,
-                customs_value_ts TEXT,
-                previous_customs_value TEXT,
-                total_customs_payments TEXT,
-                customs_region TEXT,
-                customs_procedure TEXT,
-                additional_bdecl1 TEXT,
-                customs_op TEXT,
-                incoterm TEXT,
-                category TEXT,
-                eval TEXT,
-                eval_sec TEXT,
-                created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
-            );",
-    )
-    .await?;
-
-  Ok(())
-}
-
-pub async fn init_vector_storage_data(
-  a_cli: &Client,
-  purge: bool,
-) -> Result<(), Error>
-{
-  if purge {
-    a_cli
-      .batch_execute("DROP TABLE IF EXISTS poly_docs;")
-      .await?;
-  }
-  a_cli.batch_execute("\
-    CREATE EXTENSION IF NOT EXISTS vector; \
-    CREATE TABLE IF NOT EXISTS poly_docs (\
-      id uuid DEFAULT gen_random_uuid(), -- we can have repeated entries
-      document TEXT NOT NULL,
-      embedding vector(1024)
-    ); \
-    CREATE INDEX IF NOT EXISTS document_embeddings_idx ON poly_docs 
-    USING hnsw(embedding vector_cosine_ops);
-    
-    ").await?;
-
-  Ok(())
-}
-
 #[derive(
   Serialize, Deserialize, Clone, Debug, Eq, PartialEq, Default,
 )]
@@ -261,9 +119,11 @@ pub struct VecItem
   pub eval: String,
 }
 
+/// Labelled examples of the taxonomy closest to the query
 pub async fn find_vec_store(
   a_cli: &Client,
   a_query: &String,
+  a_taxonomy: &Taxonomy,
   a_llm_conf: &LlmConfig,
   a_top_n: usize,
 ) -> Result<Vec<String>, Box<dyn std::error::Error>>
@@ -271,48 +131,57 @@ pub async fn find_vec_store(
   let emb = embed_str(a_query, a_llm_conf).await?;
 
   let res = a_cli.query(format!(
-    "SELECT document FROM poly_docs ORDER BY embedding <=> '{:?}' LIMIT {}",
+    "SELECT document FROM poly_docs WHERE taxonomy = $1 ORDER BY embedding <=> '{:?}' LIMIT {}",
     emb.vec, a_top_n
-  ).as_str(), &[]).await?;
+  ).as_str(), &[&a_taxonomy.name]).await?;
 
   let out: Vec<String> = res.into_iter().map(|x| x.get(0)).collect();
 
   return Ok(out);
 }
 
+/// Embed a row labelled by the taxonomy into poly_docs. Returns false
+/// when the row has no such label or no description to embed.
 pub async fn insert_vec_store(
   a_cli: &Client,
   a_schema: &GeneralSchema,
+  a_taxonomy: &Taxonomy,
   a_llm_conf: &LlmConfig,
-) -> Result<(), Box<dyn std::error::Error>>
+  a_run_id: Option<i32>,
+) -> Result<bool, Box<dyn std::error::Error>>
 {
+  let label = match a_taxonomy.label_of(a_schema) {
+    Some(x) => x,
+    None => return Ok(false),
+  };
   if a_schema.product_description_1.clone().is_none() {
-    return Ok(());
+    return Ok(false);
   }
-  let item = VecItem {
-    description: format!(
-      "{} {}",
-      a_schema.product_description_1.clone().unwrap(),
-      a_schema
-        .product_description_2
-        .clone()
-        .unwrap_or("".to_string())
-    ),
-    eval: format!("{:?}", a_schema.eval.clone().unwrap()),
+  // Embedded as prepared for prompting, so that searches compare alike
+  let description = match &a_schema.description_norm {
+    Some(x) => x.clone(),
+    None => {
+      let raw = row_description(
+        a_schema.product_description_1.as_deref(),
+        a_schema.product_description_2.as_deref(),
+      );
+      preprocess(&raw.unwrap_or_default()).text
+    }
   };
+  let item = VecItem { description, eval: label };
 
   let doc = embed_item(&item, a_llm_conf).await?;
   a_cli
     .execute(
       format!(
-        "INSERT INTO poly_docs (document, embedding) VALUES ('{}', '{:?}')",
+        "INSERT INTO poly_docs (document, embedding, import_run_id, taxonomy) VALUES ('{}', '{:?}', $1, $2)",
         doc.document, doc.vec
       ).as_str(),
-      &[],
+      &[&a_run_id, &a_taxonomy.name],
     )
     .await?;
 
-  Ok(())
+  Ok(true)
 }
 /// Insert a GeneralSchema object into the PostgreSQL table
 pub async fn insert_general_schema(
@@ -322,25 +191,9 @@ pub async fn insert_general_schema(
 {
   // Validate and convert the eval field before insertion
   let validated_eval =
-    TypeT::from_str(&schema.eval.clone().unwrap_or("".to_string()));
-  let validated_eval_sec = TypeT::from_str(
-    &schema.eval_sec.clone().unwrap_or("".to_string()),
-  );
-
-  let effective_date = match &schema.effective_date {
-    Some(date_str) if !date_str.is_empty() => parse_date(date_str),
-    _ => None,
-  };
-
-  let fill_date = match &schema.fill_date {
-    Some(date_str) if !date_str.is_empty() => parse_date(date_str),
-    _ => None,
-  };
-
-  let gtd_registration_date = match &schema.gtd_registration_date {
-    Some(date_str) if !date_str.is_empty() => parse_date(date_str),
-    _ => None,
-  };
+    normalize_label("eval", schema.eval.as_deref());
+  let validated_eval_sec =
+    normalize_label("eval_sec", schema.eval_sec.as_deref());
 
   let (seg_str, seg_value) = match schema.seg {
     SegmentT::Kz => ("Kz", 1),
@@ -379,7 +232,14 @@ pub async fn insert_general_schema(
                 decision_code_ts, recall_decision_code, customs_value_ts, 
                 previous_customs_value, total_customs_payments, customs_region, 
                 customs_procedure, additional_bdecl1, customs_op, incoterm, 
-                category, eval, eval_sec
+                category, eval, eval_sec, natural_key, unit_norm,
+                quantity_norm, weight_kg, origin_country_iso,
+                origin_country_name, destination_country_iso,
+                destination_country_name, trade_country_iso,
+                trade_country_name, origin_country_2_iso,
+                origin_country_2_name, tnved_norm, movement_period,
+                description_norm, description_lang, length_mm, width_mm,
+                thickness_mm, density_kg_m3
             ) VALUES (
                 $1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, 
                 $16, $17, $18, $19, $20, $21, $22, $23, $24, $25, $26, $27, $28, 
@@ -389,7 +249,9 @@ pub async fn insert_general_schema(
                 $68, $69, $70, $71, $72, $73, $74, $75, $76, $77, $78, $79, $80, 
                 $81, $82, $83, $84, $85, $86, $87, $88, $89, $90, $91, $92, $93, 
                 $94, $95, $96, $97, $98, $99, $100, $101, $102, $103, $104, $105, 
-                $106, $107, $108
+                $106, $107, $108, $109, $110, $111, $112, $113, $114,
+                $115, $116, $117, $118, $119, $120, $121, $122, $123,
+                $124, $125, $126, $127, $128
             ) RETURNING id",
             &[
                 &seg_str,
@@ -397,8 +259,8 @@ pub async fn insert_general_schema(
                 &schema.regime,
                 &schema.sheet_number,
                 &schema.position,
-                &effective_date,
-                &fill_date,
+                &schema.effective_date,
+                &schema.fill_date,
                 &schema.batch,
                 &schema.sender_inn,
                 &schema.sender_kpp,
@@ -478,7 +340,7 @@ pub async fn insert_general_schema(
                 &schema.currency_description,
                 &schema.exchange_rate,
                 &schema.gtd_registration_place,
-                &gtd_registration_date,
+                &schema.gtd_registration_date,
                 &schema.loading_place,
                 &schema.processing_code,
                 &schema.processing_description,
@@ -498,8 +360,28 @@ pub async fn insert_general_schema(
                 &schema.customs_op,
                 &schema.incoterm,
                 &schema.category,
-                &format!("{:?}", validated_eval),
-                &format!("{:?}", validated_eval_sec),
+                &validated_eval,
+                &validated_eval_sec,
+                &schema.natural_key(),
+                &schema.unit_norm,
+                &schema.quantity_norm,
+                &schema.weight_kg,
+                &schema.origin_country_iso,
+                &schema.origin_country_name,
+                &schema.destination_country_iso,
+                &schema.destination_country_name,
+                &schema.trade_country_iso,
+                &schema.trade_country_name,
+                &schema.origin_country_2_iso,
+                &schema.origin_country_2_name,
+                &schema.tnved_norm,
+                &schema.movement_period,
+                &schema.description_norm,
+                &schema.description_lang,
+                &schema.length_mm,
+                &schema.width_mm,
+                &schema.thickness_mm,
+                &schema.density_kg_m3,
             ],
         )
         .await?;
@@ -508,6 +390,559 @@ pub async fn insert_general_schema(
   Ok(rows.len() as u64)
 }
 
+/// Columns written by the importers, in the order used by
+/// `general_schema_record`
+pub const GENERAL_SCHEMA_COLUMNS: &[&str] = &[
+  "seg",
+  "declaration_number",
+  "regime",
+  "sheet_number",
+  "position",
+  "effective_date",
+  "fill_date",
+  "movement_period",
+  "batch",
+  "sender_inn",
+  "sender_kpp",
+  "sender_category",
+  "sender_region",
+  "sender_name",
+  "sender_address",
+  "receiver_inn",
+  "receiver_kpp",
+  "receiver_category",
+  "receiver_region",
+  "receiver_name",
+  "receiver_address",
+  "contractor_inn",
+  "contractor_kpp",
+  "contractor_category",
+  "contractor_region",
+  "contractor_name",
+  "contractor_address",
+  "regime_code",
+  "customs_regime",
+  "border_customs_code",
+  "border_customs_name",
+  "border_customs_city",
+  "border_customs_address",
+  "internal_customs_code",
+  "internal_customs_name",
+  "internal_customs_address",
+  "delivery_terms_code",
+  "delivery_terms_alpha_code",
+  "delivery_terms",
+  "payment_form_code",
+  "payment_form",
+  "stat_accounting_sign_code",
+  "stat_accounting_sign",
+  "origin_country_code",
+  "origin_country",
+  "destination_country_code",
+  "destination_country",
+  "trade_country_code",
+  "trade_country",
+  "origin_country_code_2",
+  "origin_country_2",
+  "origin_country_iso",
+  "origin_country_name",
+  "destination_country_iso",
+  "destination_country_name",
+  "trade_country_iso",
+  "trade_country_name",
+  "origin_country_2_iso",
+  "origin_country_2_name",
+  "tnved_code",
+  "tnved_description",
+  "tnved_norm",
+  "transport_border_code",
+  "transport_border",
+  "transport_internal_code",
+  "transport_internal",
+  "additional_unit_code_1",
+  "additional_unit_1",
+  "additional_unit_code_2",
+  "additional_unit_2",
+  "cost_usd",
+  "product_description_1",
+  "product_description_2",
+  "description_norm",
+  "description_lang",
+  "length_mm",
+  "width_mm",
+  "thickness_mm",
+  "density_kg_m3",
+  "manufacturer",
+  "brand",
+  "brand_more",
+  "net_weight",
+  "gross_weight",
+  "quantity_physical",
+  "quantity_additional",
+  "unit_norm",
+  "quantity_norm",
+  "weight_kg",
+  "cargo_type",
+  "number_of_packages",
+  "delivery_point",
+  "container_sign",
+  "preferences",
+  "customs_value",
+  "invoice_value",
+  "stat_value",
+  "stat_value_usd",
+  "total_invoice_value",
+  "customs_correct_sign",
+  "customs_correct_desc",
+  "currency_code",
+  "currency_description",
+  "exchange_rate",
+  "gtd_registration_place",
+  "gtd_registration_date",
+  "loading_place",
+  "processing_code",
+  "processing_description",
+  "declaration_type",
+  "price_per_kg",
+  "price_per_unit",
+  "price_per_additional_unit",
+  "info_type",
+  "decision_code_ts",
+  "recall_decision_code",
+  "customs_value_ts",
+  "previous_customs_value",
+  "total_customs_payments",
+  "customs_region",
+  "customs_procedure",
+  "additional_bdecl1",
+  "customs_op",
+  "incoterm",
+  "category",
+  "eval",
+  "eval_sec",
+  "natural_key",
+];
+
+/// Columns derived in the database after each import run (volumes,
+/// currency values, companies), restored on rollback but not compared
+/// on import
+pub const DERIVED_COLUMNS: &[&str] = &[
+  "volume_m3",
+  "volume_estimated",
+  "value_rub",
+  "value_usd",
+  "fx_method",
+  "sender_company_id",
+  "receiver_company_id",
+  "contractor_company_id",
+];
+
+/// Text values of a GeneralSchema row in `GENERAL_SCHEMA_COLUMNS` order,
+/// with labels normalized and dates converted to ISO format
+pub fn general_schema_record(
+  schema: &GeneralSchema,
+) -> Vec<Option<String>>
+{
+  let validated_eval =
+    normalize_label("eval", schema.eval.as_deref());
+  let validated_eval_sec =
+    normalize_label("eval_sec", schema.eval_sec.as_deref());
+
+  let seg_str = format!("{:?}", schema.seg);
+
+  vec![
+    Some(seg_str.to_string()),
+    schema.declaration_number.clone(),
+    schema.regime.clone(),
+    schema.sheet_number.clone(),
+    schema.position.clone(),
+    schema
+      .effective_date
+      .map(|x| x.format("%Y-%m-%d").to_string()),
+    schema.fill_date.map(|x| x.format("%Y-%m-%d").to_string()),
+    schema
+      .movement_period
+      .map(|x| x.format("%Y-%m-%d").to_string()),
+    schema.batch.clone(),
+    schema.sender_inn.clone(),
+    schema.sender_kpp.clone(),
+    schema.sender_category.clone(),
+    schema.sender_region.clone(),
+    schema.sender_name.clone(),
+    schema.sender_address.clone(),
+    schema.receiver_inn.clone(),
+    schema.receiver_kpp.clone(),
+    schema.receiver_category.clone(),
+    schema.receiver_region.clone(),
+    schema.receiver_name.clone(),
+    schema.receiver_address.clone(),
+    schema.contractor_inn.clone(),
+    schema.contractor_kpp.clone(),
+    schema.contractor_category.clone(),
+    schema.contractor_region.clone(),
+    schema.contractor_name.clone(),
+    schema.contractor_address.clone(),
+    schema.regime_code.clone(),
+    schema.customs_regime.clone(),
+    schema.border_customs_code.clone(),
+    schema.border_customs_name.clone(),
+    schema.border_customs_city.clone(),
+    schema.border_customs_address.clone(),
+    schema.internal_customs_code.clone(),
+    schema.internal_customs_name.clone(),
+    schema.internal_customs_address.clone(),
+    schema.delivery_terms_code.clone(),
+    schema.delivery_terms_alpha_code.clone(),
+    schema.delivery_terms.clone(),
+    schema.payment_form_code.clone(),
+    schema.payment_form.clone(),
+    schema.stat_accounting_sign_code.clone(),
+    schema.stat_accounting_sign.clone(),
+    schema.origin_country_code.clone(),
+    schema.origin_country.clone(),
+    schema.destination_country_code.clone(),
+    schema.destination_country.clone(),
+    schema.trade_country_code.clone(),
+    schema.trade_country.clone(),
+    schema.origin_country_code_2.clone(),
+    schema.origin_country_2.clone(),
+    schema.origin_country_iso.clone(),
+    schema.origin_country_name.clone(),
+    schema.destination_country_iso.clone(),
+    schema.destination_country_name.clone(),
+    schema.trade_country_iso.clone(),
+    schema.trade_country_name.clone(),
+    schema.origin_country_2_iso.clone(),
+    schema.origin_country_2_name.clone(),
+    schema.tnved_code.clone(),
+    schema.tnved_description.clone(),
+    schema.tnved_norm.clone(),
+    schema.transport_border_code.clone(),
+    schema.transport_border.clone(),
+    schema.transport_internal_code.clone(),
+    schema.transport_internal.clone(),
+    schema.additional_unit_code_1.clone(),
+    schema.additional_unit_1.clone(),
+    schema.additional_unit_code_2.clone(),
+    schema.additional_unit_2.clone(),
+    schema.cost_usd.map(|x| x.to_string()),
+    schema.product_description_1.clone(),
+    schema.product_description_2.clone(),
+    schema.description_norm.clone(),
+    schema.description_lang.clone(),
+    schema.length_mm.map(|x| x.to_string()),
+    schema.width_mm.map(|x| x.to_string()),
+    schema.thickness_mm.map(|x| x.to_string()),
+    schema.density_kg_m3.map(|x| x.to_string()),
+    schema.manufacturer.clone(),
+    schema.brand.clone(),
+    schema.brand_more.clone(),
+    schema.net_weight.map(|x| x.to_string()),
+    schema.gross_weight.map(|x| x.to_string()),
+    schema.quantity_physical.map(|x| x.to_string()),
+    schema.quantity_additional.map(|x| x.to_string()),
+    schema.unit_norm.clone(),
+    schema.quantity_norm.map(|x| x.to_string()),
+    schema.weight_kg.map(|x| x.to_string()),
+    schema.cargo_type.clone(),
+    schema.number_of_packages.map(|x| x.to_string()),
+    schema.delivery_point.clone(),
+    schema.container_sign.clone(),
+    schema.preferences.clone(),
+    schema.customs_value.map(|x| x.to_string()),
+    schema.invoice_value.map(|x| x.to_string()),
+    schema.stat_value.map(|x| x.to_string()),
+    schema.stat_value_usd.map(|x| x.to_string()),
+    schema.total_invoice_value.map(|x| x.to_string()),
+    schema.customs_correct_sign.clone(),
+    schema.customs_correct_desc.clone(),
+    schema.currency_code.clone(),
+    schema.currency_description.clone(),
+    schema.exchange_rate.map(|x| x.to_string()),
+    schema.gtd_registration_place.clone(),
+    schema
+      .gtd_registration_date
+      .map(|x| x.format("%Y-%m-%d").to_string()),
+    schema.loading_place.clone(),
+    schema.processing_code.clone(),
+    schema.processing_description.clone(),
+    schema.declaration_type.clone(),
+    schema.price_per_kg.map(|x| x.to_string()),
+    schema.price_per_unit.map(|x| x.to_string()),
+    schema.price_per_additional_unit.map(|x| x.to_string()),
+    schema.info_type.clone(),
+    schema.decision_code_ts.clone(),
+    schema.recall_decision_code.clone(),
+    schema.customs_value_ts.map(|x| x.to_string()),
+    schema.previous_customs_value.map(|x| x.to_string()),
+    schema.total_customs_payments.map(|x| x.to_string()),
+    schema.customs_region.clone(),
+    schema.customs_procedure.clone(),
+    schema.additional_bdecl1.clone(),
+    schema.customs_op.clone(),
+    schema.incoterm.clone(),
+    schema.category.clone(),
+    Some(validated_eval),
+    Some(validated_eval_sec),
+    schema.natural_key(),
+  ]
+}
+
+/// Marker for NULL in the COPY stream, so empty strings stay empty
+const COPY_NULL: &str = "\\N";
+
+/// Rows sent to the server per COPY chunk
+const COPY_CHUNK: usize = 10000;
+
+/// Columns never overwritten by a re-import: the conflict target and
+/// the classification labels, which are merged separately
+const UPSERT_KEEP_COLUMNS: &[&str] =
+  &["seg", "natural_key", "eval", "eval_sec"];
+
+/// Outcome of a bulk load
+#[derive(Debug, Default)]
+pub struct CopyStats
+{
+  pub staged: u64,
+  pub inserted: u64,
+  pub updated: u64,
+  /// Natural keys of the newly inserted rows
+  pub inserted_keys: HashSet<String>,
+}
+
+/// Bulk upsert rows into general_schema in a single transaction.
+///
+/// Rows are streamed with `COPY ... FROM STDIN` into a temporary staging
+/// table, validated there and merged into general_schema at once, keyed
+/// by (seg, natural_key). Existing rows are only updated when a value
+/// changed, and their classification labels are kept unless they are
+/// still pending. Rows are tagged with `a_run_id`, and the previous
+/// version of updated rows is kept in import_run_backup. On any error
+/// the transaction is rolled back and the table is left untouched.
+pub async fn copy_general_schema(
+  a_cli: &mut Client,
+  a_rows: &[GeneralSchema],
+  a_run_id: i32,
+) -> Result<CopyStats, Box<dyn std::error::Error>>
+{
+  let cols = GENERAL_SCHEMA_COLUMNS.join(", ");
+  let tr = a_cli.transaction().await?;
+
+  tr.batch_execute(
+    format!(
+      "CREATE TEMP TABLE general_schema_staging ON COMMIT DROP AS \
+      SELECT {} FROM general_schema WITH NO DATA;",
+      cols
+    )
+    .as_str(),
+  )
+  .await?;
+
+  let sink = tr
+    .copy_in(
+      format!(
+        "COPY general_schema_staging ({}) FROM STDIN \
+        (FORMAT csv, NULL '{}')",
+        cols, COPY_NULL
+      )
+      .as_str(),
+    )
+    .await?;
+  pin_mut!(sink);
+
+  for chunk in a_rows.chunks(COPY_CHUNK) {
+    let mut wtr = Writer::from_writer(Vec::new());
+    for row in chunk {
+      let rec: Vec<String> = general_schema_record(row)
+        .into_iter()
+        .map(|x| x.unwrap_or(COPY_NULL.to_string()))
+        .collect();
+      wtr.write_record(&rec)?;
+    }
+    let buf = wtr.into_inner()?;
+    sink.send(Cursor::new(buf)).await?;
+  }
+
+  let mut stats = CopyStats {
+    staged: sink.as_mut().finish().await?,
+    ..Default::default()
+  };
+
+  //------------------------------------//
+  // Validate staged rows               //
+  //------------------------------------//
+  let bad = tr
+    .query_one(
+      "SELECT COUNT(*) FROM general_schema_staging \
+      WHERE seg NOT IN ('Kz', 'Rus', 'Eas') OR effective_date IS NULL",
+      &[],
+    )
+    .await?;
+  let bad: i64 = bad.get(0);
+  if bad > 0 {
+    return Err(
+      format!(
+        "{} of {} staged rows have no valid segment or effective_date",
+        bad, stats.staged
+      )
+      .into(),
+    );
+  }
+
+  //------------------------------------//
+  // Merge into general_schema          //
+  //------------------------------------//
+  let upd_cols: Vec<&str> = GENERAL_SCHEMA_COLUMNS
+    .iter()
+    .filter(|x| !UPSERT_KEEP_COLUMNS.contains(x))
+    .copied()
+    .collect();
+  let set: Vec<String> = upd_cols
+    .iter()
+    .map(|x| format!("{} = EXCLUDED.{}", x, x))
+    .collect();
+  let old: Vec<String> =
+    upd_cols.iter().map(|x| format!("g.{}", x)).collect();
+  let new: Vec<String> =
+    upd_cols.iter().map(|x| format!("EXCLUDED.{}", x)).collect();
+
+  // Keep the current version of matched rows so the run can be
+  // rolled back. A row met again in a later batch keeps its first
+  // backup.
+  tr.execute(
+    "INSERT INTO import_run_backup (run_id, general_schema_id, row) \
+    SELECT $1, g.id, to_jsonb(g) FROM general_schema g \
+    WHERE (g.seg, g.natural_key) IN ( \
+      SELECT seg, natural_key FROM general_schema_staging) \
+    ON CONFLICT (run_id, general_schema_id) DO NOTHING",
+    &[&a_run_id],
+  )
+  .await?;
+
+  // Labels read from the file carry its path as provenance, by
+  // taxonomy; a row met again only takes those replacing the pending
+  // label of the taxonomy
+  let labelled: Vec<&Taxonomy> = TAXONOMIES
+    .iter()
+    .filter(|x| SOURCE_LABEL_COLUMNS.contains(&x.column.as_str()))
+    .collect();
+  let pending_of = |a_col: &str| {
+    let p = labelled
+      .iter()
+      .find(|x| x.column == a_col)
+      .map(|x| x.pending.as_str())
+      .unwrap_or("NoEval");
+    format!("'{}'", p.replace('\'', "''"))
+  };
+  let label_set: Vec<String> = SOURCE_LABEL_COLUMNS
+    .iter()
+    .map(|x| {
+      format!(
+        "{col} = CASE WHEN g.{col} IS NULL OR g.{col} = {pending} \
+          THEN EXCLUDED.{col} ELSE g.{col} END",
+        col = x,
+        pending = pending_of(x)
+      )
+    })
+    .collect();
+  let label_changed: Vec<String> = SOURCE_LABEL_COLUMNS
+    .iter()
+    .map(|x| {
+      format!(
+        "((g.{col} IS NULL OR g.{col} = {pending}) \
+          AND g.{col} IS DISTINCT FROM EXCLUDED.{col})",
+        col = x,
+        pending = pending_of(x)
+      )
+    })
+    .collect();
+  let file_meta: Vec<String> = labelled
+    .iter()
+    .map(|x| {
+      format!(
+        "'{name}', CASE WHEN s.{col} <> {pending} THEN \
+          jsonb_build_object('source', 'file', 'ref', ( \
+            SELECT file_path FROM import_runs WHERE id = $1), \
+          'run', $1, 'at', now()) END",
+        name = x.name,
+        col = x.column,
+        pending = pending_of(&x.column)
+      )
+    })
+    .collect();
+  let keep_meta: Vec<String> = labelled
+    .iter()
+    .map(|x| {
+      format!(
+        "'{name}', CASE WHEN (g.{col} IS NULL OR g.{col} = {pending}) \
+          AND g.{col} IS DISTINCT FROM EXCLUDED.{col} \
+          THEN EXCLUDED.label_meta -> '{name}' END",
+        name = x.name,
+        col = x.column,
+        pending = pending_of(&x.column)
+      )
+    })
+    .collect();
+
+  // Inside one file the last occurrence of a key wins
+  let res = tr
+    .query(
+      format!(
+        "INSERT INTO general_schema AS g ({cols}, import_run_id, label_meta) \
+        SELECT {cols}, $1, \
+          jsonb_strip_nulls(jsonb_build_object({file_meta})) FROM ( \
+          SELECT *, ROW_NUMBER() OVER ( \
+            PARTITION BY seg, natural_key ORDER BY ctid DESC \
+          ) AS rn FROM general_schema_staging \
+        ) s WHERE s.natural_key IS NULL OR s.rn = 1 \
+        ON CONFLICT (seg, natural_key) DO UPDATE SET {set}, \
+          {label_set}, \
+          label_meta = g.label_meta \
+            || jsonb_strip_nulls(jsonb_build_object({keep_meta})), \
+          updated_run_id = $1 \
+        WHERE ({old}) IS DISTINCT FROM ({new}) \
+          OR {label_changed} \
+        RETURNING g.natural_key, (g.xmax = 0) AS inserted",
+        cols = cols,
+        set = set.join(", "),
+        old = old.join(", "),
+        new = new.join(", "),
+        file_meta = file_meta.join(", "),
+        keep_meta = keep_meta.join(", "),
+        label_set = label_set.join(", "),
+        label_changed = label_changed.join(" OR "),
+      )
+      .as_str(),
+      &[&a_run_id],
+    )
+    .await?;
+
+  tr.execute(
+    "DELETE FROM import_run_backup b WHERE b.run_id = $1 \
+    AND NOT EXISTS (SELECT 1 FROM general_schema g \
+      WHERE g.id = b.general_schema_id AND g.updated_run_id = $1)",
+    &[&a_run_id],
+  )
+  .await?;
+
+  tr.commit().await?;
+
+  for r in res {
+    let key: Option<String> = r.get(0);
+    let inserted: bool = r.get(1);
+    if inserted {
+      stats.inserted += 1;
+      if let Some(k) = key {
+        stats.inserted_keys.insert(k);
+      }
+    } else {
+      stats.updated += 1;
+    }
+  }
+
+  Ok(stats)
+}
+
 /// Export all GeneralSchema records from the database to a CSV file
 pub async fn export_general_schema_to_csv<P: AsRef<Path>>(
   client: &Client,
@@ -704,31 +1139,31 @@ pub async fn export_general_schema_to_csv<P: AsRef<Path>>(
       option_to_string(row.get::<_, Option<String>>(58)), // additional_unit_1
       option_to_string(row.get::<_, Option<String>>(59)), // additional_unit_code_2
       option_to_string(row.get::<_, Option<String>>(60)), // additional_unit_2
-      option_to_string(row.get::<_, Option<String>>(61)), // cost_usd
+      option_decimal_to_string(row.get::<_, Option<Decimal>>(61)), // cost_usd
       option_to_string(row.get::<_, Option<String>>(62)), // product_description_1
       option_to_string(row.get::<_, Option<String>>(63)), // product_description_2
       option_to_string(row.get::<_, Option<String>>(64)), // manufacturer
       option_to_string(row.get::<_, Option<String>>(65)), // brand
       option_to_string(row.get::<_, Option<String>>(66)), // brand_more
-      option_to_string(row.get::<_, Option<String>>(67)), // net_weight
-      option_to_string(row.get::<_, Option<String>>(68)), // gross_weight
-      option_to_string(row.get::<_, Option<String>>(69)), // quantity_physical
-      option_to_string(row.get::<_, Option<String>>(70)), // quantity_additional
+      option_decimal_to_string(row.get::<_, Option<Decimal>>(67)), // net_weight
+      option_decimal_to_string(row.get::<_, Option<Decimal>>(68)), // gross_weight
+      option_decimal_to_string(row.get::<_, Option<Decimal>>(69)), // quantity_physical
+      option_decimal_to_string(row.get::<_, Option<Decimal>>(70)), // quantity_additional
       option_to_string(row.get::<_, Option<String>>(71)), // cargo_type
-      option_to_string(row.get::<_, Option<String>>(72)), // number_of_packages
+      option_int_to_string(row.get::<_, Option<i64>>(72)), // number_of_packages
       option_to_string(row.get::<_, Option<String>>(73)), // delivery_point
       option_to_string(row.get::<_, Option<String>>(74)), // container_sign
       option_to_string(row.get::<_, Option<String>>(75)), // preferences
-      option_to_string(row.get::<_, Option<String>>(76)), // customs_value
-      option_to_string(row.get::<_, Option<String>>(77)), // invoice_value
-      option_to_string(row.get::<_, Option<String>>(78)), // stat_value
-      option_to_string(row.get::<_, Option<String>>(79)), // stat_value_usd
-      option_to_string(row.get::<_, Option<String>>(80)), // total_invoice_value
+      option_decimal_to_string(row.get::<_, Option<Decimal>>(76)), // customs_value
+      option_decimal_to_string(row.get::<_, Option<Decimal>>(77)), // invoice_value
+      option_decimal_to_string(row.get::<_, Option<Decimal>>(78)), // stat_value
+      option_decimal_to_string(row.get::<_, Option<Decimal>>(79)), // stat_value_usd
+      option_decimal_to_string(row.get::<_, Option<Decimal>>(80)), // total_invoice_value
       option_to_string(row.get::<_, Option<String>>(81)), // customs_correct_sign
       option_to_string(row.get::<_, Option<String>>(82)), // customs_correct_desc
       option_to_string(row.get::<_, Option<String>>(83)), // currency_code
       option_to_string(row.get::<_, Option<String>>(84)), // currency_description
-      option_to_string(row.get::<_, Option<String>>(85)), // exchange_rate
+      option_decimal_to_string(row.get::<_, Option<Decimal>>(85)), // exchange_rate
       option_to_string(row.get::<_, Option<String>>(86)), // gtd_registration_place
       option_date_to_string(
         row.get::<_, Option<chrono::NaiveDate>>(87),
@@ -737,15 +1172,15 @@ pub async fn export_general_schema_to_csv<P: AsRef<Path>>(
       option_to_string(row.get::<_, Option<String>>(89)), // processing_code
       option_to_string(row.get::<_, Option<String>>(90)), // processing_description
       option_to_string(row.get::<_, Option<String>>(91)), // declaration_type
-      option_to_string(row.get::<_, Option<String>>(92)), // price_per_kg
-      option_to_string(row.get::<_, Option<String>>(93)), // price_per_unit
-      option_to_string(row.get::<_, Option<String>>(94)), // price_per_additional_unit
+      option_decimal_to_string(row.get::<_, Option<Decimal>>(92)), // price_per_kg
+      option_decimal_to_string(row.get::<_, Option<Decimal>>(93)), // price_per_unit
+      option_decimal_to_string(row.get::<_, Option<Decimal>>(94)), // price_per_additional_unit
       option_to_string(row.get::<_, Option<String>>(95)), // info_type
       option_to_string(row.get::<_, Option<String>>(96)), // decision_code_ts
       option_to_string(row.get::<_, Option<String>>(97)), // recall_decision_code
-      option_to_string(row.get::<_, Option<String>>(98)), // customs_value_ts
-      option_to_string(row.get::<_, Option<String>>(99)), // previous_customs_value
-      option_to_string(row.get::<_, Option<String>>(100)), // total_customs_payments
+      option_decimal_to_string(row.get::<_, Option<Decimal>>(98)), // customs_value_ts
+      option_decimal_to_string(row.get::<_, Option<Decimal>>(99)), // previous_customs_value
+      option_decimal_to_string(row.get::<_, Option<Decimal>>(100)), // total_customs_payments
       option_to_string(row.get::<_, Option<String>>(101)), // customs_region
       option_to_string(row.get::<_, Option<String>>(102)), // customs_procedure
       option_to_string(row.get::<_, Option<String>>(103)), // additional_bdecl1
@@ -776,6 +1211,24 @@ fn option_to_string(opt: Option<String>) -> String
   }
 }
 
+// Helper function to convert Option<Decimal> to String for CSV output
+fn option_decimal_to_string(opt: Option<Decimal>) -> String
+{
+  match opt {
+    Some(d) => d.to_string(),
+    None => String::new(),
+  }
+}
+
+// Helper function to convert Option<i64> to String for CSV output
+fn option_int_to_string(opt: Option<i64>) -> String
+{
+  match opt {
+    Some(n) => n.to_string(),
+    None => String::new(),
+  }
+}
+
 // Helper function to convert Option<NaiveDate> to String for CSV output
 fn option_date_to_string(opt: Option<chrono::NaiveDate>) -> String
 {
@@ -796,44 +1249,201 @@ fn option_datetime_to_string(
   }
 }
 
-/// Connect to the PostgreSQL database
-/// Update the eval status for a specific entry in the general_schema table
+/// Store the label of the taxonomy for a specific entry in the
+/// general_schema table. Returns false for locked labels, which are
+/// kept.
 pub async fn update_eval_status(
   a_cli: &Client,
+  a_taxonomy: &Taxonomy,
   to_eval: &ToEval,
-) -> Result<(), Box<dyn std::error::Error>>
+  a_provenance: &Provenance,
+) -> Result<bool, Box<dyn std::error::Error>>
 {
-  a_cli
-    .execute(
-      format!(
-        "UPDATE general_schema SET eval = '{:?}' WHERE id = {}",
-        to_eval.eval, to_eval.id
-      )
-      .as_str(),
-      &[],
-    )
-    .await?;
+  let id: i32 = to_eval.id.parse()?;
+  set_label(a_cli, a_taxonomy, id, &to_eval.eval, a_provenance).await
+}
 
-  Ok(())
+/// TLS settings of the connection and the URL left to tokio-postgres
+struct SslSettings
+{
+  url: Option<String>,
+  mode: SslModeT,
+  rootcert: Option<PathBuf>,
 }
 
-pub async fn connect_to_database(
-  host: &str,
-  user: &str,
-  password: &str,
-) -> Result<Client, Error>
+/// Decode the %XX escapes of a URL parameter
+fn percent_decode(a_value: &str) -> String
 {
-  let connection_string =
-    format!("host={} user={} password={}", host, user, password);
-  let (client, connection) =
-    tokio_postgres::connect(&connection_string, NoTls).await?;
-
-  // Spawn the connection handling in the background
-  tokio::spawn(async move {
-    if let Err(e) = connection.await {
-      eprintln!("Connection error: {}", e);
+  let bytes = a_value.as_bytes();
+  let mut out = Vec::with_capacity(bytes.len());
+  let mut i = 0;
+  while i < bytes.len() {
+    let hex = a_value
+      .get(i + 1..i + 3)
+      .filter(|_| bytes[i] == b'%')
+      .and_then(|x| u8::from_str_radix(x, 16).ok());
+    match hex {
+      Some(x) => {
+        out.push(x);
+        i += 3;
+      }
+      None => {
+        out.push(bytes[i]);
+        i += 1;
+      }
     }
+  }
+  String::from_utf8_lossy(&out).into_owned()
+}
+
+/// tokio-postgres reads sslmode only up to require and ignores
+/// sslrootcert, so both are taken out of the URL here. Like the rest of
+/// the URL they take precedence over the separate fields.
+fn ssl_settings(
+  a_conf: &PostgresConfig,
+) -> Result<SslSettings, Box<dyn std::error::Error>>
+{
+  let mut res = SslSettings {
+    url: a_conf.url.clone(),
+    mode: a_conf.sslmode,
+    rootcert: a_conf.sslrootcert.clone(),
+  };
+  let Some(url) = &a_conf.url else {
+    return Ok(res);
+  };
+
+  // postgresql://...?a=b&c=d or the key=value form
+  let is_url = url.contains("://");
+  let (base, params): (&str, Vec<&str>) = if is_url {
+    match url.split_once('?') {
+      Some((b, q)) => (b, q.split('&').collect()),
+      None => (url.as_str(), Vec::new()),
+    }
+  } else {
+    ("", url.split_whitespace().collect())
+  };
+
+  let mut kept = Vec::new();
+  for p in params {
+    let value = |x: &str| {
+      if is_url {
+        percent_decode(x)
+      } else {
+        x.trim_matches('\'').to_string()
+      }
+    };
+    match p.split_once('=') {
+      Some(("sslmode", v)) => {
+        res.mode = SslModeT::from_libpq(&value(v))
+          .ok_or_else(|| format!("Unknown sslmode '{}'", v))?;
+      }
+      Some(("sslrootcert", v)) => {
+        res.rootcert = Some(PathBuf::from(value(v)));
+      }
+      _ => kept.push(p),
+    }
+  }
+
+  res.url = Some(if !is_url {
+    kept.join(" ")
+  } else if kept.is_empty() {
+    base.to_string()
+  } else {
+    format!("{}?{}", base, kept.join("&"))
+  });
+
+  Ok(res)
+}
+
+/// Build the tokio-postgres config from the [postgres] section
+pub fn pg_config(
+  a_conf: &PostgresConfig,
+) -> Result<tokio_postgres::Config, Box<dyn std::error::Error>>
+{
+  let ssl = ssl_settings(a_conf)?;
+  let mut cfg = match &ssl.url {
+    Some(url) => url.parse::<tokio_postgres::Config>()?,
+    None => {
+      let mut c = tokio_postgres::Config::new();
+      c.host(&a_conf.host)
+        .port(a_conf.port)
+        .user(&a_conf.user)
+        .password(&a_conf.psswd);
+      if let Some(db) = &a_conf.dbname {
+        c.dbname(db);
+      }
+      c
+    }
+  };
+
+  // The verify modes need TLS; make_tls checks the certificate
+  cfg.ssl_mode(match ssl.mode {
+    SslModeT::Disable => SslMode::Disable,
+    SslModeT::Prefer => SslMode::Prefer,
+    _ => SslMode::Require,
   });
 
-  Ok(client)
+  if cfg.get_application_name().is_none() {
+    cfg.application_name(&a_conf.application_name);
+  }
+
+  Ok(cfg)
+}
+
+/// TLS connector following libpq rules: prefer/require only encrypt,
+/// verify-ca checks the chain, verify-full also checks the host name.
+/// A root certificate upgrades require to verify-ca like libpq does.
+fn make_tls(
+  a_conf: &PostgresConfig,
+) -> Result<MakeTlsConnector, Box<dyn std::error::Error>>
+{
+  let mut builder = TlsConnector::builder();
+
+  let ssl = ssl_settings(a_conf)?;
+  let mut mode = ssl.mode;
+  if ssl.rootcert.is_some() && mode == SslModeT::Require {
+    mode = SslModeT::VerifyCa;
+  }
+
+  match mode {
+    SslModeT::VerifyFull => {}
+    SslModeT::VerifyCa => {
+      builder.danger_accept_invalid_hostnames(true);
+    }
+    _ => {
+      builder.danger_accept_invalid_certs(true);
+    }
+  }
+
+  if let Some(path) = &ssl.rootcert {
+    let pem = std::fs::read(path)?;
+    builder.add_root_certificate(Certificate::from_pem(&pem)?);
+  }
+
+  Ok(MakeTlsConnector::new(builder.build()?))
+}
+
+/// Connect to the PostgreSQL database and return a connection pool.
+/// One connection is opened right away so bad settings fail early;
+/// waiting longer than `pool_wait` for a free connection is an error.
+pub async fn connect_to_database(
+  a_conf: &PostgresConfig,
+) -> Result<Pool, Box<dyn std::error::Error>>
+{
+  let mgr = Manager::from_config(
+    pg_config(a_conf)?,
+    make_tls(a_conf)?,
+    ManagerConfig {
+      recycling_method: RecyclingMethod::Fast,
+    },
+  );
+  let pool = Pool::builder(mgr)
+    .max_size(a_conf.pool_size)
+    .wait_timeout(Some(Duration::from_secs(a_conf.pool_wait)))
+    .runtime(Runtime::Tokio1)
+    .build()?;
+
+  let _ = pool.get().await?;
+
+  Ok(pool)
 }
@@ -0,0 +1,164 @@
+use chrono::{Datelike, Days, NaiveDate};
+
+/// Date format naming an Excel serial date, days since 1899-12-30
+pub const EXCEL_FORMAT: &str = "excel";
+
+/// Excel serials above this are not taken for dates (year 2173)
+const EXCEL_SERIAL_MAX: f64 = 100000.0;
+
+/// Forms the spreadsheet reader writes date cells in, read whatever the
+/// formats of the mapping: a date, or a date and time
+pub const CELL_DATE_FORMATS: &[&str] =
+  &["%Y-%m-%d", "%Y-%m-%d %H:%M:%S"];
+
+/// Formats tried when a mapping sets none. "05/01/2024" matches both
+/// slash formats and is reported as ambiguous. Excel serials are only
+/// read when a mapping lists `EXCEL_FORMAT`, as any small number would
+/// pass for one.
+pub const DEFAULT_DATE_FORMATS: &[&str] = &[
+  "%Y-%m-%d", // 2024-05-01
+  "%Y/%m/%d", // 2024/05/01
+  "%d.%m.%Y", // 01.05.2024
+  "%d-%m-%Y", // 01-05-2024
+  "%m/%d/%Y", // 05/01/2024 - US format
+  "%d/%m/%Y", // 01/05/2024
+];
+
+/// Whether a chrono format names a month without a day, e.g. "%Y-%m"
+/// or "%m.%Y". Such periods are stored as their first day.
+pub fn is_period_format(a_format: &str) -> bool
+{
+  a_format.contains("%m")
+    && !["%d", "%e", "%j"].iter().any(|x| a_format.contains(x))
+}
+
+/// Date of a value in one format: a chrono format, a month period or
+/// `EXCEL_FORMAT`
+pub fn parse_date_format(
+  a_value: &str,
+  a_format: &str,
+) -> Option<NaiveDate>
+{
+  let v = a_value.trim();
+
+  if a_format == EXCEL_FORMAT {
+    let serial = v.parse::<f64>().ok()?;
+    if serial <= 0.0 || serial >= EXCEL_SERIAL_MAX {
+      return None;
+    }
+    return NaiveDate::from_ymd_opt(1899, 12, 30)
+      .and_then(|x| x.checked_add_days(Days::new(serial as u64)));
+  }
+  if is_period_format(a_format) {
+    return NaiveDate::parse_from_str(
+      &format!("{}|1", v),
+      &format!("{}|%d", a_format),
+    )
+    .ok();
+  }
+
+  NaiveDate::parse_from_str(v, a_format).ok()
+}
+
+/// Date of a value given the formats configured for it. Formats that
+/// read the value as different days make it ambiguous, which is an
+/// error rather than a guess.
+pub fn parse_date_as<S: AsRef<str>>(
+  a_value: &str,
+  a_formats: &[S],
+) -> Result<NaiveDate, String>
+{
+  let mut found: Vec<NaiveDate> = Vec::new();
+  for f in a_formats {
+    if let Some(d) = parse_date_format(a_value, f.as_ref())
+      .filter(|x| !found.contains(x))
+    {
+      found.push(d);
+    }
+  }
+
+  match found.len() {
+    1 => Ok(found[0]),
+    0 => Err(format!(
+      "Date '{}' matches none of {}",
+      a_value,
+      a_formats
+        .iter()
+        .map(|x| format!("'{}'", x.as_ref()))
+        .collect::<Vec<_>>()
+        .join(", ")
+    )),
+    _ => Err(format!(
+      "Ambiguous date '{}': {}; set date_formats in the mapping",
+      a_value,
+      found
+        .iter()
+        .map(|x| x.format("%Y-%m-%d").to_string())
+        .collect::<Vec<_>>()
+        .join(" or ")
+    )),
+  }
+}
+
+/// First day of the month of `a_date`
+pub fn month_start(a_date: NaiveDate) -> NaiveDate
+{
+  a_date.with_day(1).unwrap_or(a_date)
+}
+
+#[cfg(test)]
+mod tests
+{
+  use super::*;
+
+  fn day(y: i32, m: u32, d: u32) -> NaiveDate
+  {
+    NaiveDate::from_ymd_opt(y, m, d).unwrap()
+  }
+
+  #[test]
+  fn parse_date_as_ambiguous()
+  {
+    let e = parse_date_as("05/01/2024", DEFAULT_DATE_FORMATS);
+    assert!(e.unwrap_err().starts_with("Ambiguous date"));
+    assert_eq!(
+      parse_date_as("05/01/2024", &["%m/%d/%Y"]),
+      Ok(day(2024, 5, 1))
+    );
+    // Only one slash order reads a day above 12
+    assert_eq!(
+      parse_date_as("13/01/2024", DEFAULT_DATE_FORMATS),
+      Ok(day(2024, 1, 13))
+    );
+  }
+
+  #[test]
+  fn parse_date_as_no_match()
+  {
+    for v in ["2024", "1", "20240501", "N\\A", ""] {
+      assert!(
+        parse_date_as(v, DEFAULT_DATE_FORMATS).is_err(),
+        "{}",
+        v
+      );
+    }
+  }
+
+  #[test]
+  fn parse_date_as_listed_formats()
+  {
+    assert_eq!(
+      parse_date_as("45413", &[EXCEL_FORMAT]),
+      Ok(day(2024, 5, 1))
+    );
+    assert!(parse_date_as("45413", DEFAULT_DATE_FORMATS).is_err());
+    assert_eq!(
+      parse_date_as("2024-05", &["%Y-%m"]),
+      Ok(day(2024, 5, 1))
+    );
+    assert_eq!(
+      parse_date_as("2024-05-01 00:00:00", CELL_DATE_FORMATS),
+      Ok(day(2024, 5, 1))
+    );
+  }
+}
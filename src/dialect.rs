@@ -0,0 +1,372 @@
+use crate::sources::InputOptions;
+use encoding_rs::{Encoding, UTF_8, WINDOWS_1251};
+use std::error::Error;
+use std::fs::File;
+use std::io::Read;
+use std::path::PathBuf;
+
+/// Bytes inspected to guess the dialect of a file
+const SAMPLE_BYTES: usize = 64 * 1024;
+
+/// Records of the sample compared when guessing the delimiter
+const SAMPLE_RECORDS: usize = 50;
+
+/// Encoding of files that are neither UTF-8 nor marked by a BOM; the
+/// vendor exports that are not UTF-8 come from Windows systems
+const FALLBACK_ENCODING: &Encoding = WINDOWS_1251;
+
+const DELIMITERS: &[u8] = b",;\t|";
+
+/// How a CSV file is written
+#[derive(Debug, Clone)]
+pub struct Dialect
+{
+  pub encoding: &'static Encoding,
+  /// The file starts with a byte order mark
+  pub bom: bool,
+  pub delimiter: u8,
+  /// None when fields are never quoted
+  pub quote: Option<u8>,
+  /// ',' for "1 234,5", '.' for "1234.5"
+  pub decimal: char,
+  /// Columns of a file with a decimal ',' whose sampled numbers group
+  /// thousands with dots, as "1.234,5" or "1.234.567"
+  pub dot_grouped: Vec<usize>,
+  /// Dates may be date cells of a spreadsheet, written in one of
+  /// `CELL_DATE_FORMATS`
+  pub date_cells: bool,
+}
+
+impl Default for Dialect
+{
+  fn default() -> Self
+  {
+    Dialect {
+      encoding: UTF_8,
+      bom: false,
+      delimiter: b',',
+      quote: Some(b'"'),
+      decimal: '.',
+      dot_grouped: Vec::new(),
+      date_cells: false,
+    }
+  }
+}
+
+/// Split a decoded sample into records of fields, honouring `a_quote`.
+/// The last record is dropped as it is likely cut by the sample size.
+fn split_sample(
+  a_text: &str,
+  a_delim: char,
+  a_quote: Option<char>,
+) -> Vec<Vec<String>>
+{
+  let mut records = Vec::new();
+  let mut fields = Vec::new();
+  let mut field = String::new();
+  let mut in_quote = false;
+
+  for c in a_text.chars() {
+    if Some(c) == a_quote {
+      in_quote = !in_quote;
+      field.push(c);
+    } else if in_quote {
+      field.push(c);
+    } else if c == a_delim {
+      fields.push(std::mem::take(&mut field));
+    } else if c == '\n' {
+      fields.push(
+        std::mem::take(&mut field).trim_end_matches('\r').into(),
+      );
+      records.push(std::mem::take(&mut fields));
+      if records.len() > SAMPLE_RECORDS {
+        break;
+      }
+    } else {
+      field.push(c);
+    }
+  }
+
+  records
+}
+
+fn guess_delimiter(a_text: &str) -> u8
+{
+  // Most records with the header's field count wins, ties go to the
+  // wider split
+  let mut best = (b',', 0, 0);
+  for d in DELIMITERS {
+    let records = split_sample(a_text, *d as char, Some('"'));
+    let width = match records.first() {
+      Some(x) if x.len() > 1 => x.len(),
+      _ => continue,
+    };
+    let consistent =
+      records.iter().filter(|x| x.len() == width).count();
+    if (consistent, width) > (best.1, best.2) {
+      best = (*d, consistent, width);
+    }
+  }
+
+  best.0
+}
+
+fn guess_quote(a_text: &str, a_delim: u8) -> Option<u8>
+{
+  let records = split_sample(a_text, a_delim as char, None);
+  let starts = |q: char| {
+    records
+      .iter()
+      .flatten()
+      .filter(|x| x.len() > 1 && x.starts_with(q) && x.ends_with(q))
+      .count()
+  };
+
+  if starts('\'') > starts('"') {
+    Some(b'\'')
+  } else {
+    Some(b'"')
+  }
+}
+
+/// Whether a field looks like a number written with `a_decimal`
+fn is_decimal(a_field: &str, a_decimal: char) -> bool
+{
+  let s = a_field.trim().trim_matches('"');
+  let (int, frac) = match s.rsplit_once(a_decimal) {
+    Some(x) => x,
+    None => return false,
+  };
+  let int = int.strip_prefix('-').unwrap_or(int);
+
+  !int.is_empty()
+    && !frac.is_empty()
+    && int
+      .chars()
+      .all(|x| x.is_ascii_digit() || x == ' ' || x == '\u{a0}')
+    && frac.chars().all(|x| x.is_ascii_digit())
+}
+
+fn guess_decimal(
+  a_text: &str,
+  a_delim: u8,
+  a_quote: Option<u8>,
+) -> char
+{
+  let records =
+    split_sample(a_text, a_delim as char, a_quote.map(|x| x as char));
+  let fields: Vec<&String> =
+    records.iter().skip(1).flatten().collect();
+  let commas = fields.iter().filter(|x| is_decimal(x, ',')).count();
+  let dots = fields.iter().filter(|x| is_decimal(x, '.')).count();
+
+  if commas > dots { ',' } else { '.' }
+}
+
+/// Positions of the columns with a value that can only be read with
+/// dots grouping thousands
+fn guess_dot_grouped(
+  a_text: &str,
+  a_delim: u8,
+  a_quote: Option<u8>,
+) -> Vec<usize>
+{
+  let records =
+    split_sample(a_text, a_delim as char, a_quote.map(|x| x as char));
+  let mut out: Vec<usize> = Vec::new();
+  for r in records.iter().skip(1) {
+    for (i, x) in r.iter().enumerate() {
+      if !out.contains(&i) && surely_dot_grouped(x.trim_matches('"'))
+      {
+        out.push(i);
+      }
+    }
+  }
+  out.sort();
+
+  out
+}
+
+fn parse_char(
+  a_name: &str,
+  a_value: &str,
+) -> Result<u8, Box<dyn Error>>
+{
+  match a_value {
+    "tab" | "\\t" | "\t" => Ok(b'\t'),
+    x if x.len() == 1 => Ok(x.as_bytes()[0]),
+    x => Err(
+      format!("Invalid {} '{}', expected one character", a_name, x)
+        .into(),
+    ),
+  }
+}
+
+/// Guess the dialect of a CSV file from its first bytes, then apply the
+/// settings given explicitly in `a_opts`
+pub fn detect_dialect(
+  a_path: &PathBuf,
+  a_opts: &InputOptions,
+) -> Result<Dialect, Box<dyn Error>>
+{
+  let mut sample = Vec::with_capacity(SAMPLE_BYTES);
+  File::open(a_path)?
+    .take(SAMPLE_BYTES as u64)
+    .read_to_end(&mut sample)?;
+
+  let mut out = Dialect::default();
+
+  match Encoding::for_bom(&sample) {
+    Some((enc, _)) => {
+      out.encoding = enc;
+      out.bom = true;
+    }
+    None => {
+      // A multi-byte character may be cut at the end of the sample
+      out.encoding = match std::str::from_utf8(&sample) {
+        Ok(_) => UTF_8,
+        Err(e) if e.error_len().is_none() => UTF_8,
+        Err(_) => FALLBACK_ENCODING,
+      };
+    }
+  }
+  if let Some(label) = &a_opts.encoding {
+    out.encoding = Encoding::for_label(label.as_bytes())
+      .ok_or(format!("Unknown encoding '{}'", label))?;
+  }
+
+  let (text, _) = out.encoding.decode_with_bom_removal(&sample);
+  // Rows above the header are not part of the table
+  let text: String =
+    text.split_inclusive('\n').skip(a_opts.header_row).collect();
+
+  out.delimiter = match &a_opts.delimiter {
+    Some(x) => parse_char("delimiter", x)?,
+    None => guess_delimiter(&text),
+  };
+  out.quote = match a_opts.quote.as_deref() {
+    Some("none") => None,
+    Some(x) => Some(parse_char("quote", x)?),
+    None => guess_quote(&text, out.delimiter),
+  };
+  out.decimal = match a_opts.decimal.as_deref() {
+    Some(",") => ',',
+    Some(".") => '.',
+    Some(x) => {
+      return Err(
+        format!("Invalid decimal '{}', expected , or .", x).into(),
+      );
+    }
+    None => guess_decimal(&text, out.delimiter, out.quote),
+  };
+  if out.decimal == ',' {
+    out.dot_grouped =
+      guess_dot_grouped(&text, out.delimiter, out.quote);
+  }
+
+  Ok(out)
+}
+
+/// True for digits grouped in threes by `a_sep`, e.g. "1.234" or
+/// "-12,345,678"
+pub fn grouped_by(a_value: &str, a_sep: char) -> bool
+{
+  let mut groups =
+    a_value.strip_prefix('-').unwrap_or(a_value).split(a_sep);
+  let first = groups.next().unwrap_or_default();
+  let mut rest = groups.peekable();
+  (1..=3).contains(&first.len())
+    && first.chars().all(|x| x.is_ascii_digit())
+    && rest.peek().is_some()
+    && rest
+      .all(|x| x.len() == 3 && x.chars().all(|x| x.is_ascii_digit()))
+}
+
+/// True when dots in `a_value` can only group thousands: before a
+/// decimal comma, as "1.234,5", or more than one, as "1.234.567"
+fn surely_dot_grouped(a_value: &str) -> bool
+{
+  let s: String =
+    a_value.chars().filter(|x| !x.is_whitespace()).collect();
+  match s.rsplit_once(',') {
+    Some((int, frac)) => {
+      !frac.is_empty()
+        && frac.chars().all(|x| x.is_ascii_digit())
+        && grouped_by(int, '.')
+    }
+    None => s.matches('.').count() > 1 && grouped_by(&s, '.'),
+  }
+}
+
+/// Rewrite a number written with a decimal comma, e.g. "1 234,5" or
+/// "1.234,5", as "1234.5". Dots are dropped before a decimal comma or
+/// when several group thousands. A single one, as in "12.500", groups
+/// thousands only in a column `a_dot_grouped` by `Dialect::dot_grouped`
+/// and is a decimal point otherwise. A stray "1,234.5" or "1.5E-3" is
+/// left to parse_number. Values that do not look like numbers are
+/// returned unchanged.
+pub fn normalize_decimal(a_value: &str, a_dot_grouped: bool)
+-> String
+{
+  let s: String = a_value
+    .trim()
+    .chars()
+    .filter(|x| !x.is_whitespace())
+    .collect();
+  // With both separators the last one is the decimal one
+  let comma_last = s.rfind(',') > s.rfind('.');
+  let grouped = grouped_by(&s, '.')
+    && (a_dot_grouped || s.matches('.').count() > 1);
+  let s = if comma_last || grouped {
+    s.replace('.', "").replace(',', ".")
+  } else {
+    s
+  };
+
+  if s.parse::<f64>().is_ok() {
+    s
+  } else {
+    a_value.to_string()
+  }
+}
+
+#[cfg(test)]
+mod tests
+{
+  use super::*;
+
+  #[test]
+  fn normalize_decimal_comma()
+  {
+    assert_eq!(normalize_decimal("1 234,5", false), "1234.5");
+    assert_eq!(normalize_decimal("1\u{a0}234,5", false), "1234.5");
+    assert_eq!(normalize_decimal("1.234,5", false), "1234.5");
+    assert_eq!(normalize_decimal("-12,5", false), "-12.5");
+    assert_eq!(normalize_decimal("1.234.567", false), "1234567");
+  }
+
+  #[test]
+  fn normalize_decimal_single_dot()
+  {
+    // A decimal point unless the column groups thousands with dots
+    assert_eq!(normalize_decimal("12.500", false), "12.500");
+    assert_eq!(normalize_decimal("12.500", true), "12500");
+    assert_eq!(normalize_decimal("12.5", true), "12.5");
+    assert_eq!(normalize_decimal("1.5E-3", true), "1.5E-3");
+  }
+
+  #[test]
+  fn normalize_decimal_leaves_other_values()
+  {
+    assert_eq!(normalize_decimal("1,234.5", false), "1,234.5");
+    assert_eq!(normalize_decimal("N\\A", false), "N\\A");
+    assert_eq!(normalize_decimal("", false), "");
+  }
+
+  #[test]
+  fn dot_grouped_columns()
+  {
+    let text = "a;b;c;d\n12.500;1.234,5;3;1.000.000\n7.250;2,5;4;5\n";
+    assert_eq!(guess_dot_grouped(text, b';', Some(b'"')), vec![1, 3]);
+  }
+}
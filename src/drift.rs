@@ -0,0 +1,234 @@
+use crate::database::parse_date;
+use crate::dates::parse_date_as;
+use crate::dialect::normalize_decimal;
+use crate::mapping::{Mapping, header_index};
+use crate::sources::open_source;
+use crate::validation::{DATE_FIELDS, NUMERIC_FIELDS, parse_number};
+use serde::Serialize;
+use std::error::Error;
+use std::path::PathBuf;
+use tokio_postgres::Client;
+
+/// Share of unparseable sampled values above which a column is reported
+/// as having changed type
+const TYPE_DRIFT_SHARE: f64 = 0.5;
+
+/// A mapped column whose values no longer fit the target field
+#[derive(Debug, Clone, Serialize)]
+pub struct TypeDrift
+{
+  pub column: String,
+  pub field: String,
+  /// "number" or "date"
+  pub expected: String,
+  pub checked: usize,
+  pub failed: usize,
+  /// A few of the offending values
+  pub samples: Vec<String>,
+}
+
+struct ColumnCheck
+{
+  column: String,
+  field: String,
+  pos: usize,
+  date_formats: Option<Vec<String>>,
+  is_date: bool,
+  checked: usize,
+  failed: usize,
+  samples: Vec<String>,
+}
+
+/// Differences between a file and the mapping it is imported with
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct DriftReport
+{
+  pub mapping: String,
+  /// Columns of the file the mapping does not know
+  pub unknown: Vec<String>,
+  /// Expected columns absent from the file
+  pub missing: Vec<String>,
+  pub type_changed: Vec<TypeDrift>,
+}
+
+impl DriftReport
+{
+  pub fn is_empty(&self) -> bool
+  {
+    self.unknown.is_empty()
+      && self.missing.is_empty()
+      && self.type_changed.is_empty()
+  }
+
+  /// One line description for logs and errors
+  pub fn summary(&self) -> String
+  {
+    let mut parts = Vec::new();
+    if !self.unknown.is_empty() {
+      parts.push(format!(
+        "unknown columns: {}",
+        self.unknown.join(", ")
+      ));
+    }
+    if !self.missing.is_empty() {
+      parts.push(format!(
+        "missing columns: {}",
+        self.missing.join(", ")
+      ));
+    }
+    for t in &self.type_changed {
+      parts.push(format!(
+        "column {} ({}) expected {}, {} of {} values are not, e.g. '{}'",
+        t.column,
+        t.field,
+        t.expected,
+        t.failed,
+        t.checked,
+        t.samples.first().cloned().unwrap_or_default()
+      ));
+    }
+
+    format!("mapping {}: {}", self.mapping, parts.join("; "))
+  }
+}
+
+/// Compare the header row and the first `a_sample_rows` rows of a file
+/// with a mapping
+pub fn detect_drift(
+  a_path: &PathBuf,
+  a_mapping: &Mapping,
+  a_sample_rows: usize,
+) -> Result<DriftReport, Box<dyn Error>>
+{
+  let source = open_source(a_path, &a_mapping.input)?;
+  let headers = source.headers;
+  let dialect = source.dialect;
+  let index = header_index(&headers);
+
+  let mut known = a_mapping.source_columns();
+  known.extend(a_mapping.ignored.iter().cloned());
+  known.extend(a_mapping.optional.iter().cloned());
+
+  let mut out = DriftReport {
+    mapping: a_mapping.name.clone(),
+    ..Default::default()
+  };
+
+  for h in headers.iter().map(|x| x.trim()) {
+    if !h.is_empty()
+      && !known.iter().any(|x| x.trim() == h)
+      && !out.unknown.iter().any(|x| x == h)
+    {
+      out.unknown.push(h.to_string());
+    }
+  }
+
+  for c in a_mapping.source_columns() {
+    if !index.contains_key(c.trim())
+      && !a_mapping.optional.contains(&c)
+    {
+      out.missing.push(c);
+    }
+  }
+
+  // Single column numeric and date fields, checked on a sample
+  let mut checks: Vec<ColumnCheck> = Vec::new();
+  for (field, spec) in &a_mapping.fields {
+    let rule = spec.rule();
+    let column = match (&rule.source, rule.sources.len()) {
+      (Some(x), 0) => x.clone(),
+      _ => continue,
+    };
+    let pos = match index.get(column.trim()) {
+      Some(x) => *x,
+      None => continue,
+    };
+
+    let date_formats =
+      a_mapping.date_formats_of(field, &rule, &dialect);
+    let is_date =
+      date_formats.is_some() || DATE_FIELDS.contains(&field.as_str());
+    let is_number = rule.scale.is_some()
+      || NUMERIC_FIELDS.contains(&field.as_str());
+    if is_date || is_number {
+      checks.push(ColumnCheck {
+        column,
+        field: field.clone(),
+        pos,
+        date_formats,
+        is_date,
+        checked: 0,
+        failed: 0,
+        samples: Vec::new(),
+      });
+    }
+  }
+  checks.sort_by_key(|x| x.pos);
+
+  for record in source.rows.take(a_sample_rows) {
+    let rec = match record {
+      Ok(x) => x,
+      Err(_) => continue,
+    };
+
+    for c in checks.iter_mut() {
+      let v = rec.get(c.pos).unwrap_or("").trim();
+      if v.is_empty() {
+        continue;
+      }
+
+      let ok = match (c.is_date, &c.date_formats) {
+        (true, Some(f)) => parse_date_as(v, f).is_ok(),
+        (true, None) => parse_date(v).is_some(),
+        _ if dialect.decimal == ',' => {
+          let grouped = dialect.dot_grouped.contains(&c.pos);
+          parse_number(&normalize_decimal(v, grouped), ',').is_some()
+        }
+        _ => parse_number(v, dialect.decimal).is_some(),
+      };
+      c.checked += 1;
+      if !ok {
+        c.failed += 1;
+        if c.samples.len() < 3 {
+          c.samples.push(v.to_string());
+        }
+      }
+    }
+  }
+
+  for c in checks {
+    if c.checked > 0
+      && c.failed as f64 / c.checked as f64 > TYPE_DRIFT_SHARE
+    {
+      out.type_changed.push(TypeDrift {
+        column: c.column,
+        field: c.field,
+        expected: if c.is_date { "date" } else { "number" }
+          .to_string(),
+        checked: c.checked,
+        failed: c.failed,
+        samples: c.samples,
+      });
+    }
+  }
+
+  Ok(out)
+}
+
+/// Attach a drift report to an import run
+pub async fn store_drift(
+  a_cli: &Client,
+  a_run_id: i32,
+  a_report: &DriftReport,
+) -> Result<(), Box<dyn Error>>
+{
+  let json = serde_json::to_string(a_report)?;
+  a_cli
+    .execute(
+      "UPDATE import_runs SET drift = $2::TEXT::JSONB WHERE id = $1",
+      &[&a_run_id, &json],
+    )
+    .await?;
+
+  Ok(())
+}
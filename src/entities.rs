@@ -0,0 +1,432 @@
+use crate::identifiers::{
+  IdentifierT, entity_type, parse_identifier,
+};
+use crate::schemas::SegmentT;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::str::FromStr;
+use tokio_postgres::Client;
+
+/// Legal forms stripped from company names, as upper case words, with
+/// the abbreviation they are recorded as
+const LEGAL_FORMS: &[(&str, &str)] = &[
+  ("ОБЩЕСТВО С ОГРАНИЧЕННОЙ ОТВЕТСТВЕННОСТЬЮ", "ООО"),
+  ("ООО", "ООО"),
+  ("OOO", "ООО"),
+  ("ТОВАРИЩЕСТВО С ОГРАНИЧЕННОЙ ОТВЕТСТВЕННОСТЬЮ", "ТОО"),
+  ("ТОО", "ТОО"),
+  ("TOO", "ТОО"),
+  ("ИООО", "ИООО"),
+  ("ОДО", "ОДО"),
+  ("ЗАКРЫТОЕ АКЦИОНЕРНОЕ ОБЩЕСТВО", "ЗАО"),
+  ("ЗАО", "ЗАО"),
+  ("ОТКРЫТОЕ АКЦИОНЕРНОЕ ОБЩЕСТВО", "ОАО"),
+  ("ОАО", "ОАО"),
+  ("ПУБЛИЧНОЕ АКЦИОНЕРНОЕ ОБЩЕСТВО", "ПАО"),
+  ("ПАО", "ПАО"),
+  ("АКЦИОНЕРНОЕ ОБЩЕСТВО", "АО"),
+  ("АО", "АО"),
+  ("JSC", "АО"),
+  ("ИНДИВИДУАЛЬНЫЙ ПРЕДПРИНИМАТЕЛЬ", "ИП"),
+  ("ИП", "ИП"),
+  ("LIMITED LIABILITY COMPANY", "LLC"),
+  ("LLC", "LLC"),
+  ("COMPANY LIMITED", "LTD"),
+  ("CO LTD", "LTD"),
+  ("LIMITED", "LTD"),
+  ("LTD", "LTD"),
+  ("INC", "INC"),
+  ("CORP", "CORP"),
+  ("GMBH", "GMBH"),
+  ("SP Z O O", "SP Z O O"),
+  ("SRL", "SRL"),
+  ("AG", "AG"),
+];
+
+/// Rows read per query when resolving
+const RESOLVE_PAGE: i64 = 5000;
+
+/// Company name reduced for matching
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompanyName
+{
+  /// Words of the name without legal form and punctuation, sorted
+  pub key: String,
+  pub legal_form: Option<&'static str>,
+}
+
+/// Reduce a company name so that spelling variants compare equal:
+/// case, quotes and punctuation, word order and the legal form, written
+/// out or abbreviated, at either end. None for names without words.
+pub fn normalize_name(a_name: &str) -> Option<CompanyName>
+{
+  let upper = a_name.to_uppercase().replace('Ё', "Е");
+  let mut words: Vec<&str> = upper
+    .split(|c: char| !c.is_alphanumeric())
+    .filter(|x| !x.is_empty())
+    .collect();
+  let mut legal_form = None;
+
+  loop {
+    // Longest legal form at the start or the end, keeping one word
+    let found = LEGAL_FORMS
+      .iter()
+      .filter_map(|(phrase, form)| {
+        let p: Vec<&str> = phrase.split(' ').collect();
+        if words.len() <= p.len() {
+          None
+        } else if words[..p.len()] == p[..] {
+          Some((p.len(), true, *form))
+        } else if words[words.len() - p.len()..] == p[..] {
+          Some((p.len(), false, *form))
+        } else {
+          None
+        }
+      })
+      .max_by_key(|(n, _, _)| *n);
+
+    match found {
+      Some((n, true, form)) => {
+        words.drain(..n);
+        legal_form.get_or_insert(form);
+      }
+      Some((n, false, form)) => {
+        words.truncate(words.len() - n);
+        legal_form.get_or_insert(form);
+      }
+      None => break,
+    }
+  }
+  if words.is_empty() {
+    return None;
+  }
+
+  words.sort();
+  words.dedup();
+  Some(CompanyName { key: words.join(" "), legal_form })
+}
+
+/// Outcome of a resolution pass
+#[derive(Debug, Default)]
+pub struct ResolveStats
+{
+  pub rows: u64,
+  pub linked: u64,
+  pub created: u64,
+}
+
+/// Companies known to the database
+#[derive(Default)]
+struct Registry
+{
+  by_identifier: HashMap<(IdentifierT, String), i32>,
+  /// Companies per name key, with their legal form
+  by_name: HashMap<String, Vec<(i32, Option<String>)>>,
+  identified: HashSet<i32>,
+  /// Import run recorded on the companies created
+  run_id: Option<i32>,
+}
+
+impl Registry
+{
+  async fn load(a_cli: &Client) -> Result<Self, Box<dyn Error>>
+  {
+    let mut out = Registry::default();
+    let rows = a_cli
+      .query(
+        "SELECT id, identifier_type, identifier, name_key, legal_form \
+        FROM companies",
+        &[],
+      )
+      .await?;
+
+    for r in rows {
+      let id: i32 = r.get(0);
+      let kind: Option<String> = r.get(1);
+      let ident: Option<String> = r.get(2);
+      let key: Option<String> = r.get(3);
+
+      if let (Some(t), Some(i)) =
+        (kind.as_deref().and_then(IdentifierT::from_code), ident)
+      {
+        out.by_identifier.insert((t, i), id);
+        out.identified.insert(id);
+      }
+      if let Some(k) = key {
+        out.by_name.entry(k).or_default().push((id, r.get(4)));
+      }
+    }
+
+    Ok(out)
+  }
+
+  /// Companies named like `a_name` whose legal form does not conflict
+  fn named(&self, a_name: &CompanyName) -> Vec<i32>
+  {
+    self
+      .by_name
+      .get(&a_name.key)
+      .map(|x| {
+        x.iter()
+          .filter(|(_, f)| match (f.as_deref(), a_name.legal_form) {
+            (Some(a), Some(b)) => a == b,
+            _ => true,
+          })
+          .map(|(id, _)| *id)
+          .collect()
+      })
+      .unwrap_or_default()
+  }
+
+  async fn create(
+    &mut self,
+    a_cli: &Client,
+    a_ident: Option<&(IdentifierT, String)>,
+    a_raw_name: Option<&str>,
+    a_name: Option<&CompanyName>,
+  ) -> Result<i32, Box<dyn Error>>
+  {
+    // A concurrent import may have created the same identifier
+    let row = a_cli
+      .query_one(
+        "INSERT INTO companies (identifier_type, identifier, \
+          entity_type, name, legal_form, name_key, import_run_id) \
+        VALUES ($1, $2, $3, $4, $5, $6, $7) \
+        ON CONFLICT (identifier_type, identifier) \
+        DO UPDATE SET identifier = EXCLUDED.identifier \
+        RETURNING id",
+        &[
+          &a_ident.map(|(t, _)| t.code()),
+          &a_ident.map(|(_, i)| i.as_str()),
+          &a_ident.map(|(t, i)| entity_type(*t, i).code()),
+          &a_raw_name.map(|x| x.trim()),
+          &a_name.and_then(|x| x.legal_form),
+          &a_name.map(|x| x.key.as_str()),
+          &self.run_id,
+        ],
+      )
+      .await?;
+    let id: i32 = row.get(0);
+
+    if let Some(i) = a_ident {
+      self.by_identifier.insert(i.clone(), id);
+      self.identified.insert(id);
+    }
+    if let Some(n) = a_name {
+      self
+        .by_name
+        .entry(n.key.clone())
+        .or_default()
+        .push((id, n.legal_form.map(|x| x.to_string())));
+    }
+
+    Ok(id)
+  }
+
+  /// Company of one party of a declaration, created when unknown. A
+  /// valid identifier decides; a company known only by name gets the
+  /// identifier once it shows up. Without one the name is matched,
+  /// preferring a company known only by name.
+  async fn resolve(
+    &mut self,
+    a_cli: &Client,
+    a_seg: &SegmentT,
+    a_inn: Option<&str>,
+    a_name: Option<&str>,
+    a_stats: &mut ResolveStats,
+  ) -> Result<Option<i32>, Box<dyn Error>>
+  {
+    let ident = a_inn.and_then(|x| parse_identifier(x, a_seg));
+    let name = a_name.and_then(normalize_name);
+
+    let named: Vec<i32> =
+      name.as_ref().map(|x| self.named(x)).unwrap_or_default();
+    let unidentified =
+      named.iter().find(|x| !self.identified.contains(x)).cloned();
+
+    if let Some(i) = &ident {
+      if let Some(id) = self.by_identifier.get(i) {
+        return Ok(Some(*id));
+      }
+      if let Some(id) = unidentified {
+        a_cli
+          .execute(
+            "UPDATE companies SET identifier_type = $2, identifier = $3, \
+              entity_type = $4 \
+            WHERE id = $1",
+            &[&id, &i.0.code(), &i.1, &entity_type(i.0, &i.1).code()],
+          )
+          .await?;
+        self.by_identifier.insert(i.clone(), id);
+        self.identified.insert(id);
+        return Ok(Some(id));
+      }
+    } else if name.is_none() {
+      return Ok(None);
+    } else if let Some(id) = unidentified {
+      return Ok(Some(id));
+    } else if named.len() == 1 {
+      return Ok(Some(named[0]));
+    }
+
+    a_stats.created += 1;
+    let id = self
+      .create(a_cli, ident.as_ref(), a_name, name.as_ref())
+      .await?;
+
+    Ok(Some(id))
+  }
+}
+
+/// Link the sender, receiver and contractor of declaration rows to
+/// companies, creating companies for new identifiers and names, then
+/// refresh the name variants and canonical name of the companies
+/// touched. Limited to the rows of one import run when `a_run_id` is
+/// set.
+pub async fn resolve_companies(
+  a_cli: &Client,
+  a_run_id: Option<i32>,
+) -> Result<ResolveStats, Box<dyn Error>>
+{
+  let mut registry = Registry::load(a_cli).await?;
+  registry.run_id = a_run_id;
+  let mut stats = ResolveStats::default();
+  let mut touched: HashSet<i32> = HashSet::new();
+  let mut last_id = 0;
+
+  loop {
+    let rows = a_cli
+      .query(
+        "SELECT id, seg, sender_inn, sender_name, receiver_inn, \
+          receiver_name, contractor_inn, contractor_name \
+        FROM general_schema \
+        WHERE id > $1 AND ($2::INTEGER IS NULL \
+          OR import_run_id = $2 OR updated_run_id = $2) \
+        ORDER BY id LIMIT $3",
+        &[&last_id, &a_run_id, &RESOLVE_PAGE],
+      )
+      .await?;
+    if rows.is_empty() {
+      break;
+    }
+
+    let mut ids = Vec::with_capacity(rows.len());
+    let mut parties: [Vec<Option<i32>>; 3] = Default::default();
+    for r in &rows {
+      let seg =
+        SegmentT::from_str(r.get::<_, Option<&str>>(1).unwrap_or(""))
+          .unwrap_or(SegmentT::Rus);
+      for (i, party) in parties.iter_mut().enumerate() {
+        let id = registry
+          .resolve(
+            a_cli,
+            &seg,
+            r.get(2 + i * 2),
+            r.get(3 + i * 2),
+            &mut stats,
+          )
+          .await?;
+        touched.extend(id);
+        party.push(id);
+      }
+      ids.push(r.get::<_, i32>(0));
+    }
+    last_id = *ids.last().unwrap_or(&last_id);
+    stats.rows += ids.len() as u64;
+
+    stats.linked += a_cli
+      .execute(
+        "UPDATE general_schema g SET sender_company_id = u.s, \
+          receiver_company_id = u.r, contractor_company_id = u.c \
+        FROM UNNEST($1::INTEGER[], $2::INTEGER[], $3::INTEGER[], \
+          $4::INTEGER[]) AS u(id, s, r, c) \
+        WHERE g.id = u.id AND (g.sender_company_id, \
+          g.receiver_company_id, g.contractor_company_id) \
+          IS DISTINCT FROM (u.s, u.r, u.c)",
+        &[&ids, &parties[0], &parties[1], &parties[2]],
+      )
+      .await?;
+  }
+
+  let touched: Vec<i32> = touched.into_iter().collect();
+  a_cli
+    .execute(
+      "DELETE FROM company_names WHERE company_id = ANY($1)",
+      &[&touched],
+    )
+    .await?;
+  a_cli
+    .execute(
+      "INSERT INTO company_names (company_id, name, rows) \
+      SELECT company_id, name, count(*) FROM ( \
+        SELECT sender_company_id, btrim(sender_name) \
+        FROM general_schema WHERE sender_company_id = ANY($1) \
+        UNION ALL \
+        SELECT receiver_company_id, btrim(receiver_name) \
+        FROM general_schema WHERE receiver_company_id = ANY($1) \
+        UNION ALL \
+        SELECT contractor_company_id, btrim(contractor_name) \
+        FROM general_schema WHERE contractor_company_id = ANY($1) \
+      ) AS p(company_id, name) WHERE name <> '' GROUP BY 1, 2",
+      &[&touched],
+    )
+    .await?;
+  // The most frequent spelling names the company
+  a_cli
+    .execute(
+      "UPDATE companies c SET name = COALESCE(( \
+        SELECT n.name FROM company_names n WHERE n.company_id = c.id \
+        ORDER BY n.rows DESC, n.name LIMIT 1), c.name) \
+      WHERE c.id = ANY($1)",
+      &[&touched],
+    )
+    .await?;
+
+  Ok(stats)
+}
+
+#[cfg(test)]
+mod tests
+{
+  use super::*;
+
+  fn name(
+    a_key: &str,
+    a_form: Option<&'static str>,
+  ) -> Option<CompanyName>
+  {
+    Some(CompanyName {
+      key: a_key.to_string(),
+      legal_form: a_form,
+    })
+  }
+
+  #[test]
+  fn spelling_variants_compare_equal()
+  {
+    let a = normalize_name("ООО \"Ромашка-Трейд\"");
+    assert_eq!(a, name("РОМАШКА ТРЕЙД", Some("ООО")));
+    assert_eq!(normalize_name("Трейд Ромашка, ооо"), a);
+    assert_eq!(
+      normalize_name(
+        "Общество с ограниченной ответственностью «Ромашка Трейд»"
+      ),
+      a
+    );
+  }
+
+  #[test]
+  fn legal_form_kept_with_one_word()
+  {
+    assert_eq!(normalize_name("ТОО ТОО"), name("ТОО", Some("ТОО")));
+    assert_eq!(normalize_name("Ёлка АО"), name("ЕЛКА", Some("АО")));
+    assert_eq!(normalize_name("Ёлка"), name("ЕЛКА", None));
+  }
+
+  #[test]
+  fn no_name_without_words()
+  {
+    assert_eq!(normalize_name(" \"\" - "), None);
+    assert_eq!(normalize_name(""), None);
+  }
+}
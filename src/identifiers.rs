@@ -0,0 +1,346 @@
+use crate::schemas::{GeneralSchema, SegmentT};
+use crate::validation::parse_number;
+
+/// Kind of a taxpayer identifier
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum IdentifierT
+{
+  /// Russian INN, 10 digits for organisations, 12 for individuals
+  Inn,
+  /// Kazakh BIN (organisations) or IIN (individuals), 12 digits
+  Bin,
+}
+
+impl IdentifierT
+{
+  /// Value stored in companies.identifier_type
+  pub fn code(&self) -> &'static str
+  {
+    match self {
+      IdentifierT::Inn => "inn",
+      IdentifierT::Bin => "bin",
+    }
+  }
+
+  pub fn from_code(a_code: &str) -> Option<Self>
+  {
+    match a_code {
+      "inn" => Some(IdentifierT::Inn),
+      "bin" => Some(IdentifierT::Bin),
+      _ => None,
+    }
+  }
+}
+
+fn digits(a_value: &str) -> Option<Vec<u32>>
+{
+  a_value.chars().map(|x| x.to_digit(10)).collect()
+}
+
+fn weighted(a_digits: &[u32], a_weights: &[u32]) -> u32
+{
+  a_digits.iter().zip(a_weights).map(|(d, w)| d * w).sum()
+}
+
+/// Whether `a_value` is a Russian INN with valid check digits
+pub fn inn_valid(a_value: &str) -> bool
+{
+  const W10: &[u32] = &[2, 4, 10, 3, 5, 9, 4, 6, 8];
+  const W11: &[u32] = &[7, 2, 4, 10, 3, 5, 9, 4, 6, 8];
+  const W12: &[u32] = &[3, 7, 2, 4, 10, 3, 5, 9, 4, 6, 8];
+
+  let d = match digits(a_value) {
+    Some(x) => x,
+    None => return false,
+  };
+  let check = |w: &[u32]| weighted(&d, w) % 11 % 10;
+
+  match d.len() {
+    10 => check(W10) == d[9],
+    12 => check(W11) == d[10] && check(W12) == d[11],
+    _ => false,
+  }
+}
+
+/// Whether `a_value` is a Kazakh BIN or IIN with a valid check digit
+pub fn bin_valid(a_value: &str) -> bool
+{
+  const W1: &[u32] = &[1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11];
+  const W2: &[u32] = &[3, 4, 5, 6, 7, 8, 9, 10, 11, 1, 2];
+
+  let d = match digits(a_value) {
+    Some(x) if x.len() == 12 => x,
+    _ => return false,
+  };
+  let mut check = weighted(&d, W1) % 11;
+  if check == 10 {
+    check = weighted(&d, W2) % 11;
+  }
+
+  check < 10 && check == d[11]
+}
+
+/// Whether an identifier belongs to an organisation or a person
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EntityT
+{
+  Legal,
+  /// A person, individual entrepreneurs included
+  Individual,
+}
+
+impl EntityT
+{
+  /// Value stored in companies.entity_type
+  pub fn code(&self) -> &'static str
+  {
+    match self {
+      EntityT::Legal => "legal",
+      EntityT::Individual => "individual",
+    }
+  }
+}
+
+/// Entity type of a valid identifier. An INN has 10 digits for
+/// organisations and 12 for persons. A BIN carries its type in the
+/// fifth digit (4 resident, 5 non-resident organisation, 6 joint
+/// entrepreneurship) where an IIN has the first digit of the birth day.
+pub fn entity_type(a_kind: IdentifierT, a_digits: &str) -> EntityT
+{
+  let legal = match a_kind {
+    IdentifierT::Inn => a_digits.len() == 10,
+    IdentifierT::Bin => {
+      matches!(a_digits.chars().nth(4), Some('4' | '5'))
+    }
+  };
+
+  if legal {
+    EntityT::Legal
+  } else {
+    EntityT::Individual
+  }
+}
+
+/// Identifier in canonical form. Exports write them as floats
+/// ("7701234567.0", "7.701234567E+9") and drop leading zeros, leaving 9
+/// or 11 digits. None for placeholders without digits or all zeros;
+/// values that are not numbers are returned trimmed.
+pub fn normalize_identifier(a_value: &str) -> Option<String>
+{
+  let s: String =
+    a_value.chars().filter(|x| !x.is_whitespace()).collect();
+  if !s.chars().any(|x| x.is_ascii_digit() && x != '0') {
+    return None;
+  }
+
+  let s = if s.chars().all(|x| x.is_ascii_digit()) {
+    s
+  } else {
+    match parse_number(&s, '.') {
+      Some(n) if n.fract().is_zero() && n.is_sign_positive() => {
+        n.trunc().normalize().to_string()
+      }
+      _ => return Some(a_value.trim().to_string()),
+    }
+  };
+
+  match s.len() {
+    9 | 11 => Some(format!("0{}", s)),
+    _ => Some(s),
+  }
+}
+
+/// KPP in canonical form: upper case, float formatting and a lost
+/// leading zero undone. None for placeholders without digits.
+pub fn normalize_kpp(a_value: &str) -> Option<String>
+{
+  let s: String = a_value
+    .trim()
+    .trim_end_matches(".0")
+    .chars()
+    .filter(|x| !x.is_whitespace())
+    .collect::<String>()
+    .to_uppercase();
+  if !s.chars().any(|x| x.is_ascii_digit() && x != '0') {
+    return None;
+  }
+
+  match s.len() == 8 && s.chars().all(|x| x.is_ascii_digit()) {
+    true => Some(format!("0{}", s)),
+    false => Some(s),
+  }
+}
+
+/// Whether `a_value` is a KPP: tax office (4 digits), reason (2 digits
+/// or capital letters) and number (3 digits)
+pub fn kpp_valid(a_value: &str) -> bool
+{
+  let c: Vec<char> = a_value.chars().collect();
+
+  c.len() == 9
+    && c[..4].iter().all(|x| x.is_ascii_digit())
+    && c[4..6]
+      .iter()
+      .all(|x| x.is_ascii_digit() || x.is_ascii_uppercase())
+    && c[6..].iter().all(|x| x.is_ascii_digit())
+}
+
+/// Why a normalized identifier is neither an INN nor a BIN/IIN, None
+/// when it is one
+pub fn identifier_issue(a_value: &str) -> Option<String>
+{
+  if !a_value.chars().all(|x| x.is_ascii_digit()) {
+    return Some(format!("Identifier '{}' is not a number", a_value));
+  }
+
+  match a_value.len() {
+    10 if !inn_valid(a_value) => {
+      Some(format!("INN '{}' fails its check digit", a_value))
+    }
+    12 if !inn_valid(a_value) && !bin_valid(a_value) => {
+      Some(format!(
+        "Identifier '{}' fails the INN and BIN/IIN check digits",
+        a_value
+      ))
+    }
+    10 | 12 => None,
+    n => Some(format!(
+      "Identifier '{}' has {} digits, expected 10 or 12",
+      a_value, n
+    )),
+  }
+}
+
+/// Bring the identifiers and KPPs of a converted row to canonical form
+pub fn normalize_identifiers(a_row: &mut GeneralSchema)
+{
+  for x in [
+    &mut a_row.sender_inn,
+    &mut a_row.receiver_inn,
+    &mut a_row.contractor_inn,
+  ] {
+    *x = x.as_deref().and_then(normalize_identifier);
+  }
+  for x in [
+    &mut a_row.sender_kpp,
+    &mut a_row.receiver_kpp,
+    &mut a_row.contractor_kpp,
+  ] {
+    *x = x.as_deref().and_then(normalize_kpp);
+  }
+}
+
+/// Identifiers and KPPs of a normalized row failing their checks, as
+/// (field, reason) pairs
+pub fn check_identifiers(
+  a_row: &GeneralSchema,
+) -> Vec<(String, String)>
+{
+  let mut out = Vec::new();
+
+  for (field, value) in [
+    ("sender_inn", &a_row.sender_inn),
+    ("receiver_inn", &a_row.receiver_inn),
+    ("contractor_inn", &a_row.contractor_inn),
+  ] {
+    if let Some(reason) = value.as_deref().and_then(identifier_issue)
+    {
+      out.push((field.to_string(), reason));
+    }
+  }
+  for (field, value) in [
+    ("sender_kpp", &a_row.sender_kpp),
+    ("receiver_kpp", &a_row.receiver_kpp),
+    ("contractor_kpp", &a_row.contractor_kpp),
+  ] {
+    if let Some(v) = value.as_deref().filter(|x| !kpp_valid(x)) {
+      out.push((field.to_string(), format!("Invalid KPP '{}'", v)));
+    }
+  }
+
+  out
+}
+
+/// Kind and digits of a valid identifier. Twelve digits may be an INN
+/// or a BIN; the kind issued where the segment's declarations are filed
+/// is tried first.
+pub fn parse_identifier(
+  a_value: &str,
+  a_seg: &SegmentT,
+) -> Option<(IdentifierT, String)>
+{
+  let d = normalize_identifier(a_value)?;
+  let order = match a_seg {
+    SegmentT::Kz => [IdentifierT::Bin, IdentifierT::Inn],
+    _ => [IdentifierT::Inn, IdentifierT::Bin],
+  };
+
+  order
+    .into_iter()
+    .find(|t| match t {
+      IdentifierT::Inn => inn_valid(&d),
+      IdentifierT::Bin => bin_valid(&d),
+    })
+    .map(|t| (t, d))
+}
+
+#[cfg(test)]
+mod tests
+{
+  use super::*;
+
+  #[test]
+  fn inn_check_digits()
+  {
+    assert!(inn_valid("7707083893"));
+    assert!(!inn_valid("7707083894"));
+    assert!(inn_valid("500100732259"));
+    assert!(!inn_valid("500100732258"));
+    assert!(!inn_valid("770708389"));
+    assert!(!inn_valid("77070838X3"));
+  }
+
+  #[test]
+  fn bin_check_digit()
+  {
+    assert!(bin_valid("990340003168"));
+    assert!(!bin_valid("990340003167"));
+    assert!(bin_valid("870101500606"));
+    assert!(!bin_valid("7707083893"));
+    assert_eq!(
+      entity_type(IdentifierT::Bin, "990340003168"),
+      EntityT::Legal
+    );
+    assert_eq!(
+      entity_type(IdentifierT::Bin, "870101500606"),
+      EntityT::Individual
+    );
+  }
+
+  #[test]
+  fn identifiers_written_as_floats()
+  {
+    assert_eq!(
+      normalize_identifier("7707083893.0"),
+      Some("7707083893".to_string())
+    );
+    assert_eq!(
+      normalize_identifier("2.74062111E+8"),
+      Some("0274062111".to_string())
+    );
+    assert_eq!(normalize_identifier(" 0000 "), None);
+    assert_eq!(normalize_identifier("-"), None);
+  }
+
+  #[test]
+  fn kpp_forms()
+  {
+    assert_eq!(normalize_kpp("77301001.0"), Some("077301001".into()));
+    assert_eq!(normalize_kpp("7730ab001"), Some("7730AB001".into()));
+    assert_eq!(normalize_kpp("000000000"), None);
+    assert!(kpp_valid("773001001"));
+    assert!(kpp_valid("7730AB001"));
+    assert!(!kpp_valid("77300100"));
+    assert!(!kpp_valid("7730ab001"));
+  }
+}
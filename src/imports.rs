@@ -1,78 +1,375 @@
-use crate::conf::LlmConfig;
-use crate::database::{
-  insert_general_schema, insert_vec_store, parse_date,
+use crate::attributes::extract_attributes;
+use crate::conf::{
+  DensityConfig, DriftModeT, ImportConfig, LlmConfig,
+};
+use crate::currency::update_values;
+use crate::database::{Pool, copy_general_schema, insert_vec_store};
+use crate::drift::{detect_drift, store_drift};
+use crate::entities::resolve_companies;
+use crate::mapping::{Mapping, header_index};
+use crate::runs::{
+  RunStats, fail_run, file_checksum, finish_run, progress_run,
+  rollback_run, start_run,
 };
 use crate::schemas::*;
-use chrono::NaiveDate;
+use crate::sources::open_source;
+use crate::taxonomies::{TAXONOMIES, Taxonomy};
+use crate::tnved::Tnved;
+use crate::units::update_volumes;
+use crate::validation::{
+  ValidatedRow, store_rejects, validate_record,
+};
 use csv::Reader;
+use futures::StreamExt;
 use futures_batch::ChunksTimeoutStreamExt;
 use serde::Deserialize;
 use std::error::Error;
 use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
 use tokio_postgres::Client;
 
+/// A batch is loaded once full or after this long, whichever is first
+const BATCH_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Rejects written to the log per file, all are kept in import_rejects
+const REJECT_LOG_LIMIT: usize = 10;
+
+/// What an import reads and checks its file with
+pub struct ImportSettings<'a>
+{
+  /// Model embedding the labelled rows
+  pub conf_embed: &'a LlmConfig,
+  pub conf_import: &'a ImportConfig,
+  pub conf_density: &'a DensityConfig,
+  pub tnved: &'a Tnved,
+}
+
+/// Import a source file into general_schema through `a_cli` and embed
+/// its newly added labelled rows with connections of `a_pool`, so the
+/// pool needs one more connection than the embedding uses.
+/// Re-importing overlapping exports is safe: rows are
+/// matched on their natural key and only updated when changed. Every
+/// call is recorded in import_runs and can be undone with `rollback`.
+///
+/// The file is streamed in batches of `batch_size` rows, each loaded in
+/// its own transaction. A failed run is rolled back, so the batches
+/// loaded before the failure are removed again. TN VED codes are
+/// normalized and checked against the reference of `a_settings`.
+/// Volumes of the loaded rows are derived with its densities, their
+/// values in RUB and USD with the loaded exchange rates, and their
+/// parties are linked to companies.
 pub async fn read_file(
+  a_cli: &mut Client,
   a_path: &PathBuf,
-  a_seg: SegmentT,
-  a_force_import: bool,
-  a_cli: &Client,
-  a_conf_embed: &LlmConfig,
-) -> Result<usize, Box<dyn Error>>
+  a_mapping: &Mapping,
+  a_pool: &Pool,
+  a_settings: &ImportSettings<'_>,
+) -> Result<RunStats, Box<dyn Error>>
 {
-  let date_res = a_cli.query("SELECT effective_date FROM general_schema WHERE seg = $1 ORDER BY effective_date DESC LIMIT 1", 
-    &[&format!("{:?}", a_seg)]).await?;
+  let checksum = file_checksum(a_path)?;
+  let run_id = start_run(
+    a_cli,
+    a_path,
+    &checksum,
+    Some(format!("{:?}", a_mapping.seg)),
+  )
+  .await?;
+  tracing::info!("{:?}: started import run {}", a_path, run_id);
 
-  let date: NaiveDate;
-
-  if date_res.len() == 0 || a_force_import {
-    date = NaiveDate::from_ymd_opt(2000, 1, 1).unwrap();
-  } else {
-    date = date_res[0].get(0);
+  let res =
+    import_run(a_cli, a_path, a_mapping, a_pool, a_settings, run_id)
+      .await;
+  match res {
+    Ok(stats) => {
+      finish_run(a_cli, &stats).await?;
+      Ok(stats)
+    }
+    Err(e) => {
+      fail_run(a_cli, run_id, &e.to_string()).await?;
+      // Remove the batches loaded before the failure
+      if let Err(x) = rollback_run(a_cli, run_id).await {
+        tracing::error!(
+          "{:?}: could not roll back failed run {}: {}",
+          a_path,
+          run_id,
+          x
+        );
+      }
+      Err(e)
+    }
   }
+}
 
-  let v = GeneralSchema::from_csv_file_path(a_path, a_seg)?;
-
-  let filtered: Vec<GeneralSchema> = v
-    .into_iter()
-    .filter(|x| {
-      parse_date(x.effective_date.clone().unwrap().as_str()).unwrap()
-        > date
-    })
-    .collect();
+async fn import_run(
+  a_cli: &mut Client,
+  a_path: &PathBuf,
+  a_mapping: &Mapping,
+  a_pool: &Pool,
+  a_settings: &ImportSettings<'_>,
+  a_run_id: i32,
+) -> Result<RunStats, Box<dyn Error>>
+{
+  let conf_import = a_settings.conf_import;
+  let mut out = RunStats {
+    run_id: a_run_id,
+    ..Default::default()
+  };
 
   //------------------------------------//
-  // Store new values                   //
+  // Compare with the mapping           //
   //------------------------------------//
-  for val in filtered.iter() {
-    insert_general_schema(a_cli, val).await?;
+  let drift =
+    detect_drift(a_path, a_mapping, conf_import.drift_sample_rows)?;
+  if !drift.is_empty() {
+    store_drift(a_cli, a_run_id, &drift).await?;
+    match conf_import.drift {
+      DriftModeT::Strict => {
+        return Err(
+          format!(
+            "Schema drift, import refused: {}",
+            drift.summary()
+          )
+          .into(),
+        );
+      }
+      DriftModeT::Lenient => {
+        tracing::warn!(
+          "{:?}: schema drift: {}",
+          a_path,
+          drift.summary()
+        );
+      }
+    }
   }
 
   //------------------------------------//
-  // Store new vector items             //
+  // Read, load and embed in batches    //
   //------------------------------------//
-  let only_known: Vec<GeneralSchema> = filtered
-    .into_iter()
-    .filter(|x| {
-      x.eval.clone().unwrap() != "NoEval"
-        && x.eval.clone().unwrap() != "NoNeed"
-    })
-    .collect();
-
-  for vecs in only_known.chunks(500) {
-    let mut tasks = Vec::new();
-    for vec in vecs {
-      tasks.push(insert_vec_store(a_cli, vec, a_conf_embed));
+  let source = open_source(a_path, &a_mapping.input)?;
+  let index = header_index(&source.headers);
+  let dialect = source.dialect;
+  let mapping = a_mapping.clone();
+  let tnved = a_settings.tnved.clone();
+  let batch_size = conf_import.batch_size.max(1);
+
+  // The reader blocks while a full batch waits to be loaded, so only a
+  // couple of batches are held in memory whatever the file size
+  let (tx, mut rx) = mpsc::channel::<ValidatedRow>(batch_size);
+  let reader = tokio::task::spawn_blocking(move || {
+    for record in source.rows {
+      let row =
+        validate_record(&mapping, &index, &dialect, &tnved, record);
+      if tx.blocking_send(row).is_err() {
+        // The import failed and stopped receiving
+        break;
+      }
+    }
+  });
+
+  let mut batches =
+    futures::stream::poll_fn(move |cx| rx.poll_recv(cx))
+      .chunks_timeout(batch_size, BATCH_TIMEOUT);
+  let mut logged = 0;
+
+  while let Some(batch) = batches.next().await {
+    let started = Instant::now();
+    out.read += batch.len() as u64;
+
+    let mut v = Vec::with_capacity(batch.len());
+    let mut rejects = Vec::new();
+    for row in batch {
+      match row {
+        Ok((x, flagged)) => {
+          v.push(x);
+          rejects.extend(flagged);
+        }
+        Err(x) => {
+          out.rejected += 1;
+          rejects.extend(x);
+        }
+      }
     }
 
-    for r in futures::future::join_all(tasks).await {
-      if !r.is_ok() {
+    if !rejects.is_empty() {
+      for r in rejects.iter().take(REJECT_LOG_LIMIT - logged) {
         tracing::warn!(
-          "Error on reading item: {:?}",
-          r.err().unwrap()
+          "{:?}: {} line {} column {}: {}",
+          a_path,
+          if r.loaded { "flagged" } else { "rejected" },
+          r.line.unwrap_or_default(),
+          r.column.clone().unwrap_or_default(),
+          r.reason
         );
+        logged += 1;
       }
+      store_rejects(a_cli, a_run_id, &rejects).await?;
     }
+
+    let stats = copy_general_schema(a_cli, &v, a_run_id).await?;
+    out.inserted += stats.inserted;
+    out.updated += stats.updated;
+    out.load_ms += started.elapsed().as_millis() as i64;
+
+    //------------------------------------//
+    // Store new vector items             //
+    //------------------------------------//
+    let started = Instant::now();
+    // One example per new row and taxonomy it is labelled by
+    let only_known: Vec<(&GeneralSchema, &Taxonomy)> = v
+      .iter()
+      .filter(|x| match x.natural_key() {
+        Some(k) => stats.inserted_keys.contains(&k),
+        None => true,
+      })
+      .flat_map(|x| {
+        TAXONOMIES
+          .iter()
+          .filter(|t| t.label_of(x).is_some())
+          .map(move |t| (x, t))
+      })
+      .collect();
+
+    for vecs in only_known.chunks(500) {
+      let mut tasks = Vec::new();
+      for (vec, taxonomy) in vecs {
+        tasks.push(async move {
+          let conn = a_pool.get().await?;
+          insert_vec_store(
+            &conn,
+            vec,
+            taxonomy,
+            a_settings.conf_embed,
+            Some(a_run_id),
+          )
+          .await
+        });
+      }
+
+      for r in futures::future::join_all(tasks).await {
+        match r {
+          Ok(true) => out.embedded += 1,
+          Ok(false) => {}
+          Err(e) => {
+            tracing::warn!("Error on reading item: {:?}", e);
+          }
+        }
+      }
+    }
+    out.embed_ms += started.elapsed().as_millis() as i64;
+
+    progress_run(a_cli, &out).await?;
+    tracing::info!(
+      "{:?}: read {} inserted {} updated {} rejected {}",
+      a_path,
+      out.read,
+      out.inserted,
+      out.updated,
+      out.rejected
+    );
   }
+  reader.await?;
+  update_volumes(a_cli, a_settings.conf_density, Some(a_run_id))
+    .await?;
+  update_values(a_cli, Some(a_run_id)).await?;
+  resolve_companies(a_cli, Some(a_run_id)).await?;
+  extract_attributes(a_cli, Some(a_run_id)).await?;
 
-  Ok(only_known.len())
+  if out.rejected > 0 {
+    tracing::warn!(
+      "{:?}: {} rows rejected, see import_rejects for run {}",
+      a_path,
+      out.rejected,
+      a_run_id
+    );
+  }
+
+  Ok(out)
+}
+
+#[cfg(test)]
+mod tests
+{
+  use super::*;
+  use crate::conf::{load_config, load_llm_config};
+  use crate::database::connect_to_database;
+  use crate::mapping::builtin_mapping;
+  use crate::migrations::init_database;
+  use crate::tnved::store_tnved;
+
+  const CONF: &str = "configs/test.ini";
+
+  /// Runs against the empty database of the POLY_CLS_TEST_DB url
+  #[tokio::test]
+  async fn failed_run_leaves_no_batch()
+  {
+    let Ok(url) = std::env::var("POLY_CLS_TEST_DB") else {
+      return;
+    };
+    let mut conf = load_config(&CONF.to_string()).unwrap();
+    let llm = load_llm_config(&CONF.to_string()).unwrap();
+    conf.postgres.url = Some(url);
+    conf.import.batch_size = 2;
+
+    let pool = connect_to_database(&conf.postgres).await.unwrap();
+    let mut cli = pool.get().await.unwrap();
+    init_database(&mut cli, false).await.unwrap();
+    let tnved = Tnved::load(&conf.tnved).unwrap();
+    store_tnved(&cli, &tnved).await.unwrap();
+
+    // Two batches load, the third fails
+    cli
+      .batch_execute(
+        "CREATE FUNCTION fail_third_batch() RETURNS trigger AS $$
+        BEGIN
+          IF (SELECT count(*) FROM general_schema
+            WHERE import_run_id = NEW.import_run_id) >= 4 THEN
+            RAISE EXCEPTION 'third batch';
+          END IF;
+          RETURN NEW;
+        END $$ LANGUAGE plpgsql;
+        CREATE TRIGGER fail_third_batch BEFORE INSERT ON general_schema
+          FOR EACH ROW EXECUTE FUNCTION fail_third_batch();",
+      )
+      .await
+      .unwrap();
+
+    let settings = ImportSettings {
+      conf_embed: &llm[1],
+      conf_import: &conf.import,
+      conf_density: &conf.density,
+      tnved: &tnved,
+    };
+    let res = read_file(
+      &mut cli,
+      &PathBuf::from("samples/rus_customs_2.csv"),
+      &builtin_mapping(&SegmentT::Rus),
+      &pool,
+      &settings,
+    )
+    .await;
+
+    cli
+      .batch_execute(
+        "DROP TRIGGER fail_third_batch ON general_schema;
+        DROP FUNCTION fail_third_batch();",
+      )
+      .await
+      .unwrap();
+    assert!(res.is_err());
+
+    let row = cli
+      .query_one(
+        "SELECT r.status, r.rows_inserted, \
+          (SELECT count(*) FROM general_schema g \
+          WHERE g.import_run_id = r.id) \
+        FROM import_runs r ORDER BY r.id DESC LIMIT 1",
+        &[],
+      )
+      .await
+      .unwrap();
+    assert_eq!(row.get::<_, String>(0), "rolled_back");
+    assert_eq!(row.get::<_, i64>(1), 4);
+    assert_eq!(row.get::<_, i64>(2), 0);
+  }
 }
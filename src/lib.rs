@@ -1,6 +1,23 @@
 pub mod agents;
+pub mod attributes;
 pub mod cls;
 pub mod conf;
+pub mod countries;
+pub mod currency;
 pub mod database;
+pub mod dates;
+pub mod dialect;
+pub mod drift;
+pub mod entities;
+pub mod identifiers;
 pub mod imports;
+pub mod mapping;
+pub mod migrations;
+pub mod runs;
 pub mod schemas;
+pub mod sources;
+pub mod taxonomies;
+pub mod text;
+pub mod tnved;
+pub mod units;
+pub mod validation;
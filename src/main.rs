@@ -1,8 +1,25 @@
 pub mod agents;
+pub mod attributes;
 pub mod conf;
+pub mod countries;
+pub mod currency;
 pub mod database;
+pub mod dates;
+pub mod dialect;
+pub mod drift;
+pub mod entities;
+pub mod identifiers;
 pub mod imports;
+pub mod mapping;
+pub mod migrations;
+pub mod runs;
 pub mod schemas;
+pub mod sources;
+pub mod taxonomies;
+pub mod text;
+pub mod tnved;
+pub mod units;
+pub mod validation;
 
 use crate::agents::embed_item;
 
@@ -29,13 +46,10 @@ async fn main() -> Result<(), Box<dyn std::error::Error>>
       .expect("Wrong llm config");
 
   // Connect to database using config details
-  let client = connect_to_database(
-    &config.postgres.host,
-    &config.postgres.user,
-    &config.postgres.psswd,
-  )
-  .await
-  .expect("Postgres error");
+  let pool = connect_to_database(&config.postgres)
+    .await
+    .expect("Postgres error");
+  let client = pool.get().await.expect("Postgres error");
 
   let path = PathBuf::from("samples/eas_dates.csv");
 
@@ -0,0 +1,727 @@
+use crate::countries::normalize_countries;
+use crate::database::GENERAL_SCHEMA_COLUMNS;
+use crate::dates::{
+  CELL_DATE_FORMATS, DEFAULT_DATE_FORMATS, is_period_format,
+  month_start, parse_date_as,
+};
+use crate::dialect::{Dialect, normalize_decimal};
+use crate::identifiers::normalize_identifiers;
+use crate::schemas::*;
+use crate::sources::{InputOptions, open_source};
+use crate::text::preprocess_description;
+use crate::units::normalize_units;
+use crate::validation::{
+  DATE_FIELDS, INTEGER_FIELDS, NUMERIC_FIELDS, parse_number,
+};
+use config::{Config, File, FileFormat};
+use csv::StringRecord;
+use rust_decimal::Decimal;
+use rust_decimal::prelude::ToPrimitive;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+/// Directory searched for mapping files given by name
+pub const MAPPINGS_DIR: &str = "mappings";
+
+/// Mappings shipped with the binary, overridable by a file of the same
+/// name in `MAPPINGS_DIR`
+const BUILTIN_MAPPINGS: &[(&str, &str)] = &[
+  ("eas", include_str!("../mappings/eas.toml")),
+  ("kz", include_str!("../mappings/kz.toml")),
+  ("rus", include_str!("../mappings/rus.toml")),
+];
+
+/// How a single general field is filled
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct FieldRule
+{
+  #[serde(default)]
+  pub source: Option<String>,
+  /// Columns joined with `separator`, after `source` if both are set
+  #[serde(default)]
+  pub sources: Vec<String>,
+  #[serde(default)]
+  pub separator: Option<String>,
+  /// chrono format of the source date, stored as ISO date. A format
+  /// without a day ("%Y-%m") reads a month as its first day, "excel" an
+  /// Excel serial date.
+  #[serde(default)]
+  pub date_format: Option<String>,
+  /// Further formats tried with `date_format`; a value read as
+  /// different days by two of them is rejected as ambiguous
+  #[serde(default)]
+  pub date_formats: Vec<String>,
+  /// Factor applied to a numeric value, e.g. 1000 for tonnes to kg
+  #[serde(default)]
+  pub scale: Option<f64>,
+  /// Value used when the source columns are empty or missing
+  #[serde(default)]
+  pub default: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum FieldSpec
+{
+  Source(String),
+  Rule(FieldRule),
+}
+
+impl FieldSpec
+{
+  pub fn rule(&self) -> FieldRule
+  {
+    match self {
+      FieldSpec::Source(x) => FieldRule {
+        source: Some(x.clone()),
+        ..Default::default()
+      },
+      FieldSpec::Rule(x) => x.clone(),
+    }
+  }
+}
+
+/// A source format described by a mapping file.
+///
+/// A mapping is a TOML file assigning general_schema fields to source
+/// columns:
+///
+/// ```toml
+/// name = "eas"
+/// seg = "Eas"
+/// date_formats = ["%m/%d/%Y", "excel"]
+///
+/// [fields]
+/// declaration_number = "Регистрационный №"
+/// sheet_number = { default = "0" }
+/// effective_date = { source = "Дата", date_format = "%d.%m.%Y" }
+/// movement_period = { source = "Месяц", date_format = "%Y-%m" }
+/// sender_address = { sources = ["Город", "Улица"], separator = ", " }
+/// net_weight = { source = "Вес, т", scale = 1000 }
+/// ```
+///
+/// A plain string names the source column. A table can combine several
+/// columns, parse dates with explicit formats, scale numbers and fall
+/// back to a default when the source is empty. Top-level `date_formats`
+/// apply to every date field without formats of its own; without any,
+/// dates are read with `DEFAULT_DATE_FORMATS`. Fields not listed are
+/// left empty. Top-level `ignored` lists known columns that are not
+/// loaded and `optional` the columns a file may lack. An `[input]` table
+/// sets the worksheet and the header row of the source file, and may fix
+/// the `encoding`, `delimiter`, `quote` and `decimal` of CSV files that
+/// are otherwise detected.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Mapping
+{
+  pub name: String,
+  /// Segment the rows are stored under
+  pub seg: SegmentT,
+  /// Known source columns that are deliberately not loaded
+  #[serde(default)]
+  pub ignored: Vec<String>,
+  /// Source columns that may be absent without being reported missing
+  #[serde(default)]
+  pub optional: Vec<String>,
+  /// Sheet, header position and CSV dialect, `[input]` table
+  #[serde(default)]
+  pub input: InputOptions,
+  /// Formats of the date fields without formats of their own
+  #[serde(default)]
+  pub date_formats: Vec<String>,
+  /// general_schema field -> source
+  pub fields: HashMap<String, FieldSpec>,
+}
+
+/// Position of every column in a header row, by trimmed name. The first
+/// occurrence wins for duplicated names.
+pub fn header_index(
+  a_headers: &StringRecord,
+) -> HashMap<String, usize>
+{
+  let mut out = HashMap::new();
+  for (i, h) in a_headers.iter().enumerate() {
+    out.entry(h.trim().to_string()).or_insert(i);
+  }
+
+  out
+}
+
+impl Mapping
+{
+  /// Parse a mapping from TOML text
+  pub fn from_toml(a_src: &str) -> Result<Self, Box<dyn Error>>
+  {
+    let config = Config::builder()
+      .add_source(File::from_str(a_src, FileFormat::Toml))
+      .build()?;
+    let mapping: Mapping = config.try_deserialize()?;
+    mapping.check()?;
+
+    Ok(mapping)
+  }
+
+  pub fn from_file(a_path: &Path) -> Result<Self, Box<dyn Error>>
+  {
+    let src = std::fs::read_to_string(a_path)?;
+    Self::from_toml(&src)
+      .map_err(|e| format!("Mapping file {:?}: {}", a_path, e).into())
+  }
+
+  fn check(&self) -> Result<(), Box<dyn Error>>
+  {
+    for (field, spec) in &self.fields {
+      if !GENERAL_SCHEMA_COLUMNS.contains(&field.as_str())
+        || field == "seg"
+        || field == "natural_key"
+      {
+        return Err(
+          format!("Mapping {}: unknown field '{}'", self.name, field)
+            .into(),
+        );
+      }
+
+      let rule = spec.rule();
+      if rule.source.is_none()
+        && rule.sources.is_empty()
+        && rule.default.is_none()
+      {
+        return Err(
+          format!(
+            "Mapping {}: field '{}' has neither a source nor a default",
+            self.name, field
+          )
+          .into(),
+        );
+      }
+    }
+
+    Ok(())
+  }
+
+  /// Every source column the mapping reads from
+  pub fn source_columns(&self) -> Vec<String>
+  {
+    let mut out: Vec<String> = Vec::new();
+    for spec in self.fields.values() {
+      let rule = spec.rule();
+      for c in rule.source.iter().chain(rule.sources.iter()) {
+        if !out.contains(c) {
+          out.push(c.clone());
+        }
+      }
+    }
+    out.sort();
+
+    out
+  }
+
+  /// Date formats of a field: its own, or the mapping's for date
+  /// fields and `DEFAULT_DATE_FORMATS` when it sets none, and the forms
+  /// of spreadsheet date cells when the file has them. None when the
+  /// field is not a date.
+  pub fn date_formats_of(
+    &self,
+    a_field: &str,
+    a_rule: &FieldRule,
+    a_dialect: &Dialect,
+  ) -> Option<Vec<String>>
+  {
+    let own: Vec<String> = a_rule
+      .date_format
+      .iter()
+      .chain(a_rule.date_formats.iter())
+      .cloned()
+      .collect();
+
+    let mut out = if !own.is_empty() {
+      own
+    } else if !DATE_FIELDS.contains(&a_field) {
+      return None;
+    } else if !self.date_formats.is_empty() {
+      self.date_formats.clone()
+    } else {
+      DEFAULT_DATE_FORMATS.iter().map(|x| x.to_string()).collect()
+    };
+
+    if a_dialect.date_cells {
+      for f in CELL_DATE_FORMATS {
+        if !out.iter().any(|x| x == f) {
+          out.push(f.to_string());
+        }
+      }
+    }
+
+    Some(out)
+  }
+
+  /// Convert one source record, parsing dates and numbers into their
+  /// field types. Numbers written with a decimal ',' are rewritten
+  /// with a decimal point first; quantities, identifiers and countries
+  /// are normalized and the description prepared. On failure returns
+  /// the offending source column and the reason.
+  pub fn apply(
+    &self,
+    a_index: &HashMap<String, usize>,
+    a_rec: &StringRecord,
+    a_dialect: &Dialect,
+  ) -> Result<GeneralSchema, (String, String)>
+  {
+    let mut values = serde_json::Map::new();
+    values.insert(
+      "seg".to_string(),
+      serde_json::to_value(&self.seg)
+        .map_err(|e| ("".to_string(), e.to_string()))?,
+    );
+
+    for (field, spec) in &self.fields {
+      let rule = spec.rule();
+      let columns: Vec<&String> =
+        rule.source.iter().chain(rule.sources.iter()).collect();
+      let column = columns
+        .first()
+        .map(|x| x.to_string())
+        .unwrap_or(field.clone());
+
+      let positions: Vec<usize> = columns
+        .iter()
+        .filter_map(|x| a_index.get(x.trim()).copied())
+        .collect();
+      let parts: Vec<&str> = positions
+        .iter()
+        .filter_map(|x| a_rec.get(*x))
+        .filter(|x| !x.trim().is_empty())
+        .collect();
+
+      let value = if parts.is_empty() {
+        match &rule.default {
+          Some(x) => x.clone(),
+          None => continue,
+        }
+      } else {
+        let mut joined =
+          parts.join(rule.separator.as_deref().unwrap_or(" "));
+        if a_dialect.decimal == ','
+          && (rule.scale.is_some()
+            || NUMERIC_FIELDS.contains(&field.as_str()))
+        {
+          let grouped = positions
+            .iter()
+            .any(|x| a_dialect.dot_grouped.contains(x));
+          joined = normalize_decimal(&joined, grouped);
+        }
+
+        if let Some(formats) =
+          self.date_formats_of(field, &rule, a_dialect)
+        {
+          // A period read from a date cell is still its month
+          let period = a_dialect.date_cells
+            && formats.iter().any(|x| is_period_format(x));
+          match parse_date_as(joined.trim(), &formats) {
+            Ok(d) if period => {
+              month_start(d).format("%Y-%m-%d").to_string()
+            }
+            Ok(d) => d.format("%Y-%m-%d").to_string(),
+            Err(e) => return Err((column, e)),
+          }
+        } else if let Some(scale) = rule.scale {
+          match (
+            parse_number(&joined, a_dialect.decimal),
+            Decimal::try_from(scale),
+          ) {
+            (Some(n), Ok(k)) => (n * k).normalize().to_string(),
+            _ => {
+              return Err((
+                column,
+                format!(
+                  "Cannot scale non-numeric value '{}'",
+                  joined
+                ),
+              ));
+            }
+          }
+        } else {
+          joined
+        }
+      };
+
+      let value = typed_value(field, value, a_dialect.decimal)
+        .map_err(|e| (column, e))?;
+      values.insert(field.clone(), value);
+    }
+
+    let mut row: GeneralSchema =
+      serde_json::from_value(serde_json::Value::Object(values))
+        .map_err(|e| ("".to_string(), e.to_string()))?;
+    normalize_units(&mut row);
+    normalize_identifiers(&mut row);
+    normalize_countries(&mut row);
+    preprocess_description(&mut row);
+    if row.movement_period.is_none() {
+      row.movement_period = row.effective_date.map(month_start);
+    }
+
+    Ok(row)
+  }
+}
+
+/// JSON value of a GeneralSchema field: dates as ISO dates, numbers
+/// written with `a_decimal` in canonical form and everything else as
+/// the text itself
+fn typed_value(
+  a_field: &str,
+  a_value: String,
+  a_decimal: char,
+) -> Result<serde_json::Value, String>
+{
+  if DATE_FIELDS.contains(&a_field) {
+    return parse_date_as(a_value.trim(), DEFAULT_DATE_FORMATS)
+      .map(|d| d.format("%Y-%m-%d").to_string().into());
+  }
+  if !NUMERIC_FIELDS.contains(&a_field) {
+    return Ok(a_value.into());
+  }
+
+  let n = match parse_number(&a_value, a_decimal) {
+    Some(x) => x,
+    None => return Err(format!("Unparseable number '{}'", a_value)),
+  };
+  if INTEGER_FIELDS.contains(&a_field) {
+    return match n.fract().is_zero().then(|| n.to_i64()).flatten() {
+      Some(x) => Ok(x.into()),
+      None => {
+        Err(format!("Expected a whole number, got '{}'", a_value))
+      }
+    };
+  }
+
+  Ok(n.to_string().into())
+}
+
+/// The shipped mapping of a segment
+pub fn builtin_mapping(a_seg: &SegmentT) -> Mapping
+{
+  let name = format!("{:?}", a_seg).to_lowercase();
+  let (_, src) = BUILTIN_MAPPINGS
+    .iter()
+    .find(|(n, _)| *n == name)
+    .expect("Every segment has a built-in mapping");
+
+  Mapping::from_toml(src).expect("Built-in mapping is valid")
+}
+
+/// All mappings known by name: the built-in ones, replaced or extended
+/// by the files in `MAPPINGS_DIR`
+pub fn known_mappings() -> Result<Vec<Mapping>, Box<dyn Error>>
+{
+  let mut out = Vec::new();
+  for (_, src) in BUILTIN_MAPPINGS {
+    out.push(Mapping::from_toml(src)?);
+  }
+
+  if let Ok(dir) = std::fs::read_dir(MAPPINGS_DIR) {
+    for entry in dir {
+      let path = entry?.path();
+      if path.extension().and_then(|x| x.to_str()) != Some("toml") {
+        continue;
+      }
+      let m = Mapping::from_file(&path)?;
+      out.retain(|x: &Mapping| x.name != m.name);
+      out.push(m);
+    }
+  }
+
+  Ok(out)
+}
+
+/// Find a mapping by file path or by name (case-insensitive). Names are
+/// looked up in `MAPPINGS_DIR` first, then among the built-in mappings.
+pub fn load_mapping(a_name: &str) -> Result<Mapping, Box<dyn Error>>
+{
+  let path = PathBuf::from(a_name);
+  if path.extension().and_then(|x| x.to_str()) == Some("toml") {
+    return Mapping::from_file(&path);
+  }
+
+  let name = a_name.to_lowercase();
+  let path = Path::new(MAPPINGS_DIR).join(format!("{}.toml", name));
+  if path.exists() {
+    return Mapping::from_file(&path);
+  }
+
+  match BUILTIN_MAPPINGS.iter().find(|(n, _)| *n == name) {
+    Some((_, src)) => Mapping::from_toml(src),
+    None => Err(format!("Unknown mapping '{}'", a_name).into()),
+  }
+}
+
+/// Share of a mapping's known columns a file must contain to be
+/// recognized as that format
+pub const MIN_MATCH_SCORE: f64 = 0.6;
+
+/// How well a header row fits a mapping
+#[derive(Debug, Clone)]
+pub struct FormatMatch
+{
+  pub mapping: Mapping,
+  /// Share of the mapping's known columns present in the file, 0..1
+  pub score: f64,
+  /// Known columns of the mapping absent from the file
+  pub missing: Vec<String>,
+}
+
+impl FormatMatch
+{
+  pub fn is_match(&self) -> bool
+  {
+    self.score >= MIN_MATCH_SCORE
+  }
+}
+
+/// Header row of a source file
+pub fn read_headers(
+  a_path: &PathBuf,
+  a_opts: &InputOptions,
+) -> Result<StringRecord, Box<dyn Error>>
+{
+  Ok(open_source(a_path, a_opts)?.headers)
+}
+
+/// Score a header row against a mapping. Both loaded and ignored
+/// columns count, so files carrying every known column score 1.
+pub fn score_mapping(
+  a_mapping: &Mapping,
+  a_headers: &StringRecord,
+) -> FormatMatch
+{
+  let index = header_index(a_headers);
+  let mut known = a_mapping.source_columns();
+  for c in &a_mapping.ignored {
+    if !known.contains(c) {
+      known.push(c.clone());
+    }
+  }
+
+  let missing: Vec<String> = known
+    .iter()
+    .filter(|x| !index.contains_key(x.trim()))
+    .filter(|x| !a_mapping.optional.contains(x))
+    .cloned()
+    .collect();
+  known.retain(|x| {
+    index.contains_key(x.trim()) || !a_mapping.optional.contains(x)
+  });
+  let score = if known.is_empty() {
+    0.0
+  } else {
+    (known.len() - missing.len()) as f64 / known.len() as f64
+  };
+
+  FormatMatch {
+    mapping: a_mapping.clone(),
+    score,
+    missing,
+  }
+}
+
+/// Every known mapping scored against the header row of a file, best
+/// match first
+pub fn rank_mappings(
+  a_path: &PathBuf,
+) -> Result<Vec<FormatMatch>, Box<dyn Error>>
+{
+  // Mappings may place the header differently, read each layout once
+  let mut headers: Vec<(InputOptions, StringRecord)> = Vec::new();
+  let mut out: Vec<FormatMatch> = Vec::new();
+  for m in known_mappings()? {
+    let found = headers.iter().find(|(o, _)| *o == m.input);
+    let h = match found {
+      Some((_, h)) => h.clone(),
+      None => match read_headers(a_path, &m.input) {
+        Ok(h) => {
+          headers.push((m.input.clone(), h.clone()));
+          h
+        }
+        // e.g. the sheet of this mapping does not exist in the file
+        Err(_) => continue,
+      },
+    };
+    out.push(score_mapping(&m, &h));
+  }
+  out.sort_by(|a, b| b.score.total_cmp(&a.score));
+
+  Ok(out)
+}
+
+fn describe_missing(a_match: &FormatMatch) -> String
+{
+  let shown: Vec<&str> = a_match
+    .missing
+    .iter()
+    .take(10)
+    .map(|x| x.as_str())
+    .collect();
+  let more = a_match.missing.len() - shown.len();
+  if more > 0 {
+    format!("{} and {} more", shown.join(", "), more)
+  } else {
+    shown.join(", ")
+  }
+}
+
+/// Pick the mapping of a file from its header row. Fails with the
+/// closest format and its missing columns when nothing fits well.
+pub fn detect_mapping(
+  a_path: &PathBuf,
+) -> Result<FormatMatch, Box<dyn Error>>
+{
+  let ranked = rank_mappings(a_path)?;
+  let best = match ranked.into_iter().next() {
+    Some(x) => x,
+    None => {
+      return Err(
+        format!(
+          "No mapping could read the header row of {:?}",
+          a_path
+        )
+        .into(),
+      );
+    }
+  };
+
+  if !best.is_match() {
+    return Err(
+      format!(
+        "Could not detect the format of {:?}: closest is {} with {:.0}% \
+         of its columns, missing: {}",
+        a_path,
+        best.mapping.name,
+        best.score * 100.0,
+        describe_missing(&best)
+      )
+      .into(),
+    );
+  }
+
+  Ok(best)
+}
+
+/// Check a file against an explicitly chosen mapping, suggesting the
+/// detected format when it does not fit
+pub fn check_mapping(
+  a_path: &PathBuf,
+  a_mapping: &Mapping,
+) -> Result<FormatMatch, Box<dyn Error>>
+{
+  let headers = read_headers(a_path, &a_mapping.input)?;
+  let m = score_mapping(a_mapping, &headers);
+  if m.is_match() {
+    return Ok(m);
+  }
+
+  let hint = match detect_mapping(a_path) {
+    Ok(x) => format!(", it looks like {}", x.mapping.name),
+    Err(_) => "".to_string(),
+  };
+  Err(
+    format!(
+      "{:?} does not match mapping {}: {:.0}% of its columns found, \
+       missing: {}{}",
+      a_path,
+      a_mapping.name,
+      m.score * 100.0,
+      describe_missing(&m),
+      hint
+    )
+    .into(),
+  )
+}
+
+#[cfg(test)]
+mod tests
+{
+  use super::*;
+
+  const MAPPING: &str = r#"
+name = "test"
+seg = "Rus"
+ignored = ["Примечание"]
+optional = ["Улица"]
+
+[fields]
+declaration_number = "Номер ДТ"
+sheet_number = { default = "0" }
+effective_date = { source = "Дата", date_format = "%d.%m.%Y" }
+sender_address = { sources = ["Город", "Улица"], separator = ", " }
+net_weight = { source = "Вес, т", scale = 1000 }
+"#;
+
+  fn headers(a_names: &[&str]) -> StringRecord
+  {
+    StringRecord::from(a_names.to_vec())
+  }
+
+  #[test]
+  fn score_counts_known_columns()
+  {
+    let m = Mapping::from_toml(MAPPING).unwrap();
+    let all =
+      ["Номер ДТ", "Дата", "Город", "Улица", "Вес, т", "Примечание"];
+
+    let s = score_mapping(&m, &headers(&all));
+    assert_eq!(s.score, 1.0);
+    assert!(s.missing.is_empty());
+
+    // Optional columns are not missed, ignored ones are
+    let s = score_mapping(&m, &headers(&all[..3]));
+    assert_eq!(s.missing, vec!["Вес, т", "Примечание"]);
+    assert_eq!(s.score, 0.6);
+    assert!(s.is_match());
+
+    let s = score_mapping(&m, &headers(&["Дата", " Город "]));
+    assert_eq!(s.score, 0.4);
+    assert!(!s.is_match());
+  }
+
+  #[test]
+  fn builtin_mappings_tell_formats_apart()
+  {
+    let eas = builtin_mapping(&SegmentT::Eas);
+    let rus = builtin_mapping(&SegmentT::Rus);
+    let cols: Vec<String> = eas.source_columns();
+    let h = StringRecord::from(cols);
+
+    assert!(score_mapping(&eas, &h).is_match());
+    assert!(!score_mapping(&rus, &h).is_match());
+  }
+
+  #[test]
+  fn apply_rules()
+  {
+    let m = Mapping::from_toml(MAPPING).unwrap();
+    let h =
+      headers(&["Номер ДТ", "Дата", "Город", "Улица", "Вес, т"]);
+    let rec = headers(&[
+      "10005020/010124/0000001",
+      "09.01.2024",
+      "Минск",
+      "",
+      "1,25",
+    ]);
+    let dialect = Dialect { decimal: ',', ..Default::default() };
+
+    let row = m.apply(&header_index(&h), &rec, &dialect).unwrap();
+    assert_eq!(row.sheet_number.as_deref(), Some("0"));
+    assert_eq!(row.sender_address.as_deref(), Some("Минск"));
+    assert_eq!(row.net_weight, Decimal::from_str_exact("1250").ok());
+    assert_eq!(
+      row.movement_period,
+      chrono::NaiveDate::from_ymd_opt(2024, 1, 1)
+    );
+
+    let rec = headers(&["1", "2024-01-09", "", "", ""]);
+    let err = m.apply(&header_index(&h), &rec, &dialect).unwrap_err();
+    assert_eq!(err.0, "Дата");
+  }
+}
@@ -0,0 +1,307 @@
+use std::error::Error;
+use tokio_postgres::Client;
+
+/// A numbered SQL migration embedded into the binary
+pub struct Migration
+{
+  pub version: i32,
+  pub name: &'static str,
+  pub sql: &'static str,
+}
+
+/// All known migrations, ordered by version. New schema changes are
+/// added as a new file under `migrations/` and appended here; already
+/// released files must never be edited.
+pub const MIGRATIONS: &[Migration] = &[
+  Migration {
+    version: 1,
+    name: "general_schema",
+    sql: include_str!("../migrations/0001_general_schema.sql"),
+  },
+  Migration {
+    version: 2,
+    name: "poly_docs",
+    sql: include_str!("../migrations/0002_poly_docs.sql"),
+  },
+  Migration {
+    version: 3,
+    name: "natural_key",
+    sql: include_str!("../migrations/0003_natural_key.sql"),
+  },
+  Migration {
+    version: 4,
+    name: "import_runs",
+    sql: include_str!("../migrations/0004_import_runs.sql"),
+  },
+  Migration {
+    version: 5,
+    name: "import_rejects",
+    sql: include_str!("../migrations/0005_import_rejects.sql"),
+  },
+  Migration {
+    version: 6,
+    name: "import_drift",
+    sql: include_str!("../migrations/0006_import_drift.sql"),
+  },
+  Migration {
+    version: 7,
+    name: "import_batches",
+    sql: include_str!("../migrations/0007_import_batches.sql"),
+  },
+  Migration {
+    version: 8,
+    name: "typed_columns",
+    sql: include_str!("../migrations/0008_typed_columns.sql"),
+  },
+  Migration {
+    version: 9,
+    name: "units",
+    sql: include_str!("../migrations/0009_units.sql"),
+  },
+  Migration {
+    version: 10,
+    name: "currency",
+    sql: include_str!("../migrations/0010_currency.sql"),
+  },
+  Migration {
+    version: 11,
+    name: "companies",
+    sql: include_str!("../migrations/0011_companies.sql"),
+  },
+  Migration {
+    version: 12,
+    name: "identifiers",
+    sql: include_str!("../migrations/0012_identifiers.sql"),
+  },
+  Migration {
+    version: 13,
+    name: "countries",
+    sql: include_str!("../migrations/0013_countries.sql"),
+  },
+  Migration {
+    version: 14,
+    name: "tnved",
+    sql: include_str!("../migrations/0014_tnved.sql"),
+  },
+  Migration {
+    version: 15,
+    name: "periods",
+    sql: include_str!("../migrations/0015_periods.sql"),
+  },
+  Migration {
+    version: 16,
+    name: "text",
+    sql: include_str!("../migrations/0016_text.sql"),
+  },
+  Migration {
+    version: 17,
+    name: "attributes",
+    sql: include_str!("../migrations/0017_attributes.sql"),
+  },
+  Migration {
+    version: 18,
+    name: "taxonomies",
+    sql: include_str!("../migrations/0018_taxonomies.sql"),
+  },
+  Migration {
+    version: 19,
+    name: "cls_decisions",
+    sql: include_str!("../migrations/0019_cls_decisions.sql"),
+  },
+  Migration {
+    version: 20,
+    name: "label_provenance",
+    sql: include_str!("../migrations/0020_label_provenance.sql"),
+  },
+  Migration {
+    version: 21,
+    name: "eas_line_key",
+    sql: include_str!("../migrations/0021_eas_line_key.sql"),
+  },
+  Migration {
+    version: 22,
+    name: "label_lock",
+    sql: include_str!("../migrations/0022_label_lock.sql"),
+  },
+  Migration {
+    version: 23,
+    name: "company_runs",
+    sql: include_str!("../migrations/0023_company_runs.sql"),
+  },
+];
+
+/// Tables owned by the migrations, dropped on purge
+const MANAGED_TABLES: &[&str] = &[
+  "company_names",
+  "fx_rates",
+  "type_conversion_errors",
+  "import_rejects",
+  "import_run_backup",
+  "poly_docs",
+  "product_attributes",
+  "cls_decisions",
+  "label_audit",
+  "general_schema",
+  "companies",
+  "tnved",
+  "import_runs",
+];
+
+/// Arbitrary key for the advisory lock serializing concurrent migrators
+const MIGRATION_LOCK_KEY: i64 = 0x706f6c795f636c73;
+
+async fn init_migrations_table(
+  a_cli: &Client,
+) -> Result<(), Box<dyn Error>>
+{
+  a_cli
+    .batch_execute(
+      "
+      CREATE TABLE IF NOT EXISTS schema_migrations (
+        version INTEGER PRIMARY KEY,
+        name TEXT NOT NULL,
+        applied_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+      );",
+    )
+    .await?;
+
+  Ok(())
+}
+
+/// Versions already applied to the database, in ascending order
+pub async fn applied_migrations(
+  a_cli: &Client,
+) -> Result<Vec<i32>, Box<dyn Error>>
+{
+  init_migrations_table(a_cli).await?;
+
+  let res = a_cli
+    .query(
+      "SELECT version FROM schema_migrations ORDER BY version",
+      &[],
+    )
+    .await?;
+
+  Ok(res.into_iter().map(|x| x.get(0)).collect())
+}
+
+/// Migrations known to the binary but not yet applied to the database
+pub async fn pending_migrations(
+  a_cli: &Client,
+) -> Result<Vec<&'static Migration>, Box<dyn Error>>
+{
+  let applied = applied_migrations(a_cli).await?;
+
+  Ok(
+    MIGRATIONS
+      .iter()
+      .filter(|x| !applied.contains(&x.version))
+      .collect(),
+  )
+}
+
+/// Apply every pending migration, each one in its own transaction.
+/// Returns the number of migrations applied.
+pub async fn run_migrations(
+  a_cli: &mut Client,
+) -> Result<usize, Box<dyn Error>>
+{
+  init_migrations_table(a_cli).await?;
+
+  let mut done: usize = 0;
+  for m in MIGRATIONS {
+    let tr = a_cli.transaction().await?;
+    tr.execute(
+      "SELECT pg_advisory_xact_lock($1)",
+      &[&MIGRATION_LOCK_KEY],
+    )
+    .await?;
+
+    // Another process could have applied it while we waited on the lock
+    let exists = tr
+      .query(
+        "SELECT 1 FROM schema_migrations WHERE version = $1",
+        &[&m.version],
+      )
+      .await?;
+    if !exists.is_empty() {
+      continue;
+    }
+
+    tracing::info!("Applying migration {:04} {}", m.version, m.name);
+    tr.batch_execute(m.sql).await?;
+    tr.execute(
+      "INSERT INTO schema_migrations (version, name) VALUES ($1, $2)",
+      &[&m.version, &m.name],
+    )
+    .await?;
+    tr.commit().await?;
+    done += 1;
+  }
+
+  Ok(done)
+}
+
+/// Fail if the database schema is behind the binary. Called at startup
+/// by the commands that read or write data.
+pub async fn check_migrations(
+  a_cli: &Client,
+) -> Result<(), Box<dyn Error>>
+{
+  let pending = pending_migrations(a_cli).await?;
+  if !pending.is_empty() {
+    let names: Vec<String> = pending
+      .iter()
+      .map(|x| format!("{:04}_{}", x.version, x.name))
+      .collect();
+    return Err(
+      format!(
+        "Database schema is out of date, run `migrate` first. Pending: {}",
+        names.join(", ")
+      )
+      .into(),
+    );
+  }
+
+  Ok(())
+}
+
+/// Number of values per column that could not be converted when the
+/// numeric columns were typed, see type_conversion_errors
+pub async fn conversion_errors(
+  a_cli: &Client,
+) -> Result<Vec<(String, i64)>, Box<dyn Error>>
+{
+  let res = a_cli
+    .query(
+      "SELECT column_name, COUNT(*) FROM type_conversion_errors \
+      GROUP BY column_name ORDER BY column_name",
+      &[],
+    )
+    .await?;
+
+  Ok(res.into_iter().map(|x| (x.get(0), x.get(1))).collect())
+}
+
+/// Bring the schema up to date. With `purge` every managed table is
+/// dropped first, so all data is lost.
+pub async fn init_database(
+  a_cli: &mut Client,
+  purge: bool,
+) -> Result<usize, Box<dyn Error>>
+{
+  if purge {
+    for t in MANAGED_TABLES {
+      a_cli
+        .batch_execute(
+          format!("DROP TABLE IF EXISTS {};", t).as_str(),
+        )
+        .await?;
+    }
+    a_cli
+      .batch_execute("DROP TABLE IF EXISTS schema_migrations;")
+      .await?;
+  }
+
+  run_migrations(a_cli).await
+}
@@ -0,0 +1,289 @@
+use crate::database::{DERIVED_COLUMNS, GENERAL_SCHEMA_COLUMNS};
+use chrono::NaiveDateTime;
+use sha2::{Digest, Sha256};
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::{Path, PathBuf};
+use tokio_postgres::Client;
+
+/// Row counters of a single import
+#[derive(Debug, Default, Clone)]
+pub struct RunStats
+{
+  pub run_id: i32,
+  pub read: u64,
+  pub inserted: u64,
+  pub updated: u64,
+  pub rejected: u64,
+  pub embedded: u64,
+  pub load_ms: i64,
+  pub embed_ms: i64,
+}
+
+#[derive(Debug, Clone)]
+pub struct ImportRun
+{
+  pub id: i32,
+  pub file_path: String,
+  pub checksum: Option<String>,
+  pub seg: Option<String>,
+  pub status: String,
+  pub rows_read: i64,
+  pub rows_inserted: i64,
+  pub rows_updated: i64,
+  pub rows_rejected: i64,
+  pub rows_embedded: i64,
+  pub error: Option<String>,
+  pub started_at: Option<NaiveDateTime>,
+  pub finished_at: Option<NaiveDateTime>,
+}
+
+/// What a rollback removed or restored
+#[derive(Debug, Default)]
+pub struct RollbackStats
+{
+  pub deleted: u64,
+  pub restored: u64,
+  pub deleted_docs: u64,
+  pub deleted_companies: u64,
+}
+
+/// Hex encoded SHA-256 of a file
+pub fn file_checksum(
+  a_path: &PathBuf,
+) -> Result<String, Box<dyn Error>>
+{
+  let mut reader = BufReader::new(File::open(a_path)?);
+  let mut hasher = Sha256::new();
+  let mut buf = [0u8; 64 * 1024];
+
+  loop {
+    let n = reader.read(&mut buf)?;
+    if n == 0 {
+      break;
+    }
+    hasher.update(&buf[..n]);
+  }
+
+  Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Register a new import and return its id
+pub async fn start_run(
+  a_cli: &Client,
+  a_path: &Path,
+  a_checksum: &String,
+  a_seg: Option<String>,
+) -> Result<i32, Box<dyn Error>>
+{
+  let path = a_path.to_string_lossy().to_string();
+  let res = a_cli
+    .query_one(
+      "INSERT INTO import_runs (file_path, checksum, seg) \
+      VALUES ($1, $2, $3) RETURNING id",
+      &[&path, a_checksum, &a_seg],
+    )
+    .await?;
+
+  Ok(res.get(0))
+}
+
+/// Save the counters of a run that is still going
+pub async fn progress_run(
+  a_cli: &Client,
+  a_stats: &RunStats,
+) -> Result<(), Box<dyn Error>>
+{
+  a_cli
+    .execute(
+      "UPDATE import_runs SET rows_read = $2, rows_inserted = $3, \
+      rows_updated = $4, rows_rejected = $5, rows_embedded = $6, \
+      load_ms = $7, embed_ms = $8 WHERE id = $1",
+      &[
+        &a_stats.run_id,
+        &(a_stats.read as i64),
+        &(a_stats.inserted as i64),
+        &(a_stats.updated as i64),
+        &(a_stats.rejected as i64),
+        &(a_stats.embedded as i64),
+        &a_stats.load_ms,
+        &a_stats.embed_ms,
+      ],
+    )
+    .await?;
+
+  Ok(())
+}
+
+pub async fn finish_run(
+  a_cli: &Client,
+  a_stats: &RunStats,
+) -> Result<(), Box<dyn Error>>
+{
+  progress_run(a_cli, a_stats).await?;
+  a_cli
+    .execute(
+      "UPDATE import_runs SET status = 'done', \
+      finished_at = CURRENT_TIMESTAMP WHERE id = $1",
+      &[&a_stats.run_id],
+    )
+    .await?;
+
+  Ok(())
+}
+
+pub async fn fail_run(
+  a_cli: &Client,
+  a_run_id: i32,
+  a_err: &String,
+) -> Result<(), Box<dyn Error>>
+{
+  a_cli
+    .execute(
+      "UPDATE import_runs SET status = 'failed', error = $2, \
+      finished_at = CURRENT_TIMESTAMP WHERE id = $1",
+      &[&a_run_id, a_err],
+    )
+    .await?;
+
+  Ok(())
+}
+
+/// Most recent imports first
+pub async fn list_runs(
+  a_cli: &Client,
+  a_limit: i64,
+) -> Result<Vec<ImportRun>, Box<dyn Error>>
+{
+  let res = a_cli
+    .query(
+      "SELECT id, file_path, checksum, seg, status, rows_read, \
+      rows_inserted, rows_updated, rows_rejected, rows_embedded, error, \
+      started_at, finished_at FROM import_runs ORDER BY id DESC LIMIT $1",
+      &[&a_limit],
+    )
+    .await?;
+
+  let out = res
+    .into_iter()
+    .map(|x| ImportRun {
+      id: x.get(0),
+      file_path: x.get(1),
+      checksum: x.get(2),
+      seg: x.get(3),
+      status: x.get(4),
+      rows_read: x.get(5),
+      rows_inserted: x.get(6),
+      rows_updated: x.get(7),
+      rows_rejected: x.get(8),
+      rows_embedded: x.get(9),
+      error: x.get(10),
+      started_at: x.get(11),
+      finished_at: x.get(12),
+    })
+    .collect();
+
+  Ok(out)
+}
+
+/// Undo a finished or failed import: delete the rows and embeddings it
+/// added and restore the previous version of the rows it changed. Rows
+/// changed again by a later run keep their newer version. Companies it
+/// created are deleted unless rows of other runs link to them.
+pub async fn rollback_run(
+  a_cli: &mut Client,
+  a_run_id: i32,
+) -> Result<RollbackStats, Box<dyn Error>>
+{
+  let tr = a_cli.transaction().await?;
+
+  let res = tr
+    .query(
+      "SELECT status FROM import_runs WHERE id = $1 FOR UPDATE",
+      &[&a_run_id],
+    )
+    .await?;
+  if res.is_empty() {
+    return Err(
+      format!("Import run {} does not exist", a_run_id).into(),
+    );
+  }
+  let status: String = res[0].get(0);
+  if status != "done" && status != "failed" {
+    return Err(
+      format!(
+        "Import run {} is {}, only done or failed runs roll back",
+        a_run_id, status
+      )
+      .into(),
+    );
+  }
+
+  let deleted_docs = tr
+    .execute(
+      "DELETE FROM poly_docs WHERE import_run_id = $1",
+      &[&a_run_id],
+    )
+    .await?;
+
+  let cols = GENERAL_SCHEMA_COLUMNS
+    .iter()
+    .chain(DERIVED_COLUMNS)
+    .cloned()
+    .collect::<Vec<_>>()
+    .join(", ");
+  let restored = tr
+    .execute(
+      format!(
+        "UPDATE general_schema g SET ({cols}, updated_run_id) = ( \
+          SELECT {cols}, updated_run_id \
+          FROM jsonb_populate_record(NULL::general_schema, b.row)), \
+          label_meta = coalesce(b.row -> 'label_meta', g.label_meta) \
+        FROM import_run_backup b \
+        WHERE b.run_id = $1 AND b.general_schema_id = g.id \
+        AND g.updated_run_id = $1",
+        cols = cols
+      )
+      .as_str(),
+      &[&a_run_id],
+    )
+    .await?;
+
+  let deleted = tr
+    .execute(
+      "DELETE FROM general_schema WHERE import_run_id = $1",
+      &[&a_run_id],
+    )
+    .await?;
+
+  let deleted_companies = tr
+    .execute(
+      "DELETE FROM companies c WHERE c.import_run_id = $1 \
+      AND NOT EXISTS (SELECT 1 FROM general_schema g \
+        WHERE c.id IN (g.sender_company_id, g.receiver_company_id, \
+          g.contractor_company_id))",
+      &[&a_run_id],
+    )
+    .await?;
+
+  tr.execute(
+    "DELETE FROM import_run_backup WHERE run_id = $1",
+    &[&a_run_id],
+  )
+  .await?;
+  tr.execute(
+    "UPDATE import_runs SET status = 'rolled_back' WHERE id = $1",
+    &[&a_run_id],
+  )
+  .await?;
+
+  tr.commit().await?;
+
+  Ok(RollbackStats {
+    deleted,
+    restored,
+    deleted_docs,
+    deleted_companies,
+  })
+}
@@ -0,0 +1,223 @@
+use crate::dates::CELL_DATE_FORMATS;
+use crate::dialect::{Dialect, detect_dialect};
+use calamine::{Data, Reader as _, open_workbook_auto};
+use csv::{Position, ReaderBuilder, StringRecord};
+use encoding_rs_io::DecodeReaderBytesBuilder;
+use serde::Deserialize;
+use std::error::Error;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+/// Extensions read as spreadsheets, everything else is read as CSV
+pub const SPREADSHEET_EXTENSIONS: &[&str] =
+  &["xlsx", "xlsm", "xlsb", "xls", "ods"];
+
+/// Where the table starts inside a source file and how it is written.
+/// CSV settings left empty are detected from the file.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct InputOptions
+{
+  /// Worksheet name, the first sheet when empty. Spreadsheets only.
+  #[serde(default)]
+  pub sheet: Option<String>,
+  /// Rows above the header row, e.g. a report title
+  #[serde(default)]
+  pub header_row: usize,
+  /// Character encoding label, e.g. "utf-8" or "windows-1251"
+  #[serde(default)]
+  pub encoding: Option<String>,
+  /// Field delimiter, "tab" for tab separated files
+  #[serde(default)]
+  pub delimiter: Option<String>,
+  /// Quote character, "none" when quotes are plain text
+  #[serde(default)]
+  pub quote: Option<String>,
+  /// Decimal separator of numbers, "," or "."
+  #[serde(default)]
+  pub decimal: Option<String>,
+}
+
+/// A data row, or the line and reason it could not be read
+pub type SourceRow = Result<StringRecord, (Option<u64>, String)>;
+
+/// Header and data rows of a CSV file or a worksheet. Every record
+/// carries its 1-based line (row) number as position. CSV rows are read
+/// lazily, so a file of any size can be walked in constant memory.
+pub struct SourceRows
+{
+  pub headers: StringRecord,
+  pub rows: Box<dyn Iterator<Item = SourceRow> + Send>,
+  /// How the file is written; the defaults for spreadsheets
+  pub dialect: Dialect,
+}
+
+pub fn is_spreadsheet(a_path: &Path) -> bool
+{
+  a_path
+    .extension()
+    .and_then(|x| x.to_str())
+    .map(|x| {
+      SPREADSHEET_EXTENSIONS.contains(&x.to_lowercase().as_str())
+    })
+    .unwrap_or(false)
+}
+
+fn with_line(mut a_rec: StringRecord, a_line: u64) -> StringRecord
+{
+  let mut pos = Position::new();
+  pos.set_line(a_line);
+  a_rec.set_position(Some(pos));
+
+  a_rec
+}
+
+/// Text of a cell as it would appear in a CSV export. Dates become ISO
+/// dates (`CELL_DATE_FORMATS`), integral floats lose their fraction.
+fn cell_to_string(a_cell: &Data) -> String
+{
+  match a_cell {
+    Data::Empty | Data::Error(_) => "".to_string(),
+    Data::String(x) => x.clone(),
+    Data::Int(x) => x.to_string(),
+    Data::Float(x) if x.fract() == 0.0 && x.abs() < 1e15 => {
+      format!("{}", *x as i64)
+    }
+    Data::Float(x) => x.to_string(),
+    Data::Bool(x) => x.to_string(),
+    Data::DateTime(x) => match x.as_datetime() {
+      Some(d) if d.time() == chrono::NaiveTime::MIN => {
+        d.format(CELL_DATE_FORMATS[0]).to_string()
+      }
+      Some(d) => d.format(CELL_DATE_FORMATS[1]).to_string(),
+      None => x.as_f64().to_string(),
+    },
+    Data::DateTimeIso(x) | Data::DurationIso(x) => x.clone(),
+  }
+}
+
+fn open_csv(
+  a_path: &PathBuf,
+  a_opts: &InputOptions,
+) -> Result<SourceRows, Box<dyn Error>>
+{
+  let dialect = detect_dialect(a_path, a_opts)?;
+  tracing::debug!("{:?} dialect {:?}", a_path, dialect);
+
+  // Decoded to UTF-8 while reading, the BOM is dropped
+  let file = DecodeReaderBytesBuilder::new()
+    .encoding(Some(dialect.encoding))
+    .bom_override(true)
+    .strip_bom(true)
+    .build(File::open(a_path)?);
+
+  // Rows above the header may have any width, so lengths are checked
+  // here instead of by the reader
+  let reader = ReaderBuilder::new()
+    .has_headers(false)
+    .flexible(true)
+    .delimiter(dialect.delimiter)
+    .quote(dialect.quote.unwrap_or(b'"'))
+    .quoting(dialect.quote.is_some())
+    .from_reader(file);
+  let mut records = reader.into_records();
+
+  let mut headers = None;
+  for _ in 0..=a_opts.header_row {
+    headers = records.next();
+  }
+  let headers = match headers {
+    Some(x) => x?,
+    None => {
+      return Err(format!("{:?} has no header row", a_path).into());
+    }
+  };
+
+  let width = headers.len();
+  let rows = records.map(move |x| match x {
+    Ok(rec) if rec.len() != width => Err((
+      rec.position().map(|p| p.line()),
+      format!(
+        "found record with {} fields, but the header has {} fields",
+        rec.len(),
+        width
+      ),
+    )),
+    Ok(rec) => Ok(rec),
+    Err(e) => Err((e.position().map(|p| p.line()), e.to_string())),
+  });
+
+  Ok(SourceRows {
+    headers,
+    rows: Box::new(rows),
+    dialect,
+  })
+}
+
+fn open_spreadsheet(
+  a_path: &PathBuf,
+  a_opts: &InputOptions,
+) -> Result<SourceRows, Box<dyn Error>>
+{
+  let mut workbook = open_workbook_auto(a_path)?;
+  let sheet = match &a_opts.sheet {
+    Some(x) => x.clone(),
+    None => match workbook.sheet_names().first() {
+      Some(x) => x.clone(),
+      None => {
+        return Err(format!("{:?} has no sheets", a_path).into());
+      }
+    },
+  };
+  let range = workbook.worksheet_range(&sheet)?;
+
+  // The range starts at the first used cell, not at A1
+  let (first_row, first_col) = range.start().unwrap_or((0, 0));
+  let pad = first_col as usize;
+  let mut rows = range.rows().enumerate().map(|(i, cells)| {
+    let mut values: Vec<String> = vec!["".to_string(); pad];
+    values.extend(cells.iter().map(cell_to_string));
+    with_line(
+      StringRecord::from(values),
+      first_row as u64 + i as u64 + 1,
+    )
+  });
+
+  let skip = a_opts.header_row.saturating_sub(first_row as usize);
+  let headers = match rows.nth(skip) {
+    Some(x) => x,
+    None => {
+      return Err(
+        format!("Sheet {} of {:?} has no header row", sheet, a_path)
+          .into(),
+      );
+    }
+  };
+
+  // Trailing empty rows are common in hand-edited workbooks
+  let data: Vec<SourceRow> = rows
+    .filter(|x| x.iter().any(|v| !v.trim().is_empty()))
+    .map(Ok)
+    .collect();
+
+  Ok(SourceRows {
+    headers,
+    rows: Box::new(data.into_iter()),
+    dialect: Dialect {
+      date_cells: true,
+      ..Dialect::default()
+    },
+  })
+}
+
+/// Open a CSV file or a spreadsheet, chosen by extension
+pub fn open_source(
+  a_path: &PathBuf,
+  a_opts: &InputOptions,
+) -> Result<SourceRows, Box<dyn Error>>
+{
+  if is_spreadsheet(a_path) {
+    open_spreadsheet(a_path, a_opts)
+  } else {
+    open_csv(a_path, a_opts)
+  }
+}
@@ -0,0 +1,783 @@
+use crate::database::VecItem;
+use crate::schemas::GeneralSchema;
+use config::{Config, File, FileFormat};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
+use tokio_postgres::Client;
+
+/// Directory searched for taxonomy files given by name
+pub const TAXONOMIES_DIR: &str = "taxonomies";
+
+/// Taxonomies shipped with the binary, overridable by a file of the
+/// same name in `TAXONOMIES_DIR`
+const BUILTIN_TAXONOMIES: &[(&str, &str)] = &[
+  ("material", include_str!("../taxonomies/material.toml")),
+  (
+    "application",
+    include_str!("../taxonomies/application.toml"),
+  ),
+];
+
+/// Label columns of general_schema filled from the source files
+pub const SOURCE_LABEL_COLUMNS: &[&str] = &["eval", "eval_sec"];
+
+/// A category of a taxonomy
+#[derive(Debug, Clone, Deserialize)]
+pub struct Label
+{
+  /// Stored value, also the answer expected from the model
+  pub name: String,
+  /// Explanation given to the model
+  #[serde(default)]
+  pub description: String,
+  /// Other spellings read as this label, e.g. in the source files
+  #[serde(default)]
+  pub aliases: Vec<String>,
+}
+
+/// A way of telling relevant rows from the others, tried in the order
+/// of `Relevance::methods`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum FilterT
+{
+  /// TN VED headings and keywords of the description
+  #[serde(rename = "rules")]
+  Rules,
+  /// Labels of the closest labelled examples
+  #[serde(rename = "knn")]
+  Knn,
+  /// A yes or no question to a small model
+  #[serde(rename = "model")]
+  Model,
+}
+
+impl FilterT
+{
+  /// Value stored in cls_decisions.method
+  pub fn code(&self) -> &'static str
+  {
+    match self {
+      FilterT::Rules => "rules",
+      FilterT::Knn => "knn",
+      FilterT::Model => "model",
+    }
+  }
+}
+
+fn default_methods() -> Vec<FilterT>
+{
+  vec![FilterT::Rules, FilterT::Knn, FilterT::Model]
+}
+
+fn default_knn_share() -> f64
+{
+  0.8
+}
+
+/// First stage of a taxonomy: rows found not relevant get `label`
+/// without asking the classifier. Rows no method decides on are taken
+/// for relevant.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Relevance
+{
+  pub label: String,
+  #[serde(default = "default_methods")]
+  pub methods: Vec<FilterT>,
+  /// TN VED headings (4 digits) of relevant products. A row with a code
+  /// outside them and no keyword is not relevant.
+  #[serde(default)]
+  pub tnved: Vec<String>,
+  /// Words, or beginnings of words, of relevant descriptions
+  #[serde(default)]
+  pub keywords: Vec<String>,
+  /// Share of the closest examples that must agree for kNN to decide
+  #[serde(default = "default_knn_share")]
+  pub knn_share: f64,
+  /// Task given to the small model, answered with yes or no
+  #[serde(default)]
+  pub preamble: String,
+}
+
+/// Lowercase ASCII letters, digits and '_', starting with a letter
+fn is_identifier(a_name: &str) -> bool
+{
+  a_name.starts_with(|x: char| x.is_ascii_lowercase())
+    && a_name.chars().all(|x| {
+      x.is_ascii_lowercase() || x.is_ascii_digit() || x == '_'
+    })
+}
+
+/// Whether a word of `a_text` begins with `a_word`
+fn starts_word(a_text: &str, a_word: &str) -> bool
+{
+  a_text.match_indices(a_word).any(|(i, _)| {
+    let before = a_text[..i].chars().next_back();
+    !before.map(|x| x.is_alphanumeric()).unwrap_or(false)
+  })
+}
+
+impl Relevance
+{
+  /// Decision of the rules, None when they do not know
+  pub fn by_rules(
+    &self,
+    a_product: &str,
+    a_tnved: Option<&str>,
+  ) -> Option<bool>
+  {
+    let text = a_product.to_lowercase();
+    if self
+      .keywords
+      .iter()
+      .any(|x| starts_word(&text, &x.to_lowercase()))
+    {
+      return Some(true);
+    }
+
+    match a_tnved {
+      Some(code) if code.len() >= 4 && !self.tnved.is_empty() => {
+        // A relevant heading alone does not tell, 3921 holds films too
+        if self.tnved.iter().any(|x| code.starts_with(x.as_str())) {
+          None
+        } else {
+          Some(false)
+        }
+      }
+      _ => None,
+    }
+  }
+
+  /// Decision of the closest labelled examples, documents of poly_docs:
+  /// not relevant when enough of them carry `label`, relevant when
+  /// enough carry another
+  pub fn by_examples(&self, a_docs: &[String]) -> Option<bool>
+  {
+    let labels: Vec<String> = a_docs
+      .iter()
+      .filter_map(|x| serde_json::from_str::<VecItem>(x).ok())
+      .map(|x| x.eval)
+      .collect();
+    if labels.is_empty() {
+      return None;
+    }
+
+    let other = labels.iter().filter(|x| **x == self.label).count();
+    let share = other as f64 / labels.len() as f64;
+    if share >= self.knn_share {
+      Some(false)
+    } else if 1.0 - share >= self.knn_share {
+      Some(true)
+    } else {
+      None
+    }
+  }
+
+  /// System prompt of the small model
+  pub fn prompt(&self) -> String
+  {
+    let mut out = self.preamble.trim().to_string();
+    out.push_str(
+      "\nAnswer ONLY with yes or no with no other descriptions or \
+      symbols.",
+    );
+
+    out
+  }
+
+  /// Decision read from the answer of the small model
+  pub fn by_answer(&self, a_answer: &str) -> Option<bool>
+  {
+    let key = a_answer
+      .trim()
+      .trim_matches(|x: char| !x.is_alphanumeric())
+      .to_lowercase();
+    match key.as_str() {
+      "yes" | "да" | "relevant" => Some(true),
+      "no" | "нет" | "not relevant" => Some(false),
+      _ => None,
+    }
+  }
+}
+
+/// Where a label comes from
+#[derive(
+  Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize,
+)]
+pub enum SourceT
+{
+  /// Label column of a source file
+  #[serde(rename = "file")]
+  File,
+  /// A rule, e.g. the relevance rules
+  #[serde(rename = "rule")]
+  Rule,
+  /// A classifier model
+  #[serde(rename = "model")]
+  Model,
+  /// An analyst
+  #[serde(rename = "user")]
+  User,
+}
+
+/// Provenance of a label, kept in general_schema.label_meta under the
+/// taxonomy name. The database adds the time of the change and fills it
+/// for edits made without one, as by the user of the connection.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Provenance
+{
+  /// None for labels stored before provenance was kept, e.g. locked
+  /// since
+  #[serde(default)]
+  pub source: Option<SourceT>,
+  /// File path, rule, model or user name
+  #[serde(rename = "ref", default)]
+  pub reference: Option<String>,
+  /// Version of the prompt of a model, see `prompt_version`
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub prompt: Option<String>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub run: Option<i32>,
+  /// Set by the database
+  #[serde(default, skip_serializing)]
+  pub at: Option<String>,
+  /// Verified by hand: automated writers leave the label alone
+  #[serde(default)]
+  pub locked: bool,
+  /// User who last locked or unlocked the label
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub locked_by: Option<String>,
+}
+
+impl Provenance
+{
+  pub fn rule(a_rule: &str) -> Self
+  {
+    Provenance {
+      source: Some(SourceT::Rule),
+      reference: Some(a_rule.to_string()),
+      prompt: None,
+      run: None,
+      at: None,
+      locked: false,
+      locked_by: None,
+    }
+  }
+
+  pub fn model(a_model: &str, a_prompt: &str) -> Self
+  {
+    Provenance {
+      source: Some(SourceT::Model),
+      prompt: Some(prompt_version(a_prompt)),
+      ..Self::rule(a_model)
+    }
+  }
+
+  pub fn user(a_user: &str, a_locked: bool) -> Self
+  {
+    Provenance {
+      source: Some(SourceT::User),
+      locked: a_locked,
+      locked_by: Some(a_user.to_string()).filter(|_| a_locked),
+      ..Self::rule(a_user)
+    }
+  }
+}
+
+/// Short hash of a prompt, telling which labels were given by which
+/// wording
+pub fn prompt_version(a_prompt: &str) -> String
+{
+  let hash = Sha256::digest(a_prompt.as_bytes());
+  hash[..6].iter().map(|x| format!("{:02x}", x)).collect()
+}
+
+fn default_pending() -> String
+{
+  "NoEval".to_string()
+}
+
+fn default_skip() -> Vec<String>
+{
+  vec!["NoNeed".to_string()]
+}
+
+/// One classification dimension: its categories, how the model is asked
+/// and the general_schema column its labels are stored in
+#[derive(Debug, Clone, Deserialize)]
+pub struct Taxonomy
+{
+  pub name: String,
+  /// Label column of general_schema, created when missing
+  pub column: String,
+  /// Task given to the model, followed by the labels and their
+  /// descriptions
+  pub preamble: String,
+  pub labels: Vec<Label>,
+  /// Label of the rows waiting for classification, also given to
+  /// values outside the taxonomy
+  #[serde(default = "default_pending")]
+  pub pending: String,
+  /// Labels of rows that are not classified, e.g. not needed for the
+  /// analysis
+  #[serde(default = "default_skip")]
+  pub skip: Vec<String>,
+  /// Optional first stage, see `Relevance`
+  #[serde(default)]
+  pub relevance: Option<Relevance>,
+}
+
+impl Taxonomy
+{
+  /// Parse a taxonomy from TOML text
+  pub fn from_toml(a_src: &str) -> Result<Self, Box<dyn Error>>
+  {
+    let config = Config::builder()
+      .add_source(File::from_str(a_src, FileFormat::Toml))
+      .build()?;
+    let taxonomy: Taxonomy = config.try_deserialize()?;
+    taxonomy.check()?;
+
+    Ok(taxonomy)
+  }
+
+  pub fn from_file(a_path: &Path) -> Result<Self, Box<dyn Error>>
+  {
+    let src = std::fs::read_to_string(a_path)?;
+    Self::from_toml(&src).map_err(|e| {
+      format!("Taxonomy file {:?}: {}", a_path, e).into()
+    })
+  }
+
+  fn check(&self) -> Result<(), Box<dyn Error>>
+  {
+    // Both are written into SQL
+    if !is_identifier(&self.name) {
+      return Err(
+        format!("Invalid taxonomy name '{}'", self.name).into(),
+      );
+    }
+    let c = &self.column;
+    if !is_identifier(c) {
+      return Err(
+        format!("Taxonomy {}: invalid column '{}'", self.name, c)
+          .into(),
+      );
+    }
+    if c != "eval" && !c.starts_with("eval_") {
+      return Err(
+        format!(
+          "Taxonomy {}: column '{}' is not a label column, those are \
+          eval or start with eval_",
+          self.name, c
+        )
+        .into(),
+      );
+    }
+    if self.labels.is_empty() {
+      return Err(
+        format!("Taxonomy {}: no labels", self.name).into(),
+      );
+    }
+    if let Some(r) = &self.relevance {
+      if !self.is_label(&r.label) {
+        return Err(
+          format!(
+            "Taxonomy {}: relevance label '{}' is not a label",
+            self.name, r.label
+          )
+          .into(),
+        );
+      }
+      if r.methods.contains(&FilterT::Model)
+        && r.preamble.trim().is_empty()
+      {
+        return Err(
+          format!(
+            "Taxonomy {}: relevance by model needs a preamble",
+            self.name
+          )
+          .into(),
+        );
+      }
+    }
+
+    Ok(())
+  }
+
+  /// The label a value stands for: a label name or alias, the pending
+  /// label or a skipped one, compared case-insensitively
+  pub fn find_label(&self, a_value: &str) -> Option<String>
+  {
+    let key = a_value
+      .trim()
+      .trim_matches(|x: char| x == '.' || x == '"' || x == '\'')
+      .trim()
+      .to_lowercase();
+    if key.is_empty() {
+      return None;
+    }
+
+    for l in &self.labels {
+      if l.name.to_lowercase() == key
+        || l.aliases.iter().any(|x| x.to_lowercase() == key)
+      {
+        return Some(l.name.clone());
+      }
+    }
+
+    std::iter::once(&self.pending)
+      .chain(self.skip.iter())
+      .find(|x| x.to_lowercase() == key)
+      .cloned()
+  }
+
+  /// Label stored for a source value; values outside the taxonomy are
+  /// left for classification
+  pub fn normalize(&self, a_value: Option<&str>) -> String
+  {
+    a_value
+      .and_then(|x| self.find_label(x))
+      .unwrap_or_else(|| self.pending.clone())
+  }
+
+  /// True for the labels of classified rows
+  pub fn is_label(&self, a_value: &str) -> bool
+  {
+    self.labels.iter().any(|x| x.name == a_value)
+  }
+
+  /// The label of a row read from the source file, when it is one of
+  /// the taxonomy's labels
+  pub fn label_of(&self, a_row: &GeneralSchema) -> Option<String>
+  {
+    let value = match self.column.as_str() {
+      "eval" => a_row.eval.as_deref(),
+      "eval_sec" => a_row.eval_sec.as_deref(),
+      _ => None,
+    };
+
+    value
+      .and_then(|x| self.find_label(x))
+      .filter(|x| self.is_label(x))
+  }
+
+  /// System prompt of the model classifying by this taxonomy
+  pub fn prompt(&self) -> String
+  {
+    let mut out = self.preamble.trim().to_string();
+    out.push_str("\nThe categories can be described as follows:\n");
+    for l in &self.labels {
+      out.push_str(&format!("-- {}: {}\n", l.name, l.description));
+    }
+    out.push_str(
+      "You will be given few examples of similar descriptions as well as \
+      evaluated category. Use those for reference. \
+      Answer ONLY with the selected category with no other descriptions \
+      or symbols.",
+    );
+
+    out
+  }
+}
+
+/// All taxonomies known by name: the built-in ones, replaced or
+/// extended by the files in `TAXONOMIES_DIR`
+pub fn known_taxonomies() -> Result<Vec<Taxonomy>, Box<dyn Error>>
+{
+  let mut out = Vec::new();
+  for (_, src) in BUILTIN_TAXONOMIES {
+    out.push(Taxonomy::from_toml(src)?);
+  }
+
+  if let Ok(dir) = std::fs::read_dir(TAXONOMIES_DIR) {
+    for entry in dir {
+      let path = entry?.path();
+      if path.extension().and_then(|x| x.to_str()) != Some("toml") {
+        continue;
+      }
+      let t = Taxonomy::from_file(&path)?;
+      match out.iter().position(|x: &Taxonomy| x.name == t.name) {
+        Some(i) => out[i] = t,
+        None => out.push(t),
+      }
+    }
+  }
+
+  for (i, t) in out.iter().enumerate() {
+    if let Some(o) = out[..i].iter().find(|x| x.column == t.column) {
+      return Err(
+        format!(
+          "Taxonomies {} and {} both store into '{}'",
+          o.name, t.name, t.column
+        )
+        .into(),
+      );
+    }
+  }
+
+  Ok(out)
+}
+
+/// Find a taxonomy by file path or by name (case-insensitive). Names
+/// are looked up in `TAXONOMIES_DIR` first, then among the built-in
+/// taxonomies.
+pub fn load_taxonomy(a_name: &str)
+-> Result<Taxonomy, Box<dyn Error>>
+{
+  let path = PathBuf::from(a_name);
+  if path.extension().and_then(|x| x.to_str()) == Some("toml") {
+    return Taxonomy::from_file(&path);
+  }
+
+  let name = a_name.to_lowercase();
+  let path = Path::new(TAXONOMIES_DIR).join(format!("{}.toml", name));
+  if path.exists() {
+    return Taxonomy::from_file(&path);
+  }
+
+  match BUILTIN_TAXONOMIES.iter().find(|(n, _)| *n == name) {
+    Some((_, src)) => Taxonomy::from_toml(src),
+    None => Err(format!("Unknown taxonomy '{}'", a_name).into()),
+  }
+}
+
+/// Taxonomies in use, read once per process
+pub static TAXONOMIES: LazyLock<Vec<Taxonomy>> =
+  LazyLock::new(|| {
+    known_taxonomies().expect("Taxonomy files are valid")
+  });
+
+/// Label stored in a label column for a source value. Columns without
+/// a taxonomy keep the value.
+pub fn normalize_label(
+  a_column: &str,
+  a_value: Option<&str>,
+) -> String
+{
+  match TAXONOMIES.iter().find(|x| x.column == a_column) {
+    Some(t) => t.normalize(a_value),
+    None => a_value
+      .map(|x| x.trim().to_string())
+      .filter(|x| !x.is_empty())
+      .unwrap_or_else(default_pending),
+  }
+}
+
+/// Create the label column of a taxonomy that is not stored in a
+/// column of its own yet, and the triggers keeping its provenance (see
+/// migrations/0020_label_provenance.sql)
+pub async fn prepare_taxonomy(
+  a_cli: &Client,
+  a_taxonomy: &Taxonomy,
+) -> Result<(), Box<dyn Error>>
+{
+  let col = &a_taxonomy.column;
+  if !SOURCE_LABEL_COLUMNS.contains(&col.as_str()) {
+    a_cli
+      .batch_execute(
+        format!(
+          "ALTER TABLE general_schema ADD COLUMN IF NOT EXISTS {} TEXT",
+          col
+        )
+        .as_str(),
+      )
+      .await?;
+  }
+
+  a_cli
+    .batch_execute(
+      format!(
+        "DROP TRIGGER IF EXISTS label_{col} ON general_schema; \
+        CREATE TRIGGER label_{col} \
+        BEFORE UPDATE OF {col}, label_meta ON general_schema \
+        FOR EACH ROW EXECUTE FUNCTION guard_label('{tax}', '{col}'); \
+        DROP TRIGGER IF EXISTS label_{col}_audit ON general_schema; \
+        CREATE TRIGGER label_{col}_audit \
+        AFTER INSERT OR UPDATE OF {col}, label_meta ON general_schema \
+        FOR EACH ROW EXECUTE FUNCTION audit_label('{tax}', '{col}');",
+        col = col,
+        tax = a_taxonomy.name
+      )
+      .as_str(),
+    )
+    .await?;
+
+  Ok(())
+}
+
+/// Keys of a label_meta entry replaced by the provenance of a new label;
+/// the others, as the lock, stay
+const PROVENANCE_KEYS: [&str; 5] =
+  ["source", "ref", "prompt", "run", "at"];
+
+/// Store a label of the taxonomy with its provenance. Locked labels are
+/// only changed by users; returns false when the label was kept. The
+/// lock of the label is kept unless the provenance locks it, see
+/// `lock_labels` to unlock.
+pub async fn set_label(
+  a_cli: &Client,
+  a_taxonomy: &Taxonomy,
+  a_id: i32,
+  a_label: &str,
+  a_provenance: &Provenance,
+) -> Result<bool, Box<dyn Error>>
+{
+  let mut meta = serde_json::to_value(a_provenance)?;
+  if let Some(x) =
+    meta.as_object_mut().filter(|_| !a_provenance.locked)
+  {
+    x.remove("locked");
+    x.remove("locked_by");
+  }
+  let n = a_cli
+    .execute(
+      format!(
+        "UPDATE general_schema SET {} = $1, \
+          label_meta = label_meta || jsonb_build_object($2::TEXT, \
+            (coalesce(label_meta -> $2::TEXT, '{{}}') - $6::TEXT[]) \
+            || $3::TEXT::JSONB) \
+        WHERE id = $4 AND ($5 OR NOT coalesce( \
+          (label_meta -> $2::TEXT ->> 'locked')::BOOLEAN, false))",
+        a_taxonomy.column
+      )
+      .as_str(),
+      &[
+        &a_label,
+        &a_taxonomy.name,
+        &meta.to_string(),
+        &a_id,
+        &(a_provenance.source == Some(SourceT::User)),
+        &&PROVENANCE_KEYS[..],
+      ],
+    )
+    .await?;
+
+  Ok(n > 0)
+}
+
+/// Lock or unlock the labels of the taxonomy of some rows. Locking marks
+/// the label as verified by the user; the rest of its provenance is
+/// kept.
+pub async fn lock_labels(
+  a_cli: &Client,
+  a_taxonomy: &Taxonomy,
+  a_ids: &[i32],
+  a_user: &str,
+  a_locked: bool,
+) -> Result<u64, Box<dyn Error>>
+{
+  let n = a_cli
+    .execute(
+      "UPDATE general_schema SET label_meta = label_meta \
+        || jsonb_build_object($1::TEXT, \
+          coalesce(label_meta -> $1::TEXT, '{}') || jsonb_build_object( \
+            'locked', $2::BOOLEAN, 'locked_by', $3::TEXT)) \
+      WHERE id = ANY($4)",
+      &[&a_taxonomy.name, &a_locked, &a_user, &a_ids],
+    )
+    .await?;
+
+  Ok(n)
+}
+
+/// A change of label_audit
+#[derive(Debug, Clone)]
+pub struct LabelChange
+{
+  pub taxonomy: String,
+  pub old_label: Option<String>,
+  pub new_label: Option<String>,
+  pub source: Option<String>,
+  pub reference: Option<String>,
+  pub prompt: Option<String>,
+  pub import_run_id: Option<i32>,
+  pub locked: bool,
+  pub changed_by: String,
+  pub changed_at: String,
+}
+
+/// Label history of a row, oldest first
+pub async fn label_history(
+  a_cli: &Client,
+  a_id: i32,
+) -> Result<Vec<LabelChange>, Box<dyn Error>>
+{
+  let rows = a_cli
+    .query(
+      "SELECT taxonomy, old_label, new_label, source, reference, prompt, \
+        import_run_id, locked, changed_by, changed_at::TEXT \
+      FROM label_audit WHERE general_schema_id = $1 ORDER BY id",
+      &[&a_id],
+    )
+    .await?;
+
+  Ok(
+    rows
+      .iter()
+      .map(|r| LabelChange {
+        taxonomy: r.get(0),
+        old_label: r.get(1),
+        new_label: r.get(2),
+        source: r.get(3),
+        reference: r.get(4),
+        prompt: r.get(5),
+        import_run_id: r.get(6),
+        locked: r.get(7),
+        changed_by: r.get(8),
+        changed_at: r.get(9),
+      })
+      .collect(),
+  )
+}
+
+/// Store the labels of all stored rows again as their taxonomies read
+/// them, e.g. after adding aliases. Values outside a taxonomy become
+/// pending; locked labels are kept. Returns the number of changed
+/// values.
+pub async fn normalize_stored_labels(
+  a_cli: &Client,
+) -> Result<u64, Box<dyn Error>>
+{
+  let mut changed = 0;
+
+  for t in TAXONOMIES.iter() {
+    prepare_taxonomy(a_cli, t).await?;
+    let rows = a_cli
+      .query(
+        format!(
+          "SELECT DISTINCT {} FROM general_schema WHERE {} IS NOT NULL",
+          t.column, t.column
+        )
+        .as_str(),
+        &[],
+      )
+      .await?;
+
+    let meta = serde_json::to_string(&Provenance::rule("normalize"))?;
+    for r in rows {
+      let value: String = r.get(0);
+      let label = t.normalize(Some(&value));
+      if label == value {
+        continue;
+      }
+      changed += a_cli
+        .execute(
+          format!(
+            "UPDATE general_schema SET {col} = $1, label_meta = label_meta \
+              || jsonb_build_object($3::TEXT, $4::TEXT::JSONB) \
+            WHERE {col} = $2 AND NOT coalesce( \
+              (label_meta -> $3::TEXT ->> 'locked')::BOOLEAN, false)",
+            col = t.column
+          )
+          .as_str(),
+          &[&label, &value, &t.name, &meta],
+        )
+        .await?;
+    }
+  }
+
+  Ok(changed)
+}
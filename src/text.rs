@@ -0,0 +1,666 @@
+use crate::schemas::GeneralSchema;
+use rust_decimal::Decimal;
+use std::collections::HashMap;
+use std::error::Error;
+use std::str::FromStr;
+use std::sync::LazyLock;
+use tokio_postgres::Client;
+
+/// Bundled dictionary of abbreviations met in product descriptions,
+/// in lower case, with their expansions
+const ABBREVIATIONS_CSV: &str =
+  include_str!("../references/abbreviations.csv");
+
+/// Rows read per query when normalizing stored rows
+const NORMALIZE_PAGE: i64 = 5000;
+
+/// Latin letters typed for their Cyrillic look-alikes, in lower case
+const HOMOGLYPHS: &[(char, char)] = &[
+  ('a', 'а'),
+  ('b', 'в'),
+  ('c', 'с'),
+  ('e', 'е'),
+  ('h', 'н'),
+  ('k', 'к'),
+  ('m', 'м'),
+  ('o', 'о'),
+  ('p', 'р'),
+  ('t', 'т'),
+  ('x', 'х'),
+  ('y', 'у'),
+];
+
+/// Letters of the Kazakh alphabet not used in Russian
+const KAZAKH_LETTERS: &[char] =
+  &['ә', 'ғ', 'қ', 'ң', 'ө', 'ұ', 'ү', 'һ', 'і'];
+
+/// Separators of dimensions, "1200х600", "170 x 108", "50*70"
+const DIMENSION_SEPARATORS: &[char] = &['х', 'x', '×', '*'];
+
+/// Length units with their size in mm
+const LENGTH_UNITS: &[(&str, i64)] = &[
+  ("мм", 1),
+  ("mm", 1),
+  ("см", 10),
+  ("cm", 10),
+  ("м", 1000),
+  ("m", 1000),
+];
+
+/// Spellings of kg/m³ following a density
+pub const DENSITY_UNITS: &[&str] =
+  &["кг/м3", "кг/м³", "кг/куб", "kg/m3", "kg/m³"];
+
+/// Share of Cyrillic or Latin letters above which a text is taken for
+/// Russian or English
+const LANGUAGE_SHARE: f64 = 0.7;
+
+/// Language of a description
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LangT
+{
+  Ru,
+  Kk,
+  En,
+  /// Comparable amounts of Cyrillic and Latin text
+  Mixed,
+}
+
+impl LangT
+{
+  /// Value stored in general_schema.description_lang
+  pub fn code(&self) -> &'static str
+  {
+    match self {
+      LangT::Ru => "ru",
+      LangT::Kk => "kk",
+      LangT::En => "en",
+      LangT::Mixed => "mixed",
+    }
+  }
+}
+
+/// Dimensions of a product in mm, largest first
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Dimensions
+{
+  pub length_mm: Option<Decimal>,
+  pub width_mm: Option<Decimal>,
+  pub thickness_mm: Option<Decimal>,
+  /// A length unit was written, rather than mm assumed
+  pub unit_given: bool,
+}
+
+/// A description prepared for embedding and prompting, with what was
+/// read from it
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TextFeatures
+{
+  pub text: String,
+  pub language: Option<LangT>,
+  pub dimensions: Dimensions,
+  /// Declared density in kg/m³
+  pub density_kg_m3: Option<Decimal>,
+}
+
+/// The bundled dictionary, parsed on first use
+pub static ABBREVIATIONS: LazyLock<HashMap<String, String>> =
+  LazyLock::new(|| {
+    let mut out = HashMap::new();
+    let mut reader =
+      csv::Reader::from_reader(ABBREVIATIONS_CSV.as_bytes());
+    for record in reader.records() {
+      let rec = record.expect("Invalid abbreviations.csv");
+      out.insert(
+        rec.get(0).unwrap_or("").trim().to_lowercase(),
+        rec.get(1).unwrap_or("").trim().to_string(),
+      );
+    }
+    out
+  });
+
+fn is_cyrillic(a_char: char) -> bool
+{
+  matches!(a_char, 'а'..='я' | 'ё')
+    || KAZAKH_LETTERS.contains(&a_char)
+}
+
+/// Replace Latin look-alikes inside Cyrillic words ("полиmеров",
+/// "cтирола"). Words without Cyrillic letters, or with Latin letters
+/// that have no look-alike, are kept.
+fn fix_homoglyphs(a_text: &str) -> String
+{
+  let mut out = String::with_capacity(a_text.len());
+  let mut word = String::new();
+  let flush = |word: &mut String, out: &mut String| {
+    let mixed = word.chars().any(is_cyrillic)
+      && word
+        .chars()
+        .filter(|x| x.is_ascii_alphabetic())
+        .all(|x| HOMOGLYPHS.iter().any(|(l, _)| *l == x));
+    for c in word.chars() {
+      match HOMOGLYPHS.iter().find(|(l, _)| mixed && *l == c) {
+        Some((_, cyr)) => out.push(*cyr),
+        None => out.push(c),
+      }
+    }
+    word.clear();
+  };
+
+  for c in a_text.chars() {
+    if c.is_alphabetic() {
+      word.push(c);
+    } else {
+      flush(&mut word, &mut out);
+      out.push(c);
+    }
+  }
+  flush(&mut word, &mut out);
+
+  out
+}
+
+/// Expand the abbreviations of the dictionary. Tokens are matched
+/// whole, without surrounding punctuation; "эппс." at the end of a
+/// sentence matches "эппс".
+fn expand_abbreviations(a_text: &str) -> String
+{
+  a_text
+    .split_whitespace()
+    .map(|token| {
+      let core = token.trim_start_matches(['(', '"', '\'', '«']);
+      let prefix = &token[..token.len() - core.len()];
+      let core =
+        core.trim_end_matches([',', ';', ':', ')', '"', '\'', '»']);
+      let suffix = &token[prefix.len() + core.len()..];
+
+      if let Some(x) = ABBREVIATIONS.get(core) {
+        return format!("{}{}{}", prefix, x, suffix);
+      }
+      match core.strip_suffix('.').and_then(|x| ABBREVIATIONS.get(x))
+      {
+        Some(x) => format!("{}{}.{}", prefix, x, suffix),
+        None => token.to_string(),
+      }
+    })
+    .collect::<Vec<String>>()
+    .join(" ")
+}
+
+/// Language of a text from its letters: Kazakh when it has letters of
+/// the Kazakh alphabet, otherwise Russian or English by the share of
+/// Cyrillic and Latin letters. None for a text without letters.
+pub fn detect_language(a_text: &str) -> Option<LangT>
+{
+  let lower = a_text.to_lowercase();
+  let cyr = lower.chars().filter(|x| is_cyrillic(*x)).count();
+  let lat = lower.chars().filter(|x| x.is_ascii_alphabetic()).count();
+  let total = cyr + lat;
+  if total == 0 {
+    return None;
+  }
+
+  let share = cyr as f64 / total as f64;
+  if lower.chars().any(|x| KAZAKH_LETTERS.contains(&x)) && cyr >= lat
+  {
+    Some(LangT::Kk)
+  } else if share >= LANGUAGE_SHARE {
+    Some(LangT::Ru)
+  } else if 1.0 - share >= LANGUAGE_SHARE {
+    Some(LangT::En)
+  } else {
+    Some(LangT::Mixed)
+  }
+}
+
+/// Number starting at `a_pos`, written with a decimal point or comma,
+/// and the position after it. Digits following a letter or digit, as in
+/// part numbers ("84a204576"), are not numbers, unless the letter is a
+/// dimension separator ("1200х600").
+fn number_at(
+  a_chars: &[char],
+  a_pos: usize,
+) -> Option<(Decimal, usize)>
+{
+  if a_pos > 0
+    && a_chars[a_pos - 1].is_alphanumeric()
+    && !(DIMENSION_SEPARATORS.contains(&a_chars[a_pos - 1])
+      && (a_pos < 2 || !a_chars[a_pos - 2].is_alphabetic()))
+  {
+    return None;
+  }
+  let mut end = a_pos;
+  while end < a_chars.len() && a_chars[end].is_ascii_digit() {
+    end += 1;
+  }
+  if end == a_pos {
+    return None;
+  }
+  if end + 1 < a_chars.len()
+    && (a_chars[end] == '.' || a_chars[end] == ',')
+    && a_chars[end + 1].is_ascii_digit()
+  {
+    end += 1;
+    while end < a_chars.len() && a_chars[end].is_ascii_digit() {
+      end += 1;
+    }
+  }
+
+  let s: String = a_chars[a_pos..end].iter().collect();
+  let n = Decimal::from_str(&s.replace(',', ".")).ok()?;
+
+  Some((n.normalize(), end))
+}
+
+fn skip_spaces(a_chars: &[char], a_pos: usize) -> usize
+{
+  let mut pos = a_pos;
+  while pos < a_chars.len() && a_chars[pos] == ' ' {
+    pos += 1;
+  }
+  pos
+}
+
+/// Length unit starting at `a_pos` as its size in mm and the position
+/// after it. The unit must be a whole word.
+fn length_unit_at(
+  a_chars: &[char],
+  a_pos: usize,
+) -> Option<(i64, usize)>
+{
+  let mut end = a_pos;
+  while end < a_chars.len() && a_chars[end].is_alphabetic() {
+    end += 1;
+  }
+  let word: String = a_chars[a_pos..end].iter().collect();
+
+  LENGTH_UNITS
+    .iter()
+    .find(|(u, _)| *u == word)
+    .map(|(_, mm)| (*mm, end))
+}
+
+/// A run of dimensions in a text: "1200х600х50 мм", "50 х 70 см",
+/// "1200х600х10/35" (a slope element, the thinner end is kept)
+struct DimensionRun
+{
+  end: usize,
+  values: Vec<Decimal>,
+  /// Second value of a range written after the last dimension
+  range: Option<Decimal>,
+  unit: Option<(String, i64)>,
+}
+
+impl DimensionRun
+{
+  /// The run written as "1200х600х50 мм"
+  fn canonical(&self) -> String
+  {
+    let mut out = self
+      .values
+      .iter()
+      .map(|x| x.to_string())
+      .collect::<Vec<String>>()
+      .join("х");
+    if let Some(r) = self.range {
+      out.push_str(&format!("/{}", r));
+    }
+    if let Some((u, _)) = &self.unit {
+      out.push_str(&format!(" {}", u));
+    }
+    out
+  }
+
+  /// Dimensions in mm, largest first. Values without a unit are taken
+  /// for mm.
+  fn dimensions(&self) -> Dimensions
+  {
+    let mm =
+      Decimal::from(self.unit.as_ref().map(|x| x.1).unwrap_or(1));
+    let mut values: Vec<Decimal> =
+      self.values.iter().map(|x| (x * mm).normalize()).collect();
+    values.sort_by(|a, b| b.cmp(a));
+
+    Dimensions {
+      length_mm: values.first().cloned(),
+      width_mm: values.get(1).cloned(),
+      thickness_mm: values.get(2).cloned(),
+      unit_given: self.unit.is_some(),
+    }
+  }
+}
+
+/// Dimension run starting at `a_pos`: two or three numbers joined by
+/// `DIMENSION_SEPARATORS`
+fn dimension_run_at(
+  a_chars: &[char],
+  a_pos: usize,
+) -> Option<DimensionRun>
+{
+  let (first, mut end) = number_at(a_chars, a_pos)?;
+  let mut values = vec![first];
+
+  while values.len() < 3 {
+    let pos = skip_spaces(a_chars, end);
+    if pos >= a_chars.len()
+      || !DIMENSION_SEPARATORS.contains(&a_chars[pos])
+    {
+      break;
+    }
+    match number_at(a_chars, skip_spaces(a_chars, pos + 1)) {
+      Some((n, e)) => {
+        values.push(n);
+        end = e;
+      }
+      None => break,
+    }
+  }
+  if values.len() < 2 {
+    return None;
+  }
+
+  let mut range = None;
+  if let Some((n, e)) = a_chars
+    .get(end)
+    .filter(|x| **x == '/')
+    .and_then(|_| number_at(a_chars, end + 1))
+  {
+    range = Some(n);
+    end = e;
+  }
+
+  let mut unit = None;
+  if let Some((mm, e)) =
+    length_unit_at(a_chars, skip_spaces(a_chars, end))
+  {
+    let u: String =
+      a_chars[skip_spaces(a_chars, end)..e].iter().collect();
+    unit = Some((u, mm));
+    end = e;
+  }
+
+  Some(DimensionRun { end, values, range, unit })
+}
+
+/// First number after a word starting with `a_stem` ("толщина 50 мм",
+/// "плотностью: 35") and the position after it
+fn number_after(
+  a_chars: &[char],
+  a_stem: &str,
+) -> Option<(Decimal, usize)>
+{
+  let stem: Vec<char> = a_stem.chars().collect();
+  let start = (0..a_chars.len()).find(|i| {
+    a_chars[*i..].starts_with(&stem)
+      && (*i == 0 || !a_chars[*i - 1].is_alphabetic())
+  })?;
+
+  let mut pos = start + stem.len();
+  while pos < a_chars.len() && a_chars[pos].is_alphabetic() {
+    pos += 1;
+  }
+  while pos < a_chars.len()
+    && matches!(a_chars[pos], ' ' | ':' | '-' | '=')
+  {
+    pos += 1;
+  }
+
+  number_at(a_chars, pos)
+}
+
+/// Thickness in mm written out, "толщина 50 мм" or "толщиной 5 см"
+fn thickness_of(a_chars: &[char]) -> Option<Decimal>
+{
+  let (n, end) = number_after(a_chars, "толщин")?;
+  match length_unit_at(a_chars, skip_spaces(a_chars, end)) {
+    Some((mm, _)) => Some((n * Decimal::from(mm)).normalize()),
+    None => Some(n),
+  }
+}
+
+/// Density in kg/m³: a number followed by a kg/m³ unit, or the number
+/// after "плотность"
+fn density_of(a_chars: &[char]) -> Option<Decimal>
+{
+  let units: Vec<Vec<char>> =
+    DENSITY_UNITS.iter().map(|x| x.chars().collect()).collect();
+
+  for pos in 0..a_chars.len() {
+    if let Some((n, end)) = number_at(a_chars, pos) {
+      let after = skip_spaces(a_chars, end);
+      if units.iter().any(|u| a_chars[after..].starts_with(u)) {
+        return Some(n);
+      }
+    }
+  }
+
+  number_after(a_chars, "плотност").map(|x| x.0)
+}
+
+/// Prepare a description: lower case, Latin look-alikes in Cyrillic
+/// words replaced, abbreviations expanded, dimensions written as
+/// "1200х600х50 мм" and whitespace collapsed. Dimensions, density and
+/// language are read on the way.
+pub fn preprocess(a_text: &str) -> TextFeatures
+{
+  let lower = a_text
+    .trim()
+    .trim_matches('`')
+    .to_lowercase()
+    .replace('ё', "е");
+  let fixed = fix_homoglyphs(&lower);
+
+  // A space after commas glued to the next word, "вставок,внутренних"
+  let mut spaced = String::with_capacity(fixed.len());
+  let mut chars = fixed.chars().peekable();
+  while let Some(c) = chars.next() {
+    spaced.push(c);
+    if (c == ',' || c == ';')
+      && chars.peek().map(|x| x.is_alphabetic()).unwrap_or(false)
+    {
+      spaced.push(' ');
+    }
+  }
+
+  let chars: Vec<char> =
+    expand_abbreviations(&spaced).chars().collect();
+  let mut text = String::with_capacity(chars.len());
+  let mut dimensions: Option<Dimensions> = None;
+  let mut pos = 0;
+  while pos < chars.len() {
+    if let Some(run) = dimension_run_at(&chars, pos) {
+      if dimensions.is_none() {
+        dimensions = Some(run.dimensions());
+      }
+      text.push_str(&run.canonical());
+      pos = run.end;
+      continue;
+    }
+    text.push(chars[pos]);
+    pos += 1;
+  }
+  let text = text.split_whitespace().collect::<Vec<&str>>().join(" ");
+
+  let chars: Vec<char> = text.chars().collect();
+  let mut dimensions = dimensions.unwrap_or_default();
+  if dimensions.thickness_mm.is_none() {
+    dimensions.thickness_mm = thickness_of(&chars);
+  }
+
+  TextFeatures {
+    language: detect_language(&text),
+    density_kg_m3: density_of(&chars),
+    dimensions,
+    text,
+  }
+}
+
+/// Raw product description of a row, both description columns joined
+pub fn row_description(
+  a_desc_1: Option<&str>,
+  a_desc_2: Option<&str>,
+) -> Option<String>
+{
+  let parts: Vec<&str> = [a_desc_1, a_desc_2]
+    .into_iter()
+    .flatten()
+    .map(|x| x.trim())
+    .filter(|x| !x.is_empty())
+    .collect();
+
+  match parts.len() {
+    0 => None,
+    _ => Some(parts.join(" ")),
+  }
+}
+
+/// Fill description_norm, description_lang and the attributes read
+/// from the description of a converted row
+pub fn preprocess_description(a_row: &mut GeneralSchema)
+{
+  let features = row_description(
+    a_row.product_description_1.as_deref(),
+    a_row.product_description_2.as_deref(),
+  )
+  .map(|x| preprocess(&x))
+  .unwrap_or_default();
+
+  a_row.description_norm = match features.text.is_empty() {
+    true => None,
+    false => Some(features.text),
+  };
+  a_row.description_lang =
+    features.language.map(|x| x.code().to_string());
+  a_row.length_mm = features.dimensions.length_mm;
+  a_row.width_mm = features.dimensions.width_mm;
+  a_row.thickness_mm = features.dimensions.thickness_mm;
+  a_row.density_kg_m3 = features.density_kg_m3;
+}
+
+/// Recompute the prepared descriptions and their attributes of every
+/// stored row, e.g. for rows imported before descriptions were
+/// prepared or after extending the abbreviations. Returns the rows
+/// changed.
+pub async fn normalize_stored_text(
+  a_cli: &Client,
+) -> Result<u64, Box<dyn Error>>
+{
+  let mut last_id = 0;
+  let mut changed = 0;
+
+  loop {
+    let rows = a_cli
+      .query(
+        "SELECT id, product_description_1, product_description_2 \
+        FROM general_schema WHERE id > $1 ORDER BY id LIMIT $2",
+        &[&last_id, &NORMALIZE_PAGE],
+      )
+      .await?;
+    if rows.is_empty() {
+      break;
+    }
+
+    let mut ids = Vec::with_capacity(rows.len());
+    let mut texts = Vec::with_capacity(rows.len());
+    let mut langs = Vec::with_capacity(rows.len());
+    // Length, width, thickness and density
+    let mut values: Vec<Vec<Option<Decimal>>> = vec![Vec::new(); 4];
+    for r in &rows {
+      let desc_1: Option<String> = r.get(1);
+      let desc_2: Option<String> = r.get(2);
+      let f = row_description(desc_1.as_deref(), desc_2.as_deref())
+        .map(|x| preprocess(&x))
+        .unwrap_or_default();
+
+      ids.push(r.get::<_, i32>(0));
+      texts.push(match f.text.is_empty() {
+        true => None,
+        false => Some(f.text),
+      });
+      langs.push(f.language.map(|x| x.code().to_string()));
+      values[0].push(f.dimensions.length_mm);
+      values[1].push(f.dimensions.width_mm);
+      values[2].push(f.dimensions.thickness_mm);
+      values[3].push(f.density_kg_m3);
+    }
+    last_id = *ids.last().unwrap_or(&last_id);
+
+    changed += a_cli
+      .execute(
+        "UPDATE general_schema g SET description_norm = u.text, \
+          description_lang = u.lang, length_mm = u.length, \
+          width_mm = u.width, thickness_mm = u.thickness, \
+          density_kg_m3 = u.density \
+        FROM UNNEST($1::INTEGER[], $2::TEXT[], $3::TEXT[], \
+          $4::NUMERIC[], $5::NUMERIC[], $6::NUMERIC[], $7::NUMERIC[]) \
+          AS u(id, text, lang, length, width, thickness, density) \
+        WHERE g.id = u.id AND (g.description_norm, g.description_lang, \
+            g.length_mm, g.width_mm, g.thickness_mm, g.density_kg_m3) \
+          IS DISTINCT FROM (u.text, u.lang, u.length, u.width, \
+            u.thickness, u.density)",
+        &[
+          &ids, &texts, &langs, &values[0], &values[1], &values[2],
+          &values[3],
+        ],
+      )
+      .await?;
+  }
+
+  Ok(changed)
+}
+
+#[cfg(test)]
+mod tests
+{
+  use super::*;
+
+  fn mm(a_value: i64) -> Option<Decimal>
+  {
+    Some(Decimal::from(a_value))
+  }
+
+  #[test]
+  fn abbreviations_dimensions_and_density()
+  {
+    let f =
+      preprocess("ПЛИТЫ ЭППС 1200*600*50ММ, ПЛОТНОСТЬ 35 КГ/М3");
+    assert_eq!(
+      f.text,
+      "плиты экструдированный пенополистирол 1200х600х50 мм, \
+      плотность 35 кг/м3"
+    );
+    assert_eq!(f.language, Some(LangT::Ru));
+    assert_eq!(f.dimensions.length_mm, mm(1200));
+    assert_eq!(f.dimensions.width_mm, mm(600));
+    assert_eq!(f.dimensions.thickness_mm, mm(50));
+    assert!(f.dimensions.unit_given);
+    assert_eq!(f.density_kg_m3, mm(35));
+  }
+
+  #[test]
+  fn homoglyphs_and_metres()
+  {
+    let f = preprocess("Плиты из полиmеров cтирола 1,2х0,6х0,05 м");
+    assert_eq!(f.text, "плиты из полимеров стирола 1.2х0.6х0.05 м");
+    assert_eq!(f.dimensions.length_mm, mm(1200));
+    assert_eq!(f.dimensions.thickness_mm, mm(50));
+  }
+
+  #[test]
+  fn thickness_and_language()
+  {
+    let f = preprocess("вата минеральная,толщина 50 мм");
+    assert_eq!(f.text, "вата минеральная, толщина 50 мм");
+    assert_eq!(f.dimensions.thickness_mm, mm(50));
+    assert_eq!(f.dimensions.length_mm, None);
+
+    let f = preprocess("Foam board XPS 1200x600x30");
+    assert_eq!(f.text, "foam board xps 1200х600х30");
+    assert_eq!(f.language, Some(LangT::En));
+    assert!(!f.dimensions.unit_given);
+
+    let f = preprocess("жылу оқшаулағыш тақталар");
+    assert_eq!(f.language, Some(LangT::Kk));
+    assert_eq!(preprocess("  "), TextFeatures::default());
+  }
+}
@@ -0,0 +1,289 @@
+use crate::conf::TnvedConfig;
+use crate::schemas::GeneralSchema;
+use crate::sources::{InputOptions, open_source};
+use csv::StringRecord;
+use std::collections::HashMap;
+use std::error::Error;
+use std::path::PathBuf;
+use tokio_postgres::Client;
+
+/// Bundled TN VED EAEU reference: every chapter and the headings,
+/// subheadings and codes of the products classified here
+const TNVED_CSV: &str = include_str!("../references/tnved.csv");
+
+/// Rows read per query when normalizing stored rows
+const NORMALIZE_PAGE: i64 = 5000;
+
+/// Digits of the chapter, heading, subheading and code levels of the
+/// TN VED hierarchy
+const TNVED_LEVELS: &[usize] = &[2, 4, 6, 10];
+
+/// TN VED codes with their descriptions, at any level
+#[derive(Debug, Clone, Default)]
+pub struct Tnved
+{
+  pub codes: HashMap<String, String>,
+  /// Every 10-digit code is listed, so an unknown one is an error
+  pub complete: bool,
+}
+
+impl Tnved
+{
+  /// The bundled reference
+  pub fn bundled() -> Self
+  {
+    let mut reader = csv::Reader::from_reader(TNVED_CSV.as_bytes());
+    let headers =
+      reader.headers().expect("Invalid tnved.csv").clone();
+    let rows =
+      reader.into_records().map(|x| x.map_err(|e| e.to_string()));
+
+    let mut out = Tnved::default();
+    out.read(&headers, rows).expect("Invalid tnved.csv");
+
+    out
+  }
+
+  /// Add the codes of rows with `code` and `description` columns. Codes
+  /// may be written with spaces ("3921 13 100 0").
+  fn read(
+    &mut self,
+    a_headers: &StringRecord,
+    a_rows: impl Iterator<Item = Result<StringRecord, String>>,
+  ) -> Result<u64, Box<dyn Error>>
+  {
+    let column = |name: &str| {
+      a_headers
+        .iter()
+        .position(|x| x.trim().eq_ignore_ascii_case(name))
+        .ok_or(format!("no {} column", name))
+    };
+    let (i_code, i_desc) = (column("code")?, column("description")?);
+
+    let mut n = 0;
+    for record in a_rows {
+      let rec = record?;
+      let code = match tnved_digits(rec.get(i_code).unwrap_or("")) {
+        Some(x) if TNVED_LEVELS.contains(&x.len()) => x,
+        _ => continue,
+      };
+      let desc = rec.get(i_desc).unwrap_or("").trim().to_string();
+      self.codes.insert(code, desc);
+      n += 1;
+    }
+
+    Ok(n)
+  }
+
+  /// The bundled reference completed with the file of `[tnved]
+  /// reference`, which is expected to list every code
+  pub fn load(a_conf: &TnvedConfig) -> Result<Self, Box<dyn Error>>
+  {
+    let mut out = Tnved::bundled();
+    if let Some(path) = &a_conf.reference {
+      out.load_file(path)?;
+    }
+
+    Ok(out)
+  }
+
+  /// Add the codes of a CSV file or spreadsheet and mark the
+  /// reference complete
+  pub fn load_file(
+    &mut self,
+    a_path: &PathBuf,
+  ) -> Result<u64, Box<dyn Error>>
+  {
+    let source = open_source(a_path, &InputOptions::default())?;
+    let rows = source.rows.map(|x| {
+      x.map_err(|(line, e)| {
+        format!("line {}: {}", line.unwrap_or(0), e)
+      })
+    });
+    let n = self
+      .read(&source.headers, rows)
+      .map_err(|e| format!("{:?}: {}", a_path, e))?;
+    self.complete = true;
+
+    Ok(n)
+  }
+
+  pub fn contains(&self, a_code: &str) -> bool
+  {
+    self.codes.contains_key(a_code)
+  }
+
+  /// Digits of the most detailed level of `a_code` in the reference
+  fn depth(&self, a_code: &str) -> usize
+  {
+    TNVED_LEVELS
+      .iter()
+      .rev()
+      .find(|l| **l <= a_code.len() && self.contains(&a_code[..**l]))
+      .cloned()
+      .unwrap_or(0)
+  }
+}
+
+/// Digits of a code written with spaces, dots or dashes, or as a float
+/// ("3921131000.0"). None for placeholders without digits and values
+/// with other characters.
+pub fn tnved_digits(a_value: &str) -> Option<String>
+{
+  let s = a_value.trim();
+  let s = s.strip_suffix(".0").unwrap_or(s);
+  let digits: String = s
+    .chars()
+    .filter(|x| !x.is_whitespace() && *x != '.' && *x != '-')
+    .collect();
+
+  match !digits.is_empty()
+    && digits.chars().all(|x| x.is_ascii_digit())
+  {
+    true => Some(digits),
+    false => None,
+  }
+}
+
+/// 10-digit code of a declared value. Nine digits lost either the
+/// leading zero of chapters 01-09 or the trailing zero; the reading
+/// known deeper in `a_ref` wins, the trailing zero on a tie.
+pub fn normalize_tnved(a_value: &str, a_ref: &Tnved)
+-> Option<String>
+{
+  let d = tnved_digits(a_value)?;
+  if d.len() != 9 {
+    return Some(d);
+  }
+
+  let trailing = format!("{}0", d);
+  let leading = format!("0{}", d);
+  match a_ref.depth(&leading) > a_ref.depth(&trailing) {
+    true => Some(leading),
+    false => Some(trailing),
+  }
+}
+
+/// Why a normalized code is not a TN VED code, None when it is one.
+/// An unknown 10-digit code is only an issue with a complete reference.
+pub fn tnved_issue(a_code: &str, a_ref: &Tnved) -> Option<String>
+{
+  if a_code.len() != 10 || !a_code.chars().all(|x| x.is_ascii_digit())
+  {
+    return Some(format!(
+      "TN VED code '{}' is not 10 digits",
+      a_code
+    ));
+  }
+  if !a_ref.contains(&a_code[..2]) {
+    return Some(format!(
+      "TN VED code '{}' has no chapter {}",
+      a_code,
+      &a_code[..2]
+    ));
+  }
+  if a_ref.complete && !a_ref.contains(a_code) {
+    return Some(format!(
+      "TN VED code '{}' is not in the reference",
+      a_code
+    ));
+  }
+
+  None
+}
+
+/// Fill tnved_norm of a converted row and return the issue of its code
+/// as a (field, reason) pair
+pub fn check_tnved(
+  a_row: &mut GeneralSchema,
+  a_ref: &Tnved,
+) -> Option<(String, String)>
+{
+  a_row.tnved_norm = a_row
+    .tnved_code
+    .as_deref()
+    .and_then(|x| normalize_tnved(x, a_ref));
+  // Placeholders such as "-" are not flagged
+  let declared = a_row.tnved_code.as_deref().unwrap_or("");
+  if !declared.chars().any(|x| x.is_alphanumeric()) {
+    return None;
+  }
+
+  let reason = match &a_row.tnved_norm {
+    Some(x) => tnved_issue(x, a_ref)?,
+    None => format!("TN VED code '{}' is not a number", declared),
+  };
+
+  Some(("tnved_code".to_string(), reason))
+}
+
+/// Store the reference in the tnved table, for aggregating by
+/// chapter, heading or subheading with their descriptions. Returns the
+/// codes stored.
+pub async fn store_tnved(
+  a_cli: &Client,
+  a_ref: &Tnved,
+) -> Result<u64, Box<dyn Error>>
+{
+  let (codes, descriptions): (Vec<&String>, Vec<&String>) =
+    a_ref.codes.iter().unzip();
+
+  a_cli
+    .execute(
+      "INSERT INTO tnved (code, description) \
+      SELECT * FROM UNNEST($1::TEXT[], $2::TEXT[]) \
+      ON CONFLICT (code) DO UPDATE SET description = EXCLUDED.description \
+      WHERE tnved.description IS DISTINCT FROM EXCLUDED.description",
+      &[&codes, &descriptions],
+    )
+    .await?;
+
+  Ok(codes.len() as u64)
+}
+
+/// Recompute tnved_norm of every stored row, e.g. for rows imported
+/// before codes were normalized or after loading a fuller reference.
+/// Returns the rows changed.
+pub async fn normalize_stored_tnved(
+  a_cli: &Client,
+  a_ref: &Tnved,
+) -> Result<u64, Box<dyn Error>>
+{
+  let mut last_id = 0;
+  let mut changed = 0;
+
+  loop {
+    let rows = a_cli
+      .query(
+        "SELECT id, tnved_code FROM general_schema \
+        WHERE id > $1 ORDER BY id LIMIT $2",
+        &[&last_id, &NORMALIZE_PAGE],
+      )
+      .await?;
+    if rows.is_empty() {
+      break;
+    }
+
+    let mut ids = Vec::with_capacity(rows.len());
+    let mut codes = Vec::with_capacity(rows.len());
+    for r in &rows {
+      let code: Option<String> = r.get(1);
+      ids.push(r.get::<_, i32>(0));
+      codes.push(
+        code.as_deref().and_then(|x| normalize_tnved(x, a_ref)),
+      );
+    }
+    last_id = *ids.last().unwrap_or(&last_id);
+
+    changed += a_cli
+      .execute(
+        "UPDATE general_schema g SET tnved_norm = u.code \
+        FROM UNNEST($1::INTEGER[], $2::TEXT[]) AS u(id, code) \
+        WHERE g.id = u.id AND g.tnved_norm IS DISTINCT FROM u.code",
+        &[&ids, &codes],
+      )
+      .await?;
+  }
+
+  Ok(changed)
+}
@@ -0,0 +1,416 @@
+use crate::conf::DensityConfig;
+use crate::schemas::GeneralSchema;
+use rust_decimal::Decimal;
+use std::error::Error;
+use std::str::FromStr;
+use tokio_postgres::Client;
+
+/// Densities in kg/m³ used when `[density]` does not set a label
+pub const DEFAULT_DENSITIES: &[(&str, f64)] = &[
+  ("xps", 35.0),
+  ("eps", 20.0),
+  ("pir", 40.0),
+  ("glasswool", 15.0),
+  ("stonewool", 100.0),
+];
+
+/// Rows read per query when normalizing stored rows
+const NORMALIZE_PAGE: i64 = 5000;
+
+/// Canonical units quantities are converted to
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UnitT
+{
+  M3,
+  M2,
+  M,
+  Kg,
+  Piece,
+  Roll,
+  Pack,
+  Set,
+  Pair,
+}
+
+impl UnitT
+{
+  /// Value stored in general_schema.unit_norm
+  pub fn code(&self) -> &'static str
+  {
+    match self {
+      UnitT::M3 => "m3",
+      UnitT::M2 => "m2",
+      UnitT::M => "m",
+      UnitT::Kg => "kg",
+      UnitT::Piece => "pcs",
+      UnitT::Roll => "roll",
+      UnitT::Pack => "pack",
+      UnitT::Set => "set",
+      UnitT::Pair => "pair",
+    }
+  }
+}
+
+/// OKEI codes found in the unit code columns, with the factor to the
+/// canonical unit
+const UNIT_CODES: &[(&str, UnitT, &str)] = &[
+  ("113", UnitT::M3, "1"),
+  ("112", UnitT::M3, "0.001"),
+  ("111", UnitT::M3, "0.000001"),
+  ("055", UnitT::M2, "1"),
+  ("006", UnitT::M, "1"),
+  ("166", UnitT::Kg, "1"),
+  ("168", UnitT::Kg, "1000"),
+  ("163", UnitT::Kg, "0.001"),
+  ("796", UnitT::Piece, "1"),
+  ("736", UnitT::Roll, "1"),
+  ("778", UnitT::Pack, "1"),
+  ("839", UnitT::Set, "1"),
+  ("715", UnitT::Pair, "1"),
+];
+
+/// Unit names as written in the exports, upper case without spaces and
+/// dots
+const UNIT_NAMES: &[(&str, UnitT, &str)] = &[
+  ("М3", UnitT::M3, "1"),
+  ("M3", UnitT::M3, "1"),
+  ("КУБМ", UnitT::M3, "1"),
+  ("Л", UnitT::M3, "0.001"),
+  ("ЛИТР", UnitT::M3, "0.001"),
+  ("М2", UnitT::M2, "1"),
+  ("M2", UnitT::M2, "1"),
+  ("КВМ", UnitT::M2, "1"),
+  ("М", UnitT::M, "1"),
+  ("ПОГМ", UnitT::M, "1"),
+  ("КГ", UnitT::Kg, "1"),
+  ("KG", UnitT::Kg, "1"),
+  ("Т", UnitT::Kg, "1000"),
+  ("ТН", UnitT::Kg, "1000"),
+  ("ТОНН", UnitT::Kg, "1000"),
+  ("Г", UnitT::Kg, "0.001"),
+  ("ШТ", UnitT::Piece, "1"),
+  ("PCS", UnitT::Piece, "1"),
+  ("РУЛ", UnitT::Roll, "1"),
+  ("РУЛОН", UnitT::Roll, "1"),
+  ("УП", UnitT::Pack, "1"),
+  ("УПАК", UnitT::Pack, "1"),
+  ("ПАЧ", UnitT::Pack, "1"),
+  ("КОМПЛ", UnitT::Set, "1"),
+  ("ПАР", UnitT::Pair, "1"),
+];
+
+/// Quantities of a row in canonical units
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Quantities
+{
+  pub unit: Option<UnitT>,
+  /// Declared quantity converted to `unit`
+  pub quantity: Option<Decimal>,
+  pub weight_kg: Option<Decimal>,
+}
+
+/// Canonical unit and conversion factor of a unit code or name. The
+/// code wins when both are known.
+pub fn parse_unit(
+  a_code: Option<&str>,
+  a_name: Option<&str>,
+) -> Option<(UnitT, Decimal)>
+{
+  let by_code = a_code
+    .and_then(|x| x.trim().trim_end_matches(".0").parse::<u32>().ok())
+    .map(|x| format!("{:03}", x))
+    .and_then(|x| UNIT_CODES.iter().find(|(c, _, _)| *c == x));
+
+  let by_name = a_name
+    .map(|x| {
+      x.to_uppercase()
+        .replace('³', "3")
+        .replace('²', "2")
+        .chars()
+        .filter(|c| !c.is_whitespace() && *c != '.')
+        .collect::<String>()
+    })
+    .and_then(|x| UNIT_NAMES.iter().find(|(n, _, _)| *n == x));
+
+  let (_, unit, factor) = by_code.or(by_name)?;
+
+  Some((*unit, Decimal::from_str(factor).ok()?))
+}
+
+/// Pick the quantity to normalize from (quantity, unit code, unit name)
+/// pairs: a volume if one is declared, otherwise the first one with a
+/// known unit. The weight is the net weight, or a quantity declared in
+/// mass units when the net weight is missing.
+pub fn normalize_quantities(
+  a_pairs: &[(Option<Decimal>, Option<&str>, Option<&str>)],
+  a_net_weight: Option<Decimal>,
+) -> Quantities
+{
+  let known: Vec<(UnitT, Decimal)> = a_pairs
+    .iter()
+    .filter_map(|(q, code, name)| {
+      let q = q.filter(|x| x.is_sign_positive() && !x.is_zero())?;
+      let (unit, factor) = parse_unit(*code, *name)?;
+      Some((unit, (q * factor).normalize()))
+    })
+    .collect();
+
+  let picked = known
+    .iter()
+    .find(|(u, _)| *u == UnitT::M3)
+    .or(known.first());
+  let weight = a_net_weight
+    .filter(|x| !x.is_zero())
+    .or(known.iter().find(|(u, _)| *u == UnitT::Kg).map(|(_, q)| *q));
+
+  Quantities {
+    unit: picked.map(|(u, _)| *u),
+    quantity: picked.map(|(_, q)| *q),
+    weight_kg: weight,
+  }
+}
+
+/// Fill the normalized quantity fields of a converted row
+pub fn normalize_units(a_row: &mut GeneralSchema)
+{
+  let q = normalize_quantities(
+    &[
+      (
+        a_row.quantity_physical,
+        a_row.additional_unit_code_1.as_deref(),
+        a_row.additional_unit_1.as_deref(),
+      ),
+      (
+        a_row.quantity_additional,
+        a_row.additional_unit_code_2.as_deref(),
+        a_row.additional_unit_2.as_deref(),
+      ),
+    ],
+    a_row.net_weight,
+  );
+
+  a_row.unit_norm = q.unit.map(|x| x.code().to_string());
+  a_row.quantity_norm = q.quantity;
+  a_row.weight_kg = q.weight_kg;
+}
+
+/// Density per label in kg/m³, `[density]` entries over the defaults
+pub fn densities(a_conf: &DensityConfig) -> Vec<(String, Decimal)>
+{
+  let mut out: Vec<(String, f64)> = DEFAULT_DENSITIES
+    .iter()
+    .filter(|(k, _)| !a_conf.contains_key(*k))
+    .map(|(k, v)| (k.to_string(), *v))
+    .collect();
+  out.extend(a_conf.iter().map(|(k, v)| (k.to_lowercase(), *v)));
+
+  out
+    .into_iter()
+    .filter(|(_, v)| *v > 0.0)
+    .filter_map(|(k, v)| Some((k, Decimal::try_from(v).ok()?)))
+    .collect()
+}
+
+/// Label column whose labels the densities are given for
+pub const DENSITY_COLUMN: &str = "eval";
+
+/// Derive volume_m3: the declared volume, or the weight divided by the
+/// density of the row's label in `DENSITY_COLUMN` (volume_estimated).
+/// Limited to the rows of one import run when `a_run_id` is set. Run
+/// again after densities change; relabelled rows are updated with
+/// `update_row_volumes`.
+pub async fn update_volumes(
+  a_cli: &Client,
+  a_density: &DensityConfig,
+  a_run_id: Option<i32>,
+) -> Result<u64, Box<dyn Error>>
+{
+  set_volumes(a_cli, a_density, a_run_id, None).await
+}
+
+/// Derive volume_m3 of the rows `a_ids`, after their label changed
+pub async fn update_row_volumes(
+  a_cli: &Client,
+  a_density: &DensityConfig,
+  a_ids: &[i32],
+) -> Result<u64, Box<dyn Error>>
+{
+  set_volumes(a_cli, a_density, None, Some(a_ids)).await
+}
+
+async fn set_volumes(
+  a_cli: &Client,
+  a_density: &DensityConfig,
+  a_run_id: Option<i32>,
+  a_ids: Option<&[i32]>,
+) -> Result<u64, Box<dyn Error>>
+{
+  let (labels, values): (Vec<String>, Vec<Decimal>) =
+    densities(a_density).into_iter().unzip();
+
+  let n = a_cli
+    .execute(
+      "WITH d AS ( \
+        SELECT * FROM UNNEST($1::TEXT[], $2::NUMERIC[]) \
+        AS d(label, density)) \
+      UPDATE general_schema g SET \
+        volume_m3 = CASE WHEN g.unit_norm = 'm3' THEN g.quantity_norm \
+          ELSE round(g.weight_kg / ( \
+            SELECT density FROM d WHERE d.label = lower(g.eval)), 6) \
+          END, \
+        volume_estimated = g.unit_norm IS DISTINCT FROM 'm3' \
+          AND g.weight_kg IS NOT NULL \
+          AND EXISTS (SELECT 1 FROM d WHERE d.label = lower(g.eval)) \
+      WHERE ($3::INTEGER IS NULL \
+        OR g.import_run_id = $3 OR g.updated_run_id = $3) \
+        AND ($4::INTEGER[] IS NULL OR g.id = ANY($4))",
+      &[&labels, &values, &a_run_id, &a_ids],
+    )
+    .await?;
+
+  Ok(n)
+}
+
+/// Recompute the normalized quantities of every stored row, e.g. for
+/// rows imported before units were normalized. Returns the rows
+/// changed.
+pub async fn normalize_stored_units(
+  a_cli: &Client,
+) -> Result<u64, Box<dyn Error>>
+{
+  let mut last_id = 0;
+  let mut changed = 0;
+
+  loop {
+    let rows = a_cli
+      .query(
+        "SELECT id, quantity_physical, additional_unit_code_1, \
+        additional_unit_1, quantity_additional, additional_unit_code_2, \
+        additional_unit_2, net_weight FROM general_schema \
+        WHERE id > $1 ORDER BY id LIMIT $2",
+        &[&last_id, &NORMALIZE_PAGE],
+      )
+      .await?;
+    if rows.is_empty() {
+      break;
+    }
+
+    let mut ids = Vec::with_capacity(rows.len());
+    let mut units = Vec::with_capacity(rows.len());
+    let mut quantities = Vec::with_capacity(rows.len());
+    let mut weights = Vec::with_capacity(rows.len());
+    for r in &rows {
+      let code_1: Option<String> = r.get(2);
+      let name_1: Option<String> = r.get(3);
+      let code_2: Option<String> = r.get(5);
+      let name_2: Option<String> = r.get(6);
+      let q = normalize_quantities(
+        &[
+          (r.get(1), code_1.as_deref(), name_1.as_deref()),
+          (r.get(4), code_2.as_deref(), name_2.as_deref()),
+        ],
+        r.get(7),
+      );
+
+      ids.push(r.get::<_, i32>(0));
+      units.push(q.unit.map(|x| x.code().to_string()));
+      quantities.push(q.quantity);
+      weights.push(q.weight_kg);
+    }
+    last_id = *ids.last().unwrap_or(&last_id);
+
+    changed += a_cli
+      .execute(
+        "UPDATE general_schema g SET unit_norm = u.unit, \
+          quantity_norm = u.quantity, weight_kg = u.weight \
+        FROM UNNEST($1::INTEGER[], $2::TEXT[], $3::NUMERIC[], \
+          $4::NUMERIC[]) AS u(id, unit, quantity, weight) \
+        WHERE g.id = u.id AND (g.unit_norm, g.quantity_norm, g.weight_kg) \
+          IS DISTINCT FROM (u.unit, u.quantity, u.weight)",
+        &[&ids, &units, &quantities, &weights],
+      )
+      .await?;
+  }
+
+  Ok(changed)
+}
+
+#[cfg(test)]
+mod tests
+{
+  use super::*;
+
+  fn num(a_str: &str) -> Decimal
+  {
+    Decimal::from_str(a_str).unwrap()
+  }
+
+  #[test]
+  fn units_by_code_and_name()
+  {
+    assert_eq!(
+      parse_unit(Some("113"), None),
+      Some((UnitT::M3, num("1")))
+    );
+    assert_eq!(
+      parse_unit(Some("112.0"), None),
+      Some((UnitT::M3, num("0.001")))
+    );
+    assert_eq!(
+      parse_unit(Some("55"), None),
+      Some((UnitT::M2, num("1")))
+    );
+    assert_eq!(
+      parse_unit(None, Some("куб. м")),
+      Some((UnitT::M3, num("1")))
+    );
+    assert_eq!(
+      parse_unit(None, Some("м³")),
+      Some((UnitT::M3, num("1")))
+    );
+    assert_eq!(
+      parse_unit(None, Some("т")),
+      Some((UnitT::Kg, num("1000")))
+    );
+    // The code wins over the name
+    assert_eq!(
+      parse_unit(Some("796"), Some("кг")),
+      Some((UnitT::Piece, num("1")))
+    );
+    assert_eq!(parse_unit(Some("999"), Some("бочка")), None);
+  }
+
+  #[test]
+  fn volume_preferred()
+  {
+    let q = normalize_quantities(
+      &[
+        (Some(num("10")), Some("796"), None),
+        (Some(num("2500")), None, Some("л")),
+      ],
+      Some(num("120.5")),
+    );
+    assert_eq!(q.unit, Some(UnitT::M3));
+    assert_eq!(q.quantity, Some(num("2.5")));
+    assert_eq!(q.weight_kg, Some(num("120.5")));
+  }
+
+  #[test]
+  fn weight_from_mass_quantity()
+  {
+    let q = normalize_quantities(
+      &[
+        (Some(num("0")), Some("113"), None),
+        (Some(num("1.2")), None, Some("тонн")),
+      ],
+      Some(num("0")),
+    );
+    assert_eq!(q.unit, Some(UnitT::Kg));
+    assert_eq!(q.quantity, Some(num("1200")));
+    assert_eq!(q.weight_kg, Some(num("1200")));
+
+    let q =
+      normalize_quantities(&[(Some(num("3")), None, None)], None);
+    assert_eq!(q, Quantities::default());
+  }
+}
@@ -0,0 +1,386 @@
+use crate::database::parse_date;
+use crate::dialect::{Dialect, grouped_by};
+use crate::identifiers::check_identifiers;
+use crate::mapping::{Mapping, header_index};
+use crate::schemas::*;
+use crate::sources::{SourceRow, open_source};
+use crate::tnved::{Tnved, check_tnved};
+use csv::{StringRecord, Writer};
+use rust_decimal::Decimal;
+use std::collections::HashMap;
+use std::error::Error;
+use std::path::PathBuf;
+use std::str::FromStr;
+use tokio_postgres::Client;
+
+/// Fields a row cannot be loaded without
+pub const REQUIRED_FIELDS: &[&str] =
+  &["declaration_number", "effective_date"];
+
+/// Fields that must hold a date, read with the date formats of the
+/// mapping or `DEFAULT_DATE_FORMATS`
+pub const DATE_FIELDS: &[&str] = &[
+  "effective_date",
+  "fill_date",
+  "movement_period",
+  "gtd_registration_date",
+];
+
+/// Fields that must hold a number when present
+pub const NUMERIC_FIELDS: &[&str] = &[
+  "cost_usd",
+  "net_weight",
+  "gross_weight",
+  "quantity_physical",
+  "quantity_additional",
+  "number_of_packages",
+  "customs_value",
+  "invoice_value",
+  "stat_value",
+  "stat_value_usd",
+  "total_invoice_value",
+  "exchange_rate",
+  "price_per_kg",
+  "price_per_unit",
+  "price_per_additional_unit",
+  "customs_value_ts",
+  "previous_customs_value",
+  "total_customs_payments",
+];
+
+/// A source row that was not loaded, or a suspicious value of a loaded
+/// row, with the reason why
+#[derive(Debug, Clone)]
+pub struct Reject
+{
+  /// 1-based line in the source file, header included
+  pub line: Option<u64>,
+  pub column: Option<String>,
+  pub reason: String,
+  /// The source record as CSV
+  pub raw: Option<String>,
+  /// The row was loaded anyway, the value is only flagged
+  pub loaded: bool,
+}
+
+/// Rows of a source file split into loadable ones and rejects
+#[derive(Debug, Default)]
+pub struct Validated
+{
+  pub rows: Vec<GeneralSchema>,
+  pub rejects: Vec<Reject>,
+  /// Number of source rows rejected; a row may fail several checks
+  pub rejected_rows: u64,
+}
+
+/// Numeric fields stored as whole numbers
+pub const INTEGER_FIELDS: &[&str] = &["number_of_packages"];
+
+/// Number in the formats found in the exports: "1234.5", "1 234,5",
+/// "1.234,5", "1,234.5" or "1'234.5". When both ',' and '.' are present
+/// the last one is the decimal separator. Otherwise repeated ones group
+/// thousands and a single one is a decimal separator, except a ','
+/// when `a_decimal` is '.': "1,234" is then 1234, and "1,5" is
+/// ambiguous and None. With a ',' `a_decimal` this is how
+/// parse_locale_number() in 0008_typed_columns.sql reads numbers.
+pub fn parse_number(a_str: &str, a_decimal: char) -> Option<Decimal>
+{
+  let mut s: String = a_str
+    .trim()
+    .chars()
+    .filter(|x| !x.is_whitespace() && *x != '\'')
+    .collect();
+  if s.is_empty() {
+    return None;
+  }
+
+  let commas = s.matches(',').count();
+  let dots = s.matches('.').count();
+  if commas > 0 && dots > 0 {
+    if s.rfind(',') > s.rfind('.') {
+      s = s.replace('.', "").replace(',', ".");
+    } else {
+      s = s.replace(',', "");
+    }
+  } else if commas > 0 && a_decimal == '.' {
+    if !grouped_by(&s, ',') {
+      return None;
+    }
+    s = s.replace(',', "");
+  } else if commas == 1 {
+    s = s.replace(',', ".");
+  } else if commas > 1 {
+    s = s.replace(',', "");
+  } else if dots > 1 {
+    s = s.replace('.', "");
+  }
+
+  Decimal::from_str(&s)
+    .or_else(|_| Decimal::from_scientific(&s))
+    .ok()
+}
+
+/// Check a converted row. Returns the failing (column, reason) pairs,
+/// empty when the row is fine.
+pub fn validate_row(a_row: &GeneralSchema) -> Vec<(String, String)>
+{
+  let mut out = Vec::new();
+  let values = match serde_json::to_value(a_row) {
+    Ok(serde_json::Value::Object(x)) => x,
+    _ => {
+      out.push((
+        "".to_string(),
+        "Could not inspect row values".to_string(),
+      ));
+      return out;
+    }
+  };
+  let get = |a_name: &str| -> Option<String> {
+    values
+      .get(a_name)
+      .and_then(|x| x.as_str())
+      .map(|x| x.trim().to_string())
+      .filter(|x| !x.is_empty())
+  };
+
+  for f in REQUIRED_FIELDS {
+    if get(f).is_none() {
+      out.push((
+        f.to_string(),
+        "Required value is missing".to_string(),
+      ));
+    }
+  }
+
+  for f in DATE_FIELDS {
+    if let Some(v) = get(f).filter(|x| parse_date(x).is_none()) {
+      out.push((f.to_string(), format!("Unparseable date '{}'", v)));
+    }
+  }
+
+  for f in NUMERIC_FIELDS {
+    if let Some(v) = get(f).filter(|x| parse_number(x, '.').is_none())
+    {
+      out
+        .push((f.to_string(), format!("Unparseable number '{}'", v)));
+    }
+  }
+
+  out
+}
+
+fn raw_record(a_rec: &StringRecord) -> Option<String>
+{
+  let mut wtr = Writer::from_writer(Vec::new());
+  wtr.write_record(a_rec).ok()?;
+  let data = wtr.into_inner().ok()?;
+
+  Some(String::from_utf8_lossy(&data).trim_end().to_string())
+}
+
+/// A converted row with the values flagged in it, or the rejects
+/// explaining why it was not loaded
+pub type ValidatedRow =
+  Result<(GeneralSchema, Vec<Reject>), Vec<Reject>>;
+
+/// Convert and check one source record
+pub fn validate_record(
+  a_mapping: &Mapping,
+  a_index: &HashMap<String, usize>,
+  a_dialect: &Dialect,
+  a_tnved: &Tnved,
+  a_record: SourceRow,
+) -> ValidatedRow
+{
+  let rec = match a_record {
+    Ok(x) => x,
+    Err((line, reason)) => {
+      return Err(vec![Reject {
+        line,
+        column: None,
+        reason,
+        raw: None,
+        loaded: false,
+      }]);
+    }
+  };
+  let line = rec.position().map(|x| x.line());
+
+  let mut schema = match a_mapping.apply(a_index, &rec, a_dialect) {
+    Ok(x) => x,
+    Err((column, reason)) => {
+      return Err(vec![Reject {
+        line,
+        column: Some(column),
+        reason,
+        raw: raw_record(&rec),
+        loaded: false,
+      }]);
+    }
+  };
+  schema.eval = GeneralSchema::validate_and_convert_eval(schema.eval);
+
+  let rejects = |a_failed: Vec<(String, String)>, a_loaded: bool| {
+    let raw = raw_record(&rec);
+    a_failed
+      .into_iter()
+      .map(|(column, reason)| Reject {
+        line,
+        column: Some(column),
+        reason,
+        raw: raw.clone(),
+        loaded: a_loaded,
+      })
+      .collect::<Vec<Reject>>()
+  };
+
+  let failed = validate_row(&schema);
+  if !failed.is_empty() {
+    return Err(rejects(failed, false));
+  }
+
+  let mut flagged = check_identifiers(&schema);
+  flagged.extend(check_tnved(&mut schema, a_tnved));
+  let flagged = rejects(flagged, true);
+
+  Ok((schema, flagged))
+}
+
+/// Read a CSV file or spreadsheet row by row through a mapping.
+/// Malformed or invalid rows are collected as rejects instead of
+/// failing the whole file.
+pub fn read_validated(
+  a_path: &PathBuf,
+  a_mapping: &Mapping,
+  a_tnved: &Tnved,
+) -> Result<Validated, Box<dyn Error>>
+{
+  let source = open_source(a_path, &a_mapping.input)?;
+  let index = header_index(&source.headers);
+  let mut out = Validated::default();
+
+  for record in source.rows {
+    match validate_record(
+      a_mapping,
+      &index,
+      &source.dialect,
+      a_tnved,
+      record,
+    ) {
+      Ok((x, flagged)) => {
+        out.rows.push(x);
+        out.rejects.extend(flagged);
+      }
+      Err(rejects) => {
+        out.rejects.extend(rejects);
+        out.rejected_rows += 1;
+      }
+    }
+  }
+
+  Ok(out)
+}
+
+/// Save the rejects of an import run
+pub async fn store_rejects(
+  a_cli: &Client,
+  a_run_id: i32,
+  a_rejects: &[Reject],
+) -> Result<u64, Box<dyn Error>>
+{
+  if a_rejects.is_empty() {
+    return Ok(0);
+  }
+
+  let lines: Vec<Option<i64>> =
+    a_rejects.iter().map(|x| x.line.map(|l| l as i64)).collect();
+  let columns: Vec<Option<String>> =
+    a_rejects.iter().map(|x| x.column.clone()).collect();
+  let reasons: Vec<String> =
+    a_rejects.iter().map(|x| x.reason.clone()).collect();
+  let raws: Vec<Option<String>> =
+    a_rejects.iter().map(|x| x.raw.clone()).collect();
+  let loaded: Vec<bool> =
+    a_rejects.iter().map(|x| x.loaded).collect();
+
+  let n = a_cli
+    .execute(
+      "INSERT INTO import_rejects \
+        (run_id, line, column_name, reason, raw, loaded) \
+      SELECT $1, * FROM UNNEST($2::BIGINT[], $3::TEXT[], $4::TEXT[], \
+      $5::TEXT[], $6::BOOLEAN[])",
+      &[&a_run_id, &lines, &columns, &reasons, &raws, &loaded],
+    )
+    .await?;
+
+  Ok(n)
+}
+
+/// Rejects of an import run in source order
+pub async fn list_rejects(
+  a_cli: &Client,
+  a_run_id: i32,
+) -> Result<Vec<Reject>, Box<dyn Error>>
+{
+  let res = a_cli
+    .query(
+      "SELECT line, column_name, reason, raw, loaded FROM import_rejects \
+      WHERE run_id = $1 ORDER BY line, id",
+      &[&a_run_id],
+    )
+    .await?;
+
+  let out = res
+    .into_iter()
+    .map(|x| Reject {
+      line: x.get::<_, Option<i64>>(0).map(|l| l as u64),
+      column: x.get(1),
+      reason: x.get(2),
+      raw: x.get(3),
+      loaded: x.get(4),
+    })
+    .collect();
+
+  Ok(out)
+}
+
+#[cfg(test)]
+mod tests
+{
+  use super::*;
+
+  fn num(a_str: &str) -> Option<Decimal>
+  {
+    Some(Decimal::from_str(a_str).unwrap())
+  }
+
+  #[test]
+  fn parse_number_formats()
+  {
+    assert_eq!(parse_number("1234.5", '.'), num("1234.5"));
+    assert_eq!(parse_number(" 1 234,5 ", ','), num("1234.5"));
+    assert_eq!(parse_number("1.234,5", '.'), num("1234.5"));
+    assert_eq!(parse_number("1,234.5", ','), num("1234.5"));
+    assert_eq!(parse_number("1'234.5", '.'), num("1234.5"));
+    assert_eq!(parse_number("1.234.567", ','), num("1234567"));
+    assert_eq!(parse_number("1.5E-3", '.'), num("0.0015"));
+  }
+
+  #[test]
+  fn parse_number_lone_comma()
+  {
+    assert_eq!(parse_number("1,234", ','), num("1.234"));
+    assert_eq!(parse_number("1,234", '.'), num("1234"));
+    assert_eq!(parse_number("-1,234,567", '.'), num("-1234567"));
+    // Neither a decimal point nor thousands
+    assert_eq!(parse_number("1,5", '.'), None);
+  }
+
+  #[test]
+  fn parse_number_not_a_number()
+  {
+    assert_eq!(parse_number("", '.'), None);
+    assert_eq!(parse_number("N\\A", '.'), None);
+    assert_eq!(parse_number("12 kg", ','), None);
+  }
+}